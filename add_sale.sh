@@ -27,7 +27,7 @@ near call $CONTRACT_ID --accountId=$OWNER_ID sale_create '{"sale": {
       "balance": "'$AMOUNT'",
       "referral_bpt": 100
     }],
-    "in_token_account_id": "'$TOKEN_ACCOUNT_ID_IN'",
+    "in_tokens": [{"token_account_id": "'$TOKEN_ACCOUNT_ID_IN'", "weight": 1}],
     "start_time": "'$START_TIME'000000000",
     "duration": "604800000000000"
-}}' --amount=11
+}}' --amount=11 --gas=300000000000000
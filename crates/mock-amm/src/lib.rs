@@ -0,0 +1,237 @@
+//! A minimal subset of the Ref Finance exchange interface that is used to test liquidity seeding.
+
+use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LookupMap, Vector};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{
+    assert_one_yocto, env, near_bindgen, AccountId, BorshStorageKey, Gas, NearToken,
+    PanicOnDefault, Promise, PromiseOrValue,
+};
+
+const FT_TRANSFER_GAS: Gas = Gas::from_tgas(10);
+
+#[derive(BorshStorageKey, BorshSerialize)]
+#[borsh(crate = "near_sdk::borsh")]
+pub(crate) enum StorageKey {
+    Accounts,
+    Deposits,
+    Pools,
+    Shares,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct Pool {
+    pub token_account_ids: Vec<AccountId>,
+    pub amounts: Vec<u128>,
+    pub fee: u32,
+    pub shares_total_supply: u128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolInfo {
+    pub token_account_ids: Vec<AccountId>,
+    pub amounts: Vec<U128>,
+    pub total_fee: u32,
+    pub shares_total_supply: U128,
+}
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct Contract {
+    pub accounts: LookupMap<AccountId, bool>,
+    pub deposits: LookupMap<(AccountId, AccountId), u128>,
+    pub pools: Vector<Pool>,
+    pub shares: LookupMap<(u64, AccountId), u128>,
+}
+
+#[near_bindgen]
+impl Contract {
+    #[init]
+    pub fn new() -> Self {
+        Self {
+            accounts: LookupMap::new(StorageKey::Accounts),
+            deposits: LookupMap::new(StorageKey::Deposits),
+            pools: Vector::new(StorageKey::Pools),
+            shares: LookupMap::new(StorageKey::Shares),
+        }
+    }
+
+    #[payable]
+    #[allow(unused_variables)]
+    pub fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) {
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        self.accounts.insert(&account_id, &true);
+    }
+
+    #[payable]
+    #[allow(unused_variables)]
+    pub fn register_tokens(&mut self, token_ids: Vec<AccountId>) {
+        assert_one_yocto();
+        self.assert_registered(&env::predecessor_account_id());
+    }
+
+    #[payable]
+    pub fn add_simple_pool(&mut self, tokens: Vec<AccountId>, fee: u32) -> u64 {
+        assert_eq!(tokens.len(), 2, "ERR_INVALID_TOKENS");
+        assert_ne!(tokens[0], tokens[1], "ERR_SAME_TOKENS");
+        self.pools.push(&Pool {
+            token_account_ids: tokens,
+            amounts: vec![0, 0],
+            fee,
+            shares_total_supply: 0,
+        });
+        self.pools.len() - 1
+    }
+
+    #[allow(unused_variables)]
+    pub fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        self.assert_registered(&sender_id);
+        self.internal_deposit(&sender_id, &env::predecessor_account_id(), amount.0);
+        PromiseOrValue::Value(U128(0))
+    }
+
+    #[payable]
+    #[allow(unused_variables)]
+    pub fn add_liquidity(
+        &mut self,
+        pool_id: u64,
+        amounts: Vec<U128>,
+        min_amounts: Option<Vec<U128>>,
+    ) -> U128 {
+        assert!(env::attached_deposit().as_yoctonear() > 0, "ERR_NO_DEPOSIT");
+        let account_id = env::predecessor_account_id();
+        let mut pool = self.pools.get(pool_id).expect("ERR_POOL_NOT_FOUND");
+        assert_eq!(amounts.len(), 2, "ERR_INVALID_AMOUNTS");
+        for (token_account_id, amount) in pool.token_account_ids.iter().zip(amounts.iter()) {
+            self.internal_withdraw(&account_id, token_account_id, amount.0);
+        }
+        let shares = if pool.shares_total_supply == 0 {
+            amounts[0].0
+        } else {
+            amounts[0].0 * pool.shares_total_supply / pool.amounts[0]
+        };
+        assert!(shares > 0, "ERR_ZERO_SHARES");
+        pool.amounts[0] += amounts[0].0;
+        pool.amounts[1] += amounts[1].0;
+        pool.shares_total_supply += shares;
+        self.pools.replace(pool_id, &pool);
+        let key = (pool_id, account_id);
+        self.shares
+            .insert(&key, &(self.shares.get(&key).unwrap_or(0) + shares));
+        U128(shares)
+    }
+
+    #[payable]
+    #[allow(unused_variables)]
+    pub fn withdraw(
+        &mut self,
+        token_id: AccountId,
+        amount: U128,
+        unregister: Option<bool>,
+    ) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        self.internal_withdraw(&account_id, &token_id, amount.0);
+        Promise::new(token_id).function_call(
+            "ft_transfer".to_string(),
+            json!({ "receiver_id": account_id, "amount": amount })
+                .to_string()
+                .into_bytes(),
+            NearToken::from_yoctonear(1),
+            FT_TRANSFER_GAS,
+        )
+    }
+
+    #[payable]
+    pub fn mft_register(&mut self, token_id: String, account_id: AccountId) {
+        let key = (parse_pool_id(&token_id), account_id);
+        assert!(!self.shares.contains_key(&key), "ERR_LP_ALREADY_REGISTERED");
+        self.shares.insert(&key, &0);
+    }
+
+    #[payable]
+    #[allow(unused_variables)]
+    pub fn mft_transfer(
+        &mut self,
+        token_id: String,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+    ) {
+        assert_one_yocto();
+        let pool_id = parse_pool_id(&token_id);
+        let sender_key = (pool_id, env::predecessor_account_id());
+        let balance = self.shares.get(&sender_key).unwrap_or(0);
+        assert!(balance >= amount.0, "ERR_NOT_ENOUGH_SHARES");
+        self.shares.insert(&sender_key, &(balance - amount.0));
+        let receiver_key = (pool_id, receiver_id);
+        let receiver_balance = self
+            .shares
+            .get(&receiver_key)
+            .expect("ERR_LP_NOT_REGISTERED");
+        self.shares
+            .insert(&receiver_key, &(receiver_balance + amount.0));
+    }
+
+    pub fn mft_balance_of(&self, token_id: String, account_id: AccountId) -> U128 {
+        U128(
+            self.shares
+                .get(&(parse_pool_id(&token_id), account_id))
+                .unwrap_or(0),
+        )
+    }
+
+    pub fn get_deposit(&self, account_id: AccountId, token_id: AccountId) -> U128 {
+        U128(self.deposits.get(&(account_id, token_id)).unwrap_or(0))
+    }
+
+    pub fn get_pool(&self, pool_id: u64) -> PoolInfo {
+        let pool = self.pools.get(pool_id).expect("ERR_POOL_NOT_FOUND");
+        PoolInfo {
+            token_account_ids: pool.token_account_ids,
+            amounts: pool.amounts.into_iter().map(U128).collect(),
+            total_fee: pool.fee,
+            shares_total_supply: U128(pool.shares_total_supply),
+        }
+    }
+}
+
+impl Contract {
+    fn assert_registered(&self, account_id: &AccountId) {
+        assert!(self.accounts.contains_key(account_id), "ERR_NOT_REGISTERED");
+    }
+
+    fn internal_deposit(&mut self, account_id: &AccountId, token_id: &AccountId, amount: u128) {
+        let key = (account_id.clone(), token_id.clone());
+        self.deposits
+            .insert(&key, &(self.deposits.get(&key).unwrap_or(0) + amount));
+    }
+
+    fn internal_withdraw(&mut self, account_id: &AccountId, token_id: &AccountId, amount: u128) {
+        let key = (account_id.clone(), token_id.clone());
+        let balance = self.deposits.get(&key).unwrap_or(0);
+        assert!(balance >= amount, "ERR_NOT_ENOUGH_DEPOSIT");
+        self.deposits.insert(&key, &(balance - amount));
+    }
+}
+
+fn parse_pool_id(token_id: &str) -> u64 {
+    token_id
+        .strip_prefix(':')
+        .and_then(|pool_id| pool_id.parse().ok())
+        .expect("ERR_INVALID_TOKEN_ID")
+}
@@ -1,19 +1,21 @@
 use crate::{
-    assert_at_least_one_yocto, errors, refund_extra_storage_deposit, Contract, ContractExt,
-    FtOnTransferArgs, Sale, SaleOutput, StorageKey, Subscription, SubscriptionOutput,
-    VSubscription,
+    assert_at_least_one_yocto, errors, refund_extra_storage_deposit, AccountDepositData, Contract,
+    ContractExt, Event, FtOnTransferArgs, InternalTransferData, ReferralPayout, Sale, SaleOutput,
+    StorageKey, Subscription, SubscriptionOutput, SubscriptionOutputInToken, VSubscription,
 };
 use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
 use near_sdk::{
+    assert_one_yocto,
     borsh::{BorshDeserialize, BorshSerialize},
     collections::{UnorderedMap, UnorderedSet},
     env,
-    json_types::U128,
-    near_bindgen, serde_json, AccountId, Promise, PromiseOrValue,
+    json_types::{U128, U64},
+    near_bindgen,
+    serde::{Deserialize, Serialize},
+    serde_json, AccountId, Promise, PromiseOrValue,
 };
-use primitive_types::U256;
 
-const REFERRAL_FEE_DENOMINATOR: u128 = 10000;
+pub(crate) const REFERRAL_FEE_DENOMINATOR: u128 = 10000;
 
 #[derive(BorshSerialize, BorshDeserialize)]
 #[borsh(crate = "near_sdk::borsh")]
@@ -29,6 +31,68 @@ pub enum VAccount {
     Current(Account),
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct AccountPortfolioOutput {
+    pub balances: Vec<(AccountId, U128)>,
+    /// Subscriptions with remaining in tokens or unclaimed out tokens.
+    pub subscriptions: Vec<PortfolioSubscriptionOutput>,
+    /// Sales whose proceeds go to the account.
+    pub proceeds_sales: Vec<PortfolioSaleOutput>,
+    /// Referral fees from the referred subscriptions that haven't been claimed yet.
+    pub pending_referral_earnings: Vec<(AccountId, U128)>,
+    /// The total sizes of the paginated collections of the account.
+    pub counts: AccountCountsOutput,
+    pub current_time: U64,
+    pub current_block_height: U64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct PortfolioSubscriptionOutput {
+    pub sale_id: u64,
+    pub subscription: SubscriptionOutput,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct PortfolioSaleOutput {
+    pub sale_id: u64,
+    /// In tokens paid for out tokens that the proceeds receiver would be credited with now, after
+    /// the treasury fee, the liquidity reserve and the locked proceeds.
+    pub unclaimed_proceeds: Vec<(AccountId, U128)>,
+}
+
+/// A page of one collection of the account portfolio.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct PortfolioPageInput {
+    pub from_index: Option<u64>,
+    pub limit: Option<u64>,
+}
+
+impl PortfolioPageInput {
+    fn range(&self, len: u64) -> std::ops::Range<u64> {
+        let from_index = self.from_index.unwrap_or(0);
+        from_index..std::cmp::min(from_index.saturating_add(self.limit.unwrap_or(len)), len)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct AccountCountsOutput {
+    pub num_balances: u64,
+    pub num_subscriptions: u64,
+    pub num_proceeds_sales: u64,
+    /// The subscriptions referred by the account.
+    pub num_referrals: u64,
+}
+
 impl From<Account> for VAccount {
     fn from(account: Account) -> Self {
         Self::Current(account)
@@ -91,12 +155,19 @@ impl Account {
         sale_id: u64,
         sale: &Sale,
         subscription: Subscription,
-    ) {
-        if subscription.shares == 0 && (sale.permissions_contract_id.is_none() || sale.has_ended())
-        {
+    ) -> bool {
+        // Subscriptions are kept while the sale is active if they are needed to check permissions
+        // or to enforce the per-account deposit limit.
+        // Subscriptions of sales with locked proceeds are kept for a clawback.
+        let keep_subscription = (!sale.has_ended()
+            && (sale.permissions_contract_id.is_some() || sale.max_account_in_amount.is_some()))
+            || sale.is_proceeds_lock_active();
+        if subscription.shares == 0 && !keep_subscription {
             self.subs.remove(&sale_id);
+            false
         } else {
             self.subs.insert(&sale_id, &subscription.into());
+            true
         }
     }
 
@@ -111,8 +182,14 @@ impl Account {
             let remaining_in_balance = sale.shares_to_in_balance(subscription.shares);
             Some(SubscriptionOutput {
                 remaining_in_balance: remaining_in_balance.into(),
-                spent_in_balance: (subscription.spent_in_balance_without_shares
-                    + (subscription.last_in_balance - remaining_in_balance))
+                spent_in_balance: subscription
+                    .spent_in_balances
+                    .iter()
+                    .enumerate()
+                    .map(|(index, &spent_in_balance)| {
+                        sale.to_normalized_in_amount(index, spent_in_balance)
+                    })
+                    .sum::<u128>()
                     .into(),
                 unclaimed_out_balances: out_token_remaining.into_iter().map(|b| b.into()).collect(),
                 claimed_out_balance: subscription
@@ -122,6 +199,22 @@ impl Account {
                     .collect(),
                 shares: subscription.shares.into(),
                 referral_id: subscription.referral_id,
+                referral_weights: subscription
+                    .referral_weights
+                    .into_iter()
+                    .map(|(referral_id, weight)| (referral_id, weight.into()))
+                    .collect(),
+                in_tokens: sale
+                    .token_shares_to_in_balances(&subscription.in_token_shares)
+                    .into_iter()
+                    .zip(subscription.spent_in_balances)
+                    .map(
+                        |(remaining_in_balance, spent_in_balance)| SubscriptionOutputInToken {
+                            remaining_in_balance: remaining_in_balance.into(),
+                            spent_in_balance: spent_in_balance.into(),
+                        },
+                    )
+                    .collect(),
             })
         } else {
             None
@@ -148,6 +241,45 @@ impl Contract {
         }
     }
 
+    /// Saves the subscription and removes it from the referrals of its referrers once it's removed.
+    pub fn internal_save_subscription(
+        &mut self,
+        account: &mut Account,
+        account_id: &AccountId,
+        sale_id: u64,
+        sale: &mut Sale,
+        subscription: Subscription,
+    ) {
+        let referral_ids = subscription.all_referral_ids();
+        let existed = sale.proceeds_lock.is_some() && account.subs.get(&sale_id).is_some();
+        let saved = account.internal_save_subscription(sale_id, sale, subscription);
+        if let Some(lock) = sale.proceeds_lock.as_mut() {
+            if saved && !existed {
+                lock.num_subscriptions += 1;
+            } else if !saved && existed {
+                lock.num_subscriptions -= 1;
+            }
+        }
+        if !saved {
+            for referral_id in referral_ids {
+                self.internal_remove_referral(&referral_id, sale_id, account_id);
+            }
+        }
+    }
+
+    pub fn internal_remove_subscription(
+        &mut self,
+        account: &mut Account,
+        account_id: &AccountId,
+        sale_id: u64,
+        subscription: Subscription,
+    ) {
+        account.subs.remove(&sale_id);
+        for referral_id in subscription.all_referral_ids() {
+            self.internal_remove_referral(&referral_id, sale_id, account_id);
+        }
+    }
+
     pub fn internal_update_subscription(
         &mut self,
         account: &mut Account,
@@ -157,42 +289,60 @@ impl Contract {
         passed_permission_check: bool,
     ) -> Subscription {
         let create_new = passed_permission_check || sale.permissions_contract_id.is_none();
+        let spent_in_balances = account
+            .subs
+            .get(&sale_id)
+            .map(|s| Subscription::from(s).spent_in_balances)
+            .unwrap_or_default();
         let (mut subscription, out_token_amounts) =
             account.internal_get_subscription(sale_id, sale, referral_id, create_new);
+        let second_level_referral_ids: Vec<Option<AccountId>> = subscription
+            .referral_ids()
+            .iter()
+            .map(|referral_id| self.internal_second_level_referrer(referral_id))
+            .collect();
         for (index, (mut amount, out_token)) in out_token_amounts
             .into_iter()
             .zip(sale.out_tokens.iter())
             .enumerate()
         {
             if amount > 0 {
-                if let Some(referral_bpt) = out_token.referral_bpt {
-                    let mut ref_amount = (U256::from(amount) * U256::from(referral_bpt)
-                        / U256::from(REFERRAL_FEE_DENOMINATOR))
-                    .as_u128();
-                    let referral_id = subscription
-                        .referral_id
-                        .as_ref()
-                        .map(|referral_id| {
-                            ref_amount /= 2;
-                            referral_id
-                        })
-                        .unwrap_or(&sale.owner_id);
-                    if ref_amount > 0 {
-                        amount -= ref_amount;
-                        if let Some(referral) = self.accounts.get(referral_id) {
-                            let mut referral: Account = referral.into();
-                            if referral.balances.get(&out_token.token_account_id).is_some() {
-                                referral.internal_token_deposit(
-                                    &out_token.token_account_id,
-                                    ref_amount,
-                                );
-                                ref_amount = 0;
-                                self.accounts.insert(referral_id, &referral.into());
-                            }
-                        }
-                        if ref_amount > 0 {
-                            self.treasury
-                                .internal_deposit(&out_token.token_account_id, ref_amount);
+                subscription.gross_claimed_out_balance[index] += amount;
+                if out_token.referral_payout == ReferralPayout::OutToken {
+                    if second_level_referral_ids.is_empty() {
+                        let fees = out_token.referral_fees(amount, false, false);
+                        amount -= fees.total();
+                        self.internal_pay_referral(
+                            &sale.proceeds_receiver_id,
+                            sale_id,
+                            &out_token.token_account_id,
+                            fees.owner,
+                        );
+                    }
+                    for ((referral_id, referral_amount), second_level_referral_id) in subscription
+                        .split_by_referrers(amount)
+                        .into_iter()
+                        .zip(second_level_referral_ids.iter())
+                    {
+                        let fees = out_token.referral_fees(
+                            referral_amount,
+                            true,
+                            second_level_referral_id.is_some(),
+                        );
+                        amount -= fees.total();
+                        self.internal_pay_referral(
+                            &referral_id,
+                            sale_id,
+                            &out_token.token_account_id,
+                            fees.referrer,
+                        );
+                        if let Some(second_level_referral_id) = second_level_referral_id {
+                            self.internal_pay_referral(
+                                second_level_referral_id,
+                                sale_id,
+                                &out_token.token_account_id,
+                                fees.second_level_referrer,
+                            );
                         }
                     }
                 }
@@ -200,11 +350,72 @@ impl Contract {
                 subscription.claimed_out_balance[index] += amount;
             }
         }
+        // In token referral fees are paid from the reserved proceeds. The part that isn't owed to
+        // referrers is released to the proceeds.
+        for (index, spent_in_balance) in spent_in_balances.into_iter().enumerate() {
+            let spent_amount = subscription.spent_in_balances[index] - spent_in_balance;
+            let reserved = sale.in_token_referral_fees(spent_amount, true, true);
+            if reserved == 0 {
+                continue;
+            }
+            let in_token = &mut sale.in_tokens[index];
+            let reserved = std::cmp::min(reserved, in_token.referral_reserve);
+            in_token.referral_reserve -= reserved;
+            let mut remaining = reserved;
+            for out_token in sale
+                .out_tokens
+                .iter()
+                .filter(|out_token| out_token.referral_payout == ReferralPayout::InToken)
+            {
+                for ((referral_id, referral_amount), second_level_referral_id) in subscription
+                    .split_by_referrers(spent_amount)
+                    .into_iter()
+                    .zip(second_level_referral_ids.iter())
+                {
+                    let fees = out_token.referral_fees(
+                        referral_amount,
+                        true,
+                        second_level_referral_id.is_some(),
+                    );
+                    let amount = std::cmp::min(fees.referrer, remaining);
+                    remaining -= amount;
+                    self.internal_pay_referral(
+                        &referral_id,
+                        sale_id,
+                        &in_token.token_account_id,
+                        amount,
+                    );
+                    if let Some(second_level_referral_id) = second_level_referral_id {
+                        let amount = std::cmp::min(fees.second_level_referrer, remaining);
+                        remaining -= amount;
+                        self.internal_pay_referral(
+                            second_level_referral_id,
+                            sale_id,
+                            &in_token.token_account_id,
+                            amount,
+                        );
+                    }
+                }
+            }
+            in_token.paid_unclaimed += remaining;
+            sale.in_token_paid_unclaimed += in_token.to_normalized(remaining);
+        }
+        for (amount, in_token) in subscription
+            .claim_penalties(sale)
+            .into_iter()
+            .zip(sale.in_tokens.iter())
+        {
+            if amount > 0 {
+                account.internal_token_deposit(&in_token.token_account_id, amount);
+            }
+        }
         if subscription.shares > 0 {
-            let remaining_in_amount = sale.shares_to_in_balance(subscription.shares);
-            if remaining_in_amount == 0 {
-                sale.total_shares -= subscription.shares;
-                subscription.shares = 0;
+            let in_balances = sale.token_shares_to_in_balances(&subscription.in_token_shares);
+            if in_balances.iter().all(|&in_balance| in_balance == 0) {
+                // The dust stays with the remaining shares of the in tokens.
+                let token_shares = subscription.in_token_shares.clone();
+                sale.remove_token_shares(&token_shares);
+                subscription.remove_token_shares(&token_shares);
             }
         }
         subscription
@@ -225,6 +436,7 @@ impl Contract {
         token_account_ids: Vec<AccountId>,
     ) {
         assert_at_least_one_yocto();
+        assert!(!self.pause_flags.deposits, "{}", errors::DEPOSITS_PAUSED);
         let initial_storage_usage = env::storage_usage();
         let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
         let mut account = self
@@ -250,6 +462,11 @@ impl Contract {
     }
 
     pub fn withdraw_token(&mut self, token_account_id: AccountId, amount: Option<U128>) -> Promise {
+        assert!(
+            !self.pause_flags.withdrawals,
+            "{}",
+            errors::WITHDRAWALS_PAUSED
+        );
         let account_id = env::predecessor_account_id();
         let mut account = self.internal_unwrap_account(&account_id);
         let amount = amount.map(|a| a.0).unwrap_or_else(|| {
@@ -262,6 +479,84 @@ impl Contract {
         self.internal_ft_transfer(&account_id, &token_account_id, amount)
     }
 
+    /// Withdraws the given amounts of tokens, or whole balances if the amount is omitted, to the
+    /// receiver (defaults to the caller). If `msg` is given, the tokens are sent with
+    /// `ft_transfer_call` instead. Zero balances are skipped.
+    #[payable]
+    pub fn withdraw_tokens(
+        &mut self,
+        tokens: Vec<(AccountId, Option<U128>)>,
+        receiver_id: Option<AccountId>,
+        msg: Option<String>,
+    ) -> Promise {
+        assert_one_yocto();
+        assert!(
+            !self.pause_flags.withdrawals,
+            "{}",
+            errors::WITHDRAWALS_PAUSED
+        );
+        let account_id = env::predecessor_account_id();
+        let receiver_id = receiver_id.unwrap_or_else(|| account_id.clone());
+        let mut account = self.internal_unwrap_account(&account_id);
+        let mut promise: Option<Promise> = None;
+        for (token_account_id, amount) in tokens {
+            let amount = amount.map(|a| a.0).unwrap_or_else(|| {
+                account
+                    .balances
+                    .get(&token_account_id)
+                    .expect(errors::TOKEN_NOT_REGISTERED)
+            });
+            if amount == 0 {
+                continue;
+            }
+            account.internal_token_withdraw(&token_account_id, amount);
+            let transfer = self.internal_ft_transfer_to(
+                &account_id,
+                &receiver_id,
+                &token_account_id,
+                amount,
+                msg.clone(),
+            );
+            promise = Some(match promise {
+                Some(promise) => promise.and(transfer),
+                None => transfer,
+            });
+        }
+        promise.expect(errors::NOTHING_TO_WITHDRAW)
+    }
+
+    /// Moves tokens to another registered account.
+    #[payable]
+    pub fn internal_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_account_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+    ) {
+        assert_one_yocto();
+        assert!(
+            !self.pause_flags.withdrawals,
+            "{}",
+            errors::WITHDRAWALS_PAUSED
+        );
+        let sender_id = env::predecessor_account_id();
+        assert_ne!(sender_id, receiver_id, "{}", errors::SELF_TRANSFER);
+        assert!(amount.0 > 0, "{}", errors::ZERO_AMOUNT);
+        let mut sender = self.internal_unwrap_account(&sender_id);
+        sender.internal_token_withdraw(&token_account_id, amount.0);
+        let mut receiver = self.internal_unwrap_account(&receiver_id);
+        receiver.internal_token_deposit(&token_account_id, amount.0);
+        Event::InternalTransfer(vec![InternalTransferData {
+            sender_id: &sender_id,
+            receiver_id: &receiver_id,
+            token_account_id: &token_account_id,
+            amount,
+            memo: memo.as_deref(),
+        }])
+        .emit();
+    }
+
     pub fn balance_of(&self, account_id: AccountId, token_account_id: AccountId) -> Option<U128> {
         self.accounts.get(&account_id).and_then(|account| {
             let account: Account = account.into();
@@ -321,6 +616,102 @@ impl Contract {
         }
     }
 
+    /// Every collection of the portfolio is paginated by its own page. Subscriptions without
+    /// remaining or unclaimed tokens are skipped after the pagination.
+    pub fn get_account_portfolio(
+        &self,
+        account_id: AccountId,
+        balances_page: Option<PortfolioPageInput>,
+        subscriptions_page: Option<PortfolioPageInput>,
+        proceeds_sales_page: Option<PortfolioPageInput>,
+        referral_earnings_page: Option<PortfolioPageInput>,
+    ) -> Option<AccountPortfolioOutput> {
+        let account: Account = self.accounts.get(&account_id)?.into();
+        let balance_keys = account.balances.keys_as_vector();
+        let balance_values = account.balances.values_as_vector();
+        let balances = balances_page
+            .unwrap_or_default()
+            .range(balance_keys.len())
+            .map(|index| {
+                (
+                    balance_keys.get(index).unwrap(),
+                    balance_values.get(index).unwrap().into(),
+                )
+            })
+            .collect();
+        let sub_keys = account.subs.keys_as_vector();
+        let subscriptions = subscriptions_page
+            .unwrap_or_default()
+            .range(sub_keys.len())
+            .filter_map(|index| {
+                let sale_id = sub_keys.get(index).unwrap();
+                let sale = self.internal_unwrap_sale(sale_id);
+                account
+                    .internal_subscription_output(sale_id, &sale)
+                    .map(|subscription| PortfolioSubscriptionOutput {
+                        sale_id,
+                        subscription,
+                    })
+            })
+            .collect();
+        let proceeds_sale_ids = self.sale_index.sales_by_proceeds_receiver.get(&account_id);
+        let num_proceeds_sales = proceeds_sale_ids
+            .as_ref()
+            .map(|sale_ids| sale_ids.len())
+            .unwrap_or(0);
+        let proceeds_sales = proceeds_sale_ids
+            .map(|sale_ids| {
+                let keys = sale_ids.as_vector();
+                proceeds_sales_page
+                    .unwrap_or_default()
+                    .range(keys.len())
+                    .map(|index| {
+                        let sale_id = keys.get(index).unwrap();
+                        let sale = self.internal_unwrap_sale(sale_id);
+                        PortfolioSaleOutput {
+                            sale_id,
+                            unclaimed_proceeds: (0..sale.in_tokens.len())
+                                .map(|index| {
+                                    (
+                                        sale.in_tokens[index].token_account_id.clone(),
+                                        sale.claimable_proceeds(index).into(),
+                                    )
+                                })
+                                .collect(),
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let referral_earnings_page = referral_earnings_page.unwrap_or_default();
+        Some(AccountPortfolioOutput {
+            balances,
+            subscriptions,
+            proceeds_sales,
+            pending_referral_earnings: self
+                .internal_pending_referral_earnings(
+                    &account_id,
+                    referral_earnings_page.from_index,
+                    referral_earnings_page.limit,
+                )
+                .into_iter()
+                .map(|(token_account_id, amount)| (token_account_id, amount.into()))
+                .collect(),
+            counts: AccountCountsOutput {
+                num_balances: account.balances.len(),
+                num_subscriptions: account.subs.len(),
+                num_proceeds_sales,
+                num_referrals: self
+                    .referrals
+                    .get(&account_id)
+                    .map(|referrals| referrals.len())
+                    .unwrap_or(0),
+            },
+            current_time: env::block_timestamp().into(),
+            current_block_height: env::block_height().into(),
+        })
+    }
+
     pub fn get_account_sales(
         &self,
         account_id: AccountId,
@@ -352,6 +743,7 @@ impl FungibleTokenReceiver for Contract {
         amount: U128,
         msg: String,
     ) -> PromiseOrValue<U128> {
+        assert!(!self.pause_flags.deposits, "{}", errors::DEPOSITS_PAUSED);
         let args: FtOnTransferArgs =
             serde_json::from_str(&msg).expect(errors::FAILED_TO_PARSE_FT_ON_TRANSFER_MSG);
         let token_account_id = env::predecessor_account_id();
@@ -360,6 +752,17 @@ impl FungibleTokenReceiver for Contract {
                 let mut account = self.internal_unwrap_account(&sender_id);
                 account.internal_token_deposit(&token_account_id, amount.0);
             }
+            FtOnTransferArgs::AccountDepositFor { account_id } => {
+                let mut account = self.internal_unwrap_account(&account_id);
+                account.internal_token_deposit(&token_account_id, amount.0);
+                Event::AccountDeposit(vec![AccountDepositData {
+                    sender_id: &sender_id,
+                    account_id: &account_id,
+                    token_account_id: &token_account_id,
+                    amount,
+                }])
+                .emit();
+            }
         }
         PromiseOrValue::Value(0.into())
     }
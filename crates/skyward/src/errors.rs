@@ -21,9 +21,59 @@ pub(crate) const TOKEN_WITHDRAW_FAILED: &str = "ERR_TOKEN_WITHDRAW_FAILED";
 pub(crate) const SAME_TOKENS: &str = "ERR_SAME_TOKENS";
 pub(crate) const NON_UNIQUE_OUT_TOKENS: &str = "ERR_NON_UNIQUE_OUT_TOKENS";
 pub(crate) const MAX_NUM_OUT_TOKENS: &str = "ERR_MAX_NUM_OUT_TOKENS";
+pub(crate) const NO_IN_TOKENS: &str = "ERR_NO_IN_TOKENS";
+pub(crate) const NON_UNIQUE_IN_TOKENS: &str = "ERR_NON_UNIQUE_IN_TOKENS";
+pub(crate) const MAX_NUM_IN_TOKENS: &str = "ERR_MAX_NUM_IN_TOKENS";
+pub(crate) const ZERO_IN_TOKEN_WEIGHT: &str = "ERR_ZERO_IN_TOKEN_WEIGHT";
+pub(crate) const UNKNOWN_IN_TOKEN: &str = "ERR_UNKNOWN_IN_TOKEN";
 pub(crate) const SELF_REFERRAL: &str = "ERR_SELF_REFERRAL";
 pub(crate) const TOO_LONG_TITLE: &str = "ERR_TOO_LONG_TITLE";
 pub(crate) const TOO_LONG_URL: &str = "ERR_TOO_LONG_URL";
 pub(crate) const NO_PERMISSION: &str = "ERR_NO_PERMISSION";
 pub(crate) const NOT_APPROVED: &str = "ERR_NOT_APPROVED";
 pub(crate) const MAX_REFERRAL_BPT: &str = "ERR_MAX_REFERRAL_BPT";
+pub(crate) const INVALID_DEPOSIT_LIMITS: &str = "ERR_INVALID_DEPOSIT_LIMITS";
+pub(crate) const DEPOSIT_BELOW_MIN: &str = "ERR_DEPOSIT_BELOW_MIN";
+pub(crate) const MAX_ACCOUNT_IN_AMOUNT: &str = "ERR_MAX_ACCOUNT_IN_AMOUNT";
+pub(crate) const INVALID_WITHDRAWAL_LOCK_DURATION: &str = "ERR_INVALID_WITHDRAWAL_LOCK_DURATION";
+pub(crate) const MAX_EARLY_EXIT_PENALTY_BPT: &str = "ERR_MAX_EARLY_EXIT_PENALTY_BPT";
+pub(crate) const WITHDRAWALS_LOCKED: &str = "ERR_WITHDRAWALS_LOCKED";
+pub(crate) const NOT_DAO: &str = "ERR_NOT_DAO";
+pub(crate) const DEPOSITS_PAUSED: &str = "ERR_DEPOSITS_PAUSED";
+pub(crate) const WITHDRAWALS_PAUSED: &str = "ERR_WITHDRAWALS_PAUSED";
+pub(crate) const CLAIMS_PAUSED: &str = "ERR_CLAIMS_PAUSED";
+pub(crate) const SALE_CREATION_PAUSED: &str = "ERR_SALE_CREATION_PAUSED";
+pub(crate) const TREASURY_CLAIMS_PAUSED: &str = "ERR_TREASURY_CLAIMS_PAUSED";
+pub(crate) const SALE_PAUSED: &str = "ERR_SALE_PAUSED";
+pub(crate) const SALE_NOT_PAUSED: &str = "ERR_SALE_NOT_PAUSED";
+pub(crate) const IN_TOKEN_NOT_ALLOWED: &str = "ERR_IN_TOKEN_NOT_ALLOWED";
+pub(crate) const OUT_TOKEN_DENIED: &str = "ERR_OUT_TOKEN_DENIED";
+pub(crate) const INVALID_TOKEN: &str = "ERR_INVALID_TOKEN";
+pub(crate) const NOTHING_TO_WITHDRAW: &str = "ERR_NOTHING_TO_WITHDRAW";
+pub(crate) const SELF_TRANSFER: &str = "ERR_SELF_TRANSFER";
+pub(crate) const ZERO_AMOUNT: &str = "ERR_ZERO_AMOUNT";
+pub(crate) const NOT_SALE_OWNER: &str = "ERR_NOT_SALE_OWNER";
+pub(crate) const NOT_PENDING_OWNER: &str = "ERR_NOT_PENDING_OWNER";
+pub(crate) const INVALID_REFERRAL_CONFIG: &str = "ERR_INVALID_REFERRAL_CONFIG";
+pub(crate) const NO_ESCROWED_REFERRAL_REWARD: &str = "ERR_NO_ESCROWED_REFERRAL_REWARD";
+pub(crate) const REFERRAL_ESCROW_EXPIRED: &str = "ERR_REFERRAL_ESCROW_EXPIRED";
+pub(crate) const REFERRAL_ESCROW_NOT_EXPIRED: &str = "ERR_REFERRAL_ESCROW_NOT_EXPIRED";
+pub(crate) const INVALID_LIQUIDITY_CONFIG: &str = "ERR_INVALID_LIQUIDITY_CONFIG";
+pub(crate) const NO_LIQUIDITY: &str = "ERR_NO_LIQUIDITY";
+pub(crate) const SALE_NOT_ENDED: &str = "ERR_SALE_NOT_ENDED";
+pub(crate) const LIQUIDITY_NOT_PENDING: &str = "ERR_LIQUIDITY_NOT_PENDING";
+pub(crate) const NO_LIQUIDITY_TO_SEED: &str = "ERR_NO_LIQUIDITY_TO_SEED";
+pub(crate) const LIQUIDITY_SEEDING_FAILED: &str = "ERR_LIQUIDITY_SEEDING_FAILED";
+pub(crate) const LIQUIDITY_LOCKED: &str = "ERR_LIQUIDITY_LOCKED";
+pub(crate) const INVALID_PROCEEDS_LOCK: &str = "ERR_INVALID_PROCEEDS_LOCK";
+pub(crate) const NO_PROCEEDS_LOCK: &str = "ERR_NO_PROCEEDS_LOCK";
+pub(crate) const NOT_ARBITER: &str = "ERR_NOT_ARBITER";
+pub(crate) const INVALID_MILESTONE: &str = "ERR_INVALID_MILESTONE";
+pub(crate) const PROCEEDS_CLAWED_BACK: &str = "ERR_PROCEEDS_CLAWED_BACK";
+pub(crate) const PROCEEDS_NOT_CLAWED_BACK: &str = "ERR_PROCEEDS_NOT_CLAWED_BACK";
+pub(crate) const NO_REFUND_VOTE: &str = "ERR_NO_REFUND_VOTE";
+pub(crate) const PROCEEDS_RELEASED: &str = "ERR_PROCEEDS_RELEASED";
+pub(crate) const ALREADY_VOTED: &str = "ERR_ALREADY_VOTED";
+pub(crate) const NO_VOTING_POWER: &str = "ERR_NO_VOTING_POWER";
+pub(crate) const NO_STATE_TO_MIGRATE: &str = "ERR_NO_STATE_TO_MIGRATE";
+pub(crate) const OWNERSHIP_TRANSFERS_PAUSED: &str = "ERR_OWNERSHIP_TRANSFERS_PAUSED";
@@ -0,0 +1,57 @@
+use near_sdk::{env, json_types::U128, serde::Serialize, serde_json, AccountId};
+
+const EVENT_STANDARD: &str = "skyward";
+const EVENT_STANDARD_VERSION: &str = "1.0.0";
+
+/// Events are logged in the NEP-297 format.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data")]
+#[serde(rename_all = "snake_case")]
+pub enum Event<'a> {
+    InternalTransfer(Vec<InternalTransferData<'a>>),
+    AccountDeposit(Vec<AccountDepositData<'a>>),
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InternalTransferData<'a> {
+    pub sender_id: &'a AccountId,
+    pub receiver_id: &'a AccountId,
+    pub token_account_id: &'a AccountId,
+    pub amount: U128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountDepositData<'a> {
+    pub sender_id: &'a AccountId,
+    pub account_id: &'a AccountId,
+    pub token_account_id: &'a AccountId,
+    pub amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+impl Event<'_> {
+    pub fn emit(&self) {
+        let log = EventLog {
+            standard: EVENT_STANDARD,
+            version: EVENT_STANDARD_VERSION,
+            event: self,
+        };
+        env::log_str(&format!(
+            "EVENT_JSON:{}",
+            serde_json::to_string(&log).unwrap()
+        ));
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{Contract, ContractExt, Sale, StorageKey};
+use near_sdk::{
+    borsh::{BorshDeserialize, BorshSerialize},
+    collections::{LookupMap, Vector},
+    env,
+    json_types::{U128, U64},
+    near_bindgen,
+    serde::{Deserialize, Serialize},
+    BlockHeight, Timestamp,
+};
+
+pub(crate) const MAX_SALE_HISTORY_LENGTH: u64 = 64;
+
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct SaleCheckpoint {
+    pub timestamp: Timestamp,
+    pub block_height: BlockHeight,
+    pub in_token_remaining: u128,
+    pub total_shares: u128,
+    pub in_token_paid: u128,
+    pub out_token_distributed: Vec<u128>,
+}
+
+/// Ring buffer of the latest checkpoints of a sale. The whole buffer is allocated when the sale is
+/// created, so the creator pays for its storage and recording checkpoints doesn't grow it.
+#[derive(BorshSerialize, BorshDeserialize)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct SaleHistory {
+    pub checkpoints: Vector<SaleCheckpoint>,
+    /// The number of recorded checkpoints.
+    pub len: u64,
+    /// The index the next checkpoint is written to.
+    pub next_index: u64,
+}
+
+pub type SaleHistories = LookupMap<u64, SaleHistory>;
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct SaleCheckpointOutput {
+    pub timestamp: U64,
+    pub block_height: U64,
+    pub in_token_remaining: U128,
+    pub total_shares: U128,
+    pub in_token_paid: U128,
+    pub out_token_distributed: Vec<U128>,
+}
+
+impl From<SaleCheckpoint> for SaleCheckpointOutput {
+    fn from(checkpoint: SaleCheckpoint) -> Self {
+        Self {
+            timestamp: checkpoint.timestamp.into(),
+            block_height: checkpoint.block_height.into(),
+            in_token_remaining: checkpoint.in_token_remaining.into(),
+            total_shares: checkpoint.total_shares.into(),
+            in_token_paid: checkpoint.in_token_paid.into(),
+            out_token_distributed: checkpoint
+                .out_token_distributed
+                .into_iter()
+                .map(|amount| amount.into())
+                .collect(),
+        }
+    }
+}
+
+impl Sale {
+    /// Records a checkpoint if the history interval has passed since the last one, or the sale
+    /// has just ended. The checkpoint is saved with the sale.
+    pub(crate) fn maybe_checkpoint(&mut self, timestamp: Timestamp) {
+        let has_ended = timestamp >= self.start_time + self.duration;
+        if !has_ended
+            && self.last_checkpoint_time > 0
+            && timestamp < self.last_checkpoint_time + self.history_interval
+        {
+            return;
+        }
+        self.last_checkpoint_time = timestamp;
+        self.pending_checkpoint = Some(Box::new(SaleCheckpoint {
+            timestamp,
+            block_height: env::block_height(),
+            in_token_remaining: self.in_token_remaining,
+            total_shares: self.total_shares,
+            in_token_paid: self.in_token_paid,
+            out_token_distributed: self
+                .out_tokens
+                .iter()
+                .map(|out_token| out_token.distributed)
+                .collect(),
+        }));
+    }
+}
+
+impl Contract {
+    pub(crate) fn internal_init_sale_history(&mut self, sale_id: u64, num_out_tokens: usize) {
+        let mut checkpoints = Vector::new(StorageKey::SaleHistory { sale_id });
+        let empty_checkpoint = SaleCheckpoint {
+            timestamp: 0,
+            block_height: 0,
+            in_token_remaining: 0,
+            total_shares: 0,
+            in_token_paid: 0,
+            out_token_distributed: vec![0; num_out_tokens],
+        };
+        for _ in 0..MAX_SALE_HISTORY_LENGTH {
+            checkpoints.push(&empty_checkpoint);
+        }
+        self.sale_histories.insert(
+            &sale_id,
+            &SaleHistory {
+                checkpoints,
+                len: 0,
+                next_index: 0,
+            },
+        );
+    }
+
+    pub(crate) fn internal_remove_sale_history(&mut self, sale_id: u64) {
+        if let Some(mut history) = self.sale_histories.remove(&sale_id) {
+            history.checkpoints.clear();
+        }
+    }
+
+    /// Sales created before the history was introduced don't record checkpoints.
+    pub(crate) fn internal_record_checkpoint(&mut self, sale_id: u64, checkpoint: SaleCheckpoint) {
+        let mut history = match self.sale_histories.get(&sale_id) {
+            Some(history) => history,
+            None => return,
+        };
+        history.checkpoints.replace(history.next_index, &checkpoint);
+        history.next_index = (history.next_index + 1) % MAX_SALE_HISTORY_LENGTH;
+        history.len = std::cmp::min(history.len + 1, MAX_SALE_HISTORY_LENGTH);
+        self.sale_histories.insert(&sale_id, &history);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Returns the checkpoints of the sale from the oldest to the latest.
+    pub fn get_sale_history(&self, sale_id: u64) -> Vec<SaleCheckpointOutput> {
+        let history = match self.sale_histories.get(&sale_id) {
+            Some(history) => history,
+            None => return vec![],
+        };
+        let first_index = history.next_index + MAX_SALE_HISTORY_LENGTH - history.len;
+        (0..history.len)
+            .map(|index| {
+                history
+                    .checkpoints
+                    .get((first_index + index) % MAX_SALE_HISTORY_LENGTH)
+                    .unwrap()
+                    .into()
+            })
+            .collect()
+    }
+}
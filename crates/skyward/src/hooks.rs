@@ -0,0 +1,58 @@
+use crate::{Contract, Sale, SALE_HOOK_GAS};
+use near_sdk::{env, ext_contract, json_types::U128, log, AccountId};
+
+#[ext_contract(ext_sale_hooks)]
+pub trait SaleHooks {
+    fn on_sale_ended(&mut self, sale_id: u64);
+
+    /// Called with the amounts of tokens credited to the proceeds receiver.
+    fn on_proceeds_distributed(
+        &mut self,
+        sale_id: u64,
+        proceeds_receiver_id: AccountId,
+        amounts: Vec<(AccountId, U128)>,
+    );
+}
+
+/// Lifecycle events that are sent to the hooks contract once the sale is saved.
+pub enum SaleHook {
+    Ended,
+    ProceedsDistributed(Vec<(AccountId, U128)>),
+}
+
+impl Sale {
+    pub(crate) fn add_hook(&mut self, hook: SaleHook) {
+        if self.hooks_contract_id.is_some() {
+            self.pending_hooks.push(hook);
+        }
+    }
+}
+
+impl Contract {
+    /// Calls the hooks contract without a callback, so failed hooks don't affect the sale. Hooks
+    /// are skipped if there isn't enough gas left.
+    pub(crate) fn internal_call_sale_hooks(&self, sale_id: u64, sale: &mut Sale) {
+        let hooks_contract_id = match sale.hooks_contract_id.as_ref() {
+            Some(hooks_contract_id) => hooks_contract_id,
+            None => return,
+        };
+        for hook in sale.pending_hooks.drain(..) {
+            let remaining_gas = env::prepaid_gas().saturating_sub(env::used_gas());
+            if remaining_gas < SALE_HOOK_GAS.saturating_mul(2) {
+                log!("Not enough gas to call the hooks of sale {}", sale_id);
+                return;
+            }
+            let hooks = ext_sale_hooks::ext(hooks_contract_id.clone())
+                .with_static_gas(SALE_HOOK_GAS)
+                .with_unused_gas_weight(0);
+            match hook {
+                SaleHook::Ended => hooks.on_sale_ended(sale_id),
+                SaleHook::ProceedsDistributed(amounts) => hooks.on_proceeds_distributed(
+                    sale_id,
+                    sale.proceeds_receiver_id.clone(),
+                    amounts,
+                ),
+            };
+        }
+    }
+}
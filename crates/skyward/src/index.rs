@@ -0,0 +1,222 @@
+use crate::{Account, Contract, ContractExt, Sale, SaleOutput, StorageKey};
+use near_sdk::{
+    borsh::{BorshDeserialize, BorshSerialize},
+    collections::{LookupMap, UnorderedSet},
+    near_bindgen,
+    serde::{Deserialize, Serialize},
+    AccountId,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum SaleStatus {
+    Upcoming,
+    Active,
+    Ended,
+    Cancelled,
+}
+
+impl SaleStatus {
+    const ALL: [SaleStatus; 4] = [
+        SaleStatus::Upcoming,
+        SaleStatus::Active,
+        SaleStatus::Ended,
+        SaleStatus::Cancelled,
+    ];
+}
+
+/// Secondary indexes of sales. A sale is moved to the collection of its current status every time
+/// it's saved, so it stays in the collection of its previous status until the next save after it
+/// starts or ends.
+#[derive(BorshDeserialize, BorshSerialize)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct SaleIndex {
+    pub upcoming_sales: UnorderedSet<u64>,
+    pub active_sales: UnorderedSet<u64>,
+    pub ended_sales: UnorderedSet<u64>,
+    pub cancelled_sales: UnorderedSet<u64>,
+    /// Sales by their in and out tokens.
+    pub sales_by_token: LookupMap<AccountId, UnorderedSet<u64>>,
+    /// Sales by the accounts that receive their proceeds.
+    pub sales_by_proceeds_receiver: LookupMap<AccountId, UnorderedSet<u64>>,
+}
+
+impl SaleIndex {
+    pub fn new() -> Self {
+        Self {
+            upcoming_sales: UnorderedSet::new(StorageKey::UpcomingSales),
+            active_sales: UnorderedSet::new(StorageKey::ActiveSales),
+            ended_sales: UnorderedSet::new(StorageKey::EndedSales),
+            cancelled_sales: UnorderedSet::new(StorageKey::CancelledSales),
+            sales_by_token: LookupMap::new(StorageKey::SalesByToken),
+            sales_by_proceeds_receiver: LookupMap::new(StorageKey::SalesByProceedsReceiver),
+        }
+    }
+
+    fn status_sales(&self, status: SaleStatus) -> &UnorderedSet<u64> {
+        match status {
+            SaleStatus::Upcoming => &self.upcoming_sales,
+            SaleStatus::Active => &self.active_sales,
+            SaleStatus::Ended => &self.ended_sales,
+            SaleStatus::Cancelled => &self.cancelled_sales,
+        }
+    }
+
+    fn status_sales_mut(&mut self, status: SaleStatus) -> &mut UnorderedSet<u64> {
+        match status {
+            SaleStatus::Upcoming => &mut self.upcoming_sales,
+            SaleStatus::Active => &mut self.active_sales,
+            SaleStatus::Ended => &mut self.ended_sales,
+            SaleStatus::Cancelled => &mut self.cancelled_sales,
+        }
+    }
+
+    pub fn internal_add_sale(&mut self, sale_id: u64, sale: &Sale) {
+        self.internal_update_sale(sale_id, sale);
+        for token_account_id in sale.token_account_ids() {
+            let mut sale_ids = self
+                .sales_by_token
+                .get(&token_account_id)
+                .unwrap_or_else(|| {
+                    UnorderedSet::new(StorageKey::TokenSales {
+                        token_account_id: token_account_id.clone(),
+                    })
+                });
+            sale_ids.insert(&sale_id);
+            self.sales_by_token.insert(&token_account_id, &sale_ids);
+        }
+        self.internal_add_proceeds_receiver_sale(sale_id, &sale.proceeds_receiver_id);
+    }
+
+    pub fn internal_add_proceeds_receiver_sale(&mut self, sale_id: u64, account_id: &AccountId) {
+        let mut sale_ids = self
+            .sales_by_proceeds_receiver
+            .get(account_id)
+            .unwrap_or_else(|| {
+                UnorderedSet::new(StorageKey::ProceedsReceiverSales {
+                    account_id: account_id.clone(),
+                })
+            });
+        sale_ids.insert(&sale_id);
+        self.sales_by_proceeds_receiver
+            .insert(account_id, &sale_ids);
+    }
+
+    pub fn internal_remove_proceeds_receiver_sale(&mut self, sale_id: u64, account_id: &AccountId) {
+        if let Some(mut sale_ids) = self.sales_by_proceeds_receiver.get(account_id) {
+            sale_ids.remove(&sale_id);
+            if sale_ids.is_empty() {
+                self.sales_by_proceeds_receiver.remove(account_id);
+            } else {
+                self.sales_by_proceeds_receiver
+                    .insert(account_id, &sale_ids);
+            }
+        }
+    }
+
+    pub fn internal_remove_sale(&mut self, sale_id: u64, sale: &Sale) {
+        for status in SaleStatus::ALL {
+            self.status_sales_mut(status).remove(&sale_id);
+        }
+        for token_account_id in sale.token_account_ids() {
+            if let Some(mut sale_ids) = self.sales_by_token.get(&token_account_id) {
+                sale_ids.remove(&sale_id);
+                if sale_ids.is_empty() {
+                    self.sales_by_token.remove(&token_account_id);
+                } else {
+                    self.sales_by_token.insert(&token_account_id, &sale_ids);
+                }
+            }
+        }
+        self.internal_remove_proceeds_receiver_sale(sale_id, &sale.proceeds_receiver_id);
+    }
+
+    /// Moves the sale to the collection of its current status.
+    pub fn internal_update_sale(&mut self, sale_id: u64, sale: &Sale) {
+        let status = sale.status();
+        if self.status_sales(status).contains(&sale_id) {
+            return;
+        }
+        for other_status in SaleStatus::ALL {
+            if other_status != status {
+                self.status_sales_mut(other_status).remove(&sale_id);
+            }
+        }
+        self.status_sales_mut(status).insert(&sale_id);
+    }
+}
+
+impl Default for SaleIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Contract {
+    pub fn internal_save_sale(&mut self, sale_id: u64, mut sale: Sale) {
+        if let Some(checkpoint) = sale.pending_checkpoint.take() {
+            self.internal_record_checkpoint(sale_id, *checkpoint);
+        }
+        self.internal_call_sale_hooks(sale_id, &mut sale);
+        self.sale_index.internal_update_sale(sale_id, &sale);
+        self.sales.insert(&sale_id, &sale.into());
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Returns sales by their status as of the last time they were saved. Sales that have started
+    /// or ended since are listed under their previous status until they're saved again, e.g. by
+    /// `sale_distribute_unclaimed_tokens`.
+    pub fn get_sales_by_status(
+        &self,
+        status: SaleStatus,
+        account_id: Option<AccountId>,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<SaleOutput> {
+        let account: Option<Account> =
+            account_id.and_then(|account_id| self.accounts.get(&account_id).map(|a| a.into()));
+        let keys = self.sale_index.status_sales(status).as_vector();
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(keys.len());
+        (from_index..std::cmp::min(from_index + limit, keys.len()))
+            .filter_map(|index| self.internal_get_sale(keys.get(index).unwrap(), account.as_ref()))
+            .collect()
+    }
+
+    /// Returns sales that accept or sell the given token.
+    pub fn get_sales_by_token(
+        &self,
+        token_account_id: AccountId,
+        account_id: Option<AccountId>,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<SaleOutput> {
+        let account: Option<Account> =
+            account_id.and_then(|account_id| self.accounts.get(&account_id).map(|a| a.into()));
+        if let Some(sale_ids) = self.sale_index.sales_by_token.get(&token_account_id) {
+            let keys = sale_ids.as_vector();
+            let from_index = from_index.unwrap_or(0);
+            let limit = limit.unwrap_or(keys.len());
+            (from_index..std::cmp::min(from_index + limit, keys.len()))
+                .filter_map(|index| {
+                    self.internal_get_sale(keys.get(index).unwrap(), account.as_ref())
+                })
+                .collect()
+        } else {
+            vec![]
+        }
+    }
+
+    /// Returns sales created by the given owner.
+    pub fn get_sales_by_owner(
+        &self,
+        owner_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<SaleOutput> {
+        self.get_account_sales(owner_id, from_index, limit)
+    }
+}
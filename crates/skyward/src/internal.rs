@@ -1,6 +1,6 @@
 use crate::{
     errors,
-    utils::{AFTER_FT_TRANSFER_GAS, ONE_YOCTO},
+    utils::{AFTER_FT_TRANSFER_CALL_GAS, AFTER_FT_TRANSFER_GAS, ONE_YOCTO},
     Contract, ContractExt,
 };
 use near_contract_standards::fungible_token::core::ext_ft_core;
@@ -9,13 +9,17 @@ use near_sdk::{
     json_types::U128,
     log, near_bindgen,
     serde::{Deserialize, Serialize},
-    AccountId, NearToken, Promise,
+    serde_json, AccountId, NearToken, Promise, PromiseResult,
 };
 
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub enum FtOnTransferArgs {
     AccountDeposit,
+    /// Deposits to another registered account.
+    AccountDepositFor {
+        account_id: AccountId,
+    },
 }
 
 #[ext_contract(ext_permission_contract)]
@@ -30,16 +34,47 @@ impl Contract {
         token_account_id: &AccountId,
         amount: u128,
     ) -> Promise {
-        ext_ft_core::ext(token_account_id.clone())
+        self.internal_ft_transfer_to(account_id, account_id, token_account_id, amount, None)
+    }
+
+    /// Transfers the withdrawn tokens of the account to the receiver, or calls `ft_transfer_call`
+    /// on the receiver if the `msg` is given. Failed and unused amounts are returned to the account.
+    pub fn internal_ft_transfer_to(
+        &mut self,
+        account_id: &AccountId,
+        receiver_id: &AccountId,
+        token_account_id: &AccountId,
+        amount: u128,
+        msg: Option<String>,
+    ) -> Promise {
+        let ft = ext_ft_core::ext(token_account_id.clone())
             .with_attached_deposit(ONE_YOCTO)
-            .with_unused_gas_weight(1)
-            .ft_transfer(account_id.clone(), amount.into(), None)
-            .then(
-                Self::ext(env::current_account_id())
-                    .with_unused_gas_weight(1)
-                    .with_static_gas(AFTER_FT_TRANSFER_GAS)
-                    .after_ft_transfer(account_id.clone(), token_account_id.clone(), amount.into()),
-            )
+            .with_unused_gas_weight(1);
+        if let Some(msg) = msg {
+            ft.ft_transfer_call(receiver_id.clone(), amount.into(), None, msg)
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_unused_gas_weight(1)
+                        .with_static_gas(AFTER_FT_TRANSFER_CALL_GAS)
+                        .after_ft_transfer_call(
+                            account_id.clone(),
+                            token_account_id.clone(),
+                            amount.into(),
+                        ),
+                )
+        } else {
+            ft.ft_transfer(receiver_id.clone(), amount.into(), None)
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_unused_gas_weight(1)
+                        .with_static_gas(AFTER_FT_TRANSFER_GAS)
+                        .after_ft_transfer(
+                            account_id.clone(),
+                            token_account_id.clone(),
+                            amount.into(),
+                        ),
+                )
+        }
     }
 }
 
@@ -67,6 +102,35 @@ impl Contract {
         promise_success
     }
 
+    /// Returns the amount used by the receiver and returns the rest to the account.
+    #[private]
+    pub fn after_ft_transfer_call(
+        &mut self,
+        account_id: AccountId,
+        token_account_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        let used_amount = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<U128>(&value)
+                .map(|used_amount| std::cmp::min(used_amount.0, amount.0))
+                .unwrap_or(amount.0),
+            PromiseResult::Failed => 0,
+        };
+        let refund_amount = amount.0 - used_amount;
+        if refund_amount > 0 {
+            log!(
+                "{} by {} token {} amount {}",
+                errors::TOKEN_WITHDRAW_FAILED,
+                account_id,
+                token_account_id,
+                refund_amount
+            );
+            let mut account = self.internal_unwrap_account(&account_id);
+            account.internal_token_deposit(&token_account_id, refund_amount);
+        }
+        used_amount.into()
+    }
+
     #[private]
     pub fn after_near_deposit(&mut self, amount: U128) -> bool {
         let promise_success = is_promise_success();
@@ -82,11 +146,13 @@ impl Contract {
     }
 
     #[private]
+    #[allow(clippy::too_many_arguments)]
     pub fn after_is_approved(
         &mut self,
         #[callback_unwrap] is_approved: bool,
         sale_id: u64,
         account_id: AccountId,
+        in_token_account_id: Option<AccountId>,
         in_amount: U128,
         referral_id: Option<AccountId>,
         attached_deposit: U128,
@@ -98,6 +164,7 @@ impl Contract {
             .internal_deposit_in_amount(
                 sale_id,
                 &account_id,
+                in_token_account_id.as_ref(),
                 in_amount.0,
                 referral_id.as_ref(),
                 true,
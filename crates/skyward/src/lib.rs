@@ -1,13 +1,35 @@
 pub mod account;
 pub(crate) mod errors;
+pub mod events;
+pub mod history;
+pub mod hooks;
+pub mod index;
 mod internal;
+pub mod liquidity;
+mod migrate;
+mod ownership;
+pub mod pause;
+pub mod policy;
+pub mod price;
+pub mod proceeds;
+pub mod referral;
 pub mod sale;
 pub mod sub;
 pub mod treasury;
 pub(crate) mod utils;
 
 pub use crate::account::*;
+pub use crate::events::*;
+pub use crate::history::*;
+pub use crate::hooks::*;
+pub use crate::index::*;
 pub use crate::internal::*;
+pub use crate::liquidity::*;
+pub use crate::pause::*;
+pub use crate::policy::*;
+pub use crate::price::*;
+pub use crate::proceeds::*;
+pub use crate::referral::*;
 pub use crate::sale::*;
 pub use crate::sub::*;
 pub use crate::treasury::*;
@@ -15,9 +37,9 @@ pub(crate) use crate::utils::*;
 
 use near_sdk::{
     borsh::{BorshDeserialize, BorshSerialize},
-    collections::LookupMap,
+    collections::{LookupMap, UnorderedMap},
     json_types::U128,
-    near_bindgen, AccountId, BorshStorageKey, PanicOnDefault,
+    near_bindgen, AccountId, BorshStorageKey, Duration, PanicOnDefault,
 };
 
 #[derive(BorshStorageKey, BorshSerialize)]
@@ -29,6 +51,29 @@ pub(crate) enum StorageKey {
     AccountSales { account_id: AccountId },
     Sales,
     TreasuryBalances,
+    AllowedInTokens,
+    DeniedOutTokens,
+    TreasuryListingFees,
+    ActiveSales,
+    EndedSales,
+    CancelledSales,
+    SalesByToken,
+    TokenSales { token_account_id: AccountId },
+    Referrals,
+    AccountReferrals { account_id: AccountId },
+    Referrers,
+    ReferralEarnings,
+    AccountReferralEarnings { account_id: AccountId },
+    ReferralLeaderboards,
+    ReferralLost,
+    ReferralEscrow,
+    AccountReferralEscrow { account_id: AccountId },
+    SaleHistories,
+    SaleHistory { sale_id: u64 },
+    RefundVotes,
+    SalesByProceedsReceiver,
+    ProceedsReceiverSales { account_id: AccountId },
+    UpcomingSales,
 }
 
 #[near_bindgen]
@@ -44,6 +89,27 @@ pub struct Contract {
     pub num_sales: u64,
 
     pub treasury: Treasury,
+
+    pub pause_flags: PauseFlags,
+
+    pub token_policy: TokenPolicy,
+
+    pub sale_index: SaleIndex,
+
+    pub referrals: Referrals,
+
+    /// The account that first referred the given account. Used for second level referral fees.
+    pub referrers: LookupMap<AccountId, AccountId>,
+
+    pub referral_stats: ReferralStats,
+
+    pub referral_escrow: LookupMap<AccountId, UnorderedMap<AccountId, EscrowedReferralReward>>,
+    /// How long referral rewards of unregistered referrers are escrowed. Zero disables escrow.
+    pub referral_escrow_duration: Duration,
+
+    pub sale_histories: SaleHistories,
+
+    pub refund_votes: RefundVotes,
 }
 
 #[near_bindgen]
@@ -55,7 +121,17 @@ impl Contract {
             accounts: LookupMap::new(StorageKey::Accounts),
             sales: LookupMap::new(StorageKey::Sales),
             num_sales: 0,
+            token_policy: TokenPolicy::new(&w_near_token_id),
             treasury: Treasury::new(listing_fee_near.0, w_near_token_id),
+            pause_flags: PauseFlags::default(),
+            sale_index: SaleIndex::new(),
+            referrals: LookupMap::new(StorageKey::Referrals),
+            referrers: LookupMap::new(StorageKey::Referrers),
+            referral_stats: ReferralStats::new(),
+            referral_escrow: LookupMap::new(StorageKey::ReferralEscrow),
+            referral_escrow_duration: DEFAULT_REFERRAL_ESCROW_DURATION,
+            sale_histories: LookupMap::new(StorageKey::SaleHistories),
+            refund_votes: LookupMap::new(StorageKey::RefundVotes),
         }
     }
 }
@@ -0,0 +1,555 @@
+use crate::{
+    errors, BasicPoints, Contract, ContractExt, Sale, ADD_LIQUIDITY_GAS, ADD_SIMPLE_POOL_GAS,
+    AFTER_ADD_LIQUIDITY_GAS, AFTER_ADD_SIMPLE_POOL_GAS, AFTER_AMM_WITHDRAW_GAS,
+    AFTER_FT_TRANSFER_GAS, AFTER_LIQUIDITY_DEPOSIT_GAS, AMM_WITHDRAW_GAS, FT_TRANSFER_CALL_GAS,
+    ONE_YOCTO, STORAGE_DEPOSIT_GAS,
+};
+use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_sdk::{
+    assert_one_yocto,
+    borsh::{BorshDeserialize, BorshSerialize},
+    env, ext_contract, is_promise_success,
+    json_types::{U128, U64},
+    log, near_bindgen,
+    serde::{Deserialize, Serialize},
+    serde_json, AccountId, Duration, NearToken, Promise, PromiseResult, Timestamp,
+};
+use primitive_types::U256;
+
+pub(crate) const LIQUIDITY_BPT_DENOMINATOR: u128 = 10000;
+/// Ref Finance pool fees are in basic points.
+pub(crate) const MAX_POOL_FEE: u32 = 10000;
+/// Registers the contract on the AMM.
+pub(crate) const AMM_STORAGE_DEPOSIT: NearToken = NearToken::from_millinear(100);
+/// Pays for the storage of the new pool.
+pub(crate) const ADD_SIMPLE_POOL_DEPOSIT: NearToken = NearToken::from_millinear(100);
+
+/// The subset of the Ref Finance exchange interface used to seed liquidity.
+#[ext_contract(ext_amm)]
+pub trait Amm {
+    fn storage_deposit(&mut self, account_id: Option<AccountId>, registration_only: Option<bool>);
+
+    fn register_tokens(&mut self, token_ids: Vec<AccountId>);
+
+    fn add_simple_pool(&mut self, tokens: Vec<AccountId>, fee: u32) -> u64;
+
+    fn add_liquidity(
+        &mut self,
+        pool_id: u64,
+        amounts: Vec<U128>,
+        min_amounts: Option<Vec<U128>>,
+    ) -> U128;
+
+    fn withdraw(&mut self, token_id: AccountId, amount: U128, unregister: Option<bool>);
+
+    fn mft_transfer(
+        &mut self,
+        token_id: String,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+    );
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[borsh(crate = "near_sdk::borsh")]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum LiquidityStatus {
+    /// The sale hasn't ended or the liquidity wasn't seeded yet.
+    Pending,
+    /// The pool is being created and funded.
+    Seeding,
+    Seeded,
+    /// The reserved tokens were returned to the proceeds receiver.
+    Failed,
+}
+
+/// Seeds a pool of the first in token and the first out token of the sale when it ends.
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct SaleLiquidity {
+    pub amm_contract_id: AccountId,
+    /// The part of the raised first in token that is reserved for the pool.
+    pub in_token_bpt: BasicPoints,
+    /// The pool fee in basic points.
+    pub fee: u32,
+    /// The duration the LP shares are locked for after the pool is seeded.
+    pub lock_duration: Duration,
+    /// The reserved amount of the first in token.
+    pub in_amount: u128,
+    /// The extra amount of the first out token funded by the creator.
+    pub out_amount: u128,
+    pub status: LiquidityStatus,
+    pub pool_id: Option<u64>,
+    /// LP shares held by the contract until `unlock_time`.
+    pub shares: u128,
+    pub unlock_time: Option<Timestamp>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SaleInputLiquidity {
+    /// A Ref Finance compatible AMM contract.
+    pub amm_contract_id: AccountId,
+    pub in_token_bpt: BasicPoints,
+    /// Withdrawn from the creator in addition to the sale balance of the first out token.
+    pub out_token_amount: U128,
+    pub fee: u32,
+    pub lock_duration: U64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct SaleLiquidityOutput {
+    pub amm_contract_id: AccountId,
+    pub in_token_bpt: BasicPoints,
+    pub fee: u32,
+    pub lock_duration: U64,
+    pub in_amount: U128,
+    pub out_amount: U128,
+    pub status: LiquidityStatus,
+    pub pool_id: Option<u64>,
+    pub shares: U128,
+    pub unlock_time: Option<U64>,
+}
+
+impl SaleLiquidity {
+    pub fn from_input(liquidity: SaleInputLiquidity) -> Self {
+        Self {
+            amm_contract_id: liquidity.amm_contract_id,
+            in_token_bpt: liquidity.in_token_bpt,
+            fee: liquidity.fee,
+            lock_duration: liquidity.lock_duration.0,
+            in_amount: 0,
+            out_amount: liquidity.out_token_amount.0,
+            status: LiquidityStatus::Pending,
+            pool_id: None,
+            shares: 0,
+            unlock_time: None,
+        }
+    }
+
+    pub fn assert_valid(&self) {
+        assert!(
+            self.in_token_bpt > 0
+                && self.in_token_bpt as u128 <= LIQUIDITY_BPT_DENOMINATOR
+                && self.fee < MAX_POOL_FEE
+                && self.out_amount > 0,
+            "{}",
+            errors::INVALID_LIQUIDITY_CONFIG
+        );
+    }
+
+    /// Returns the part of the proceeds that is reserved for the pool.
+    pub fn reserve_amount(&self, proceeds: u128) -> u128 {
+        (U256::from(proceeds) * U256::from(self.in_token_bpt)
+            / U256::from(LIQUIDITY_BPT_DENOMINATOR))
+        .as_u128()
+    }
+}
+
+impl From<SaleLiquidity> for SaleLiquidityOutput {
+    fn from(liquidity: SaleLiquidity) -> Self {
+        Self {
+            amm_contract_id: liquidity.amm_contract_id,
+            in_token_bpt: liquidity.in_token_bpt,
+            fee: liquidity.fee,
+            lock_duration: liquidity.lock_duration.into(),
+            in_amount: liquidity.in_amount.into(),
+            out_amount: liquidity.out_amount.into(),
+            status: liquidity.status,
+            pool_id: liquidity.pool_id,
+            shares: liquidity.shares.into(),
+            unlock_time: liquidity.unlock_time.map(|t| t.into()),
+        }
+    }
+}
+
+impl Sale {
+    /// Returns the tokens of the pool.
+    pub fn liquidity_token_account_ids(&self) -> Vec<AccountId> {
+        vec![
+            self.in_tokens[0].token_account_id.clone(),
+            self.out_tokens[0].token_account_id.clone(),
+        ]
+    }
+
+    /// Returns the amounts of the first in token and the first out token that match the average
+    /// price of the sale, limited by the reserved amounts. Out tokens returned to the proceeds
+    /// receiver don't count as sold.
+    pub fn liquidity_amounts(&self, in_reserve: u128, out_reserve: u128) -> (u128, u128) {
+        let in_paid = U256::from(self.in_tokens[0].from_normalized(self.in_token_paid));
+        let out_sold = U256::from(self.out_tokens[0].sold());
+        if in_paid.is_zero() || out_sold.is_zero() {
+            return (0, 0);
+        }
+        let out_amount = U256::from(in_reserve) * out_sold / in_paid;
+        if out_amount <= U256::from(out_reserve) {
+            (in_reserve, out_amount.as_u128())
+        } else {
+            let in_amount = U256::from(out_reserve) * in_paid / out_sold;
+            (in_amount.as_u128(), out_reserve)
+        }
+    }
+}
+
+impl Contract {
+    /// Returns the reserved liquidity to the proceeds receiver.
+    fn internal_refund_liquidity(&mut self, sale: &Sale, in_amount: u128, out_amount: u128) {
+        let mut account = self.internal_unwrap_account(&sale.proceeds_receiver_id);
+        if in_amount > 0 {
+            account.internal_token_deposit(&sale.in_tokens[0].token_account_id, in_amount);
+        }
+        if out_amount > 0 {
+            account.internal_token_deposit(&sale.out_tokens[0].token_account_id, out_amount);
+        }
+        self.accounts
+            .insert(&sale.proceeds_receiver_id, &account.into());
+    }
+
+    /// Returns the reserved liquidity to the proceeds receiver and the AMM deposits to the account
+    /// that seeded the liquidity.
+    fn internal_fail_liquidity(
+        &mut self,
+        sale_id: u64,
+        mut sale: Sale,
+        payer_id: AccountId,
+        deposit: NearToken,
+    ) -> Promise {
+        let liquidity = sale.liquidity.as_mut().unwrap();
+        let (in_amount, out_amount) = (liquidity.in_amount, liquidity.out_amount);
+        liquidity.in_amount = 0;
+        liquidity.out_amount = 0;
+        liquidity.status = LiquidityStatus::Failed;
+        self.internal_refund_liquidity(&sale, in_amount, out_amount);
+        self.internal_save_sale(sale_id, sale);
+        Promise::new(payer_id).transfer(deposit)
+    }
+
+    /// Withdraws tokens deposited to the AMM back to the proceeds receiver.
+    fn internal_amm_withdraw(
+        &self,
+        amm_contract_id: &AccountId,
+        account_id: &AccountId,
+        token_account_id: &AccountId,
+        amount: u128,
+    ) {
+        if amount == 0 {
+            return;
+        }
+        ext_amm::ext(amm_contract_id.clone())
+            .with_attached_deposit(ONE_YOCTO)
+            .with_static_gas(AMM_WITHDRAW_GAS)
+            .withdraw(token_account_id.clone(), amount.into(), None)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(AFTER_AMM_WITHDRAW_GAS)
+                    .after_amm_withdraw(
+                        account_id.clone(),
+                        token_account_id.clone(),
+                        amount.into(),
+                    ),
+            );
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Seeds the AMM pool of an ended sale at the average price of the sale. Can be called by
+    /// anyone who attaches the storage deposits of the AMM. The part of the reserved tokens that
+    /// doesn't match the price is returned to the proceeds receiver.
+    #[payable]
+    pub fn sale_seed_liquidity(&mut self, sale_id: u64) -> Promise {
+        assert!(!self.pause_flags.claims, "{}", errors::CLAIMS_PAUSED);
+        let required_deposit = AMM_STORAGE_DEPOSIT.saturating_add(ADD_SIMPLE_POOL_DEPOSIT);
+        assert!(
+            env::attached_deposit() >= required_deposit,
+            "{}",
+            errors::NOT_ENOUGH_ATTACHED_BALANCE
+        );
+        let mut sale = self.internal_unwrap_sale(sale_id);
+        assert!(sale.has_ended(), "{}", errors::SALE_NOT_ENDED);
+        self.internal_distribute_unclaimed_tokens(&mut sale);
+        let liquidity = sale.liquidity.clone().expect(errors::NO_LIQUIDITY);
+        assert_eq!(
+            liquidity.status,
+            LiquidityStatus::Pending,
+            "{}",
+            errors::LIQUIDITY_NOT_PENDING
+        );
+
+        let (in_amount, out_amount) =
+            sale.liquidity_amounts(liquidity.in_amount, liquidity.out_amount);
+        if in_amount == 0 || out_amount == 0 {
+            log!("{} {}", errors::NO_LIQUIDITY_TO_SEED, sale_id);
+            return self.internal_fail_liquidity(
+                sale_id,
+                sale,
+                env::predecessor_account_id(),
+                env::attached_deposit(),
+            );
+        }
+        self.internal_refund_liquidity(
+            &sale,
+            liquidity.in_amount - in_amount,
+            liquidity.out_amount - out_amount,
+        );
+        let tokens = sale.liquidity_token_account_ids();
+        let sale_liquidity = sale.liquidity.as_mut().unwrap();
+        sale_liquidity.in_amount = in_amount;
+        sale_liquidity.out_amount = out_amount;
+        sale_liquidity.status = LiquidityStatus::Seeding;
+        self.internal_save_sale(sale_id, sale);
+
+        let refund = env::attached_deposit().saturating_sub(required_deposit);
+        if refund > ONE_YOCTO {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+
+        let amm = liquidity.amm_contract_id;
+        ext_amm::ext(amm.clone())
+            .with_attached_deposit(AMM_STORAGE_DEPOSIT)
+            .with_static_gas(STORAGE_DEPOSIT_GAS)
+            .storage_deposit(None, None)
+            .then(
+                ext_amm::ext(amm.clone())
+                    .with_attached_deposit(ONE_YOCTO)
+                    .with_static_gas(STORAGE_DEPOSIT_GAS)
+                    .register_tokens(tokens.clone()),
+            )
+            .then(
+                ext_amm::ext(amm)
+                    .with_attached_deposit(ADD_SIMPLE_POOL_DEPOSIT)
+                    .with_static_gas(ADD_SIMPLE_POOL_GAS)
+                    .add_simple_pool(tokens, liquidity.fee),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(AFTER_ADD_SIMPLE_POOL_GAS)
+                    .after_add_simple_pool(sale_id, env::predecessor_account_id()),
+            )
+    }
+
+    /// Deposits the reserved tokens to the AMM once the pool is created. If the pool wasn't
+    /// created, its deposit is returned to the payer. The storage deposit stays registered with
+    /// the AMM for the contract.
+    #[private]
+    pub fn after_add_simple_pool(&mut self, sale_id: u64, payer_id: AccountId) {
+        let mut sale = self.internal_unwrap_sale(sale_id);
+        let pool_id = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<u64>(&value).ok(),
+            PromiseResult::Failed => None,
+        };
+        let pool_id = match pool_id {
+            Some(pool_id) => pool_id,
+            None => {
+                log!("{} {}", errors::LIQUIDITY_SEEDING_FAILED, sale_id);
+                self.internal_fail_liquidity(sale_id, sale, payer_id, ADD_SIMPLE_POOL_DEPOSIT);
+                return;
+            }
+        };
+        let liquidity = sale.liquidity.as_mut().unwrap();
+        liquidity.pool_id = Some(pool_id);
+        let amm = liquidity.amm_contract_id.clone();
+        let (in_amount, out_amount) = (liquidity.in_amount, liquidity.out_amount);
+        let in_token_account_id = sale.in_tokens[0].token_account_id.clone();
+        let out_token_account_id = sale.out_tokens[0].token_account_id.clone();
+        self.internal_save_sale(sale_id, sale);
+
+        ext_ft_core::ext(in_token_account_id)
+            .with_attached_deposit(ONE_YOCTO)
+            .with_static_gas(FT_TRANSFER_CALL_GAS)
+            .ft_transfer_call(amm.clone(), in_amount.into(), None, String::new())
+            .and(
+                ext_ft_core::ext(out_token_account_id)
+                    .with_attached_deposit(ONE_YOCTO)
+                    .with_static_gas(FT_TRANSFER_CALL_GAS)
+                    .ft_transfer_call(amm, out_amount.into(), None, String::new()),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(AFTER_LIQUIDITY_DEPOSIT_GAS)
+                    .after_liquidity_deposit(sale_id),
+            );
+    }
+
+    /// Adds the deposited tokens to the pool. If any of the deposits failed, the returned tokens
+    /// and the tokens deposited to the AMM go back to the proceeds receiver.
+    #[private]
+    pub fn after_liquidity_deposit(&mut self, sale_id: u64) {
+        let mut sale = self.internal_unwrap_sale(sale_id);
+        let liquidity = sale.liquidity.clone().unwrap();
+        let amounts = [liquidity.in_amount, liquidity.out_amount];
+        let used_amounts: Vec<u128> = amounts
+            .iter()
+            .enumerate()
+            .map(|(index, &amount)| match env::promise_result(index as u64) {
+                PromiseResult::Successful(value) => serde_json::from_slice::<U128>(&value)
+                    .map(|used_amount| std::cmp::min(used_amount.0, amount))
+                    .unwrap_or(amount),
+                PromiseResult::Failed => 0,
+            })
+            .collect();
+        if used_amounts[..] == amounts[..] {
+            ext_amm::ext(liquidity.amm_contract_id)
+                .with_attached_deposit(ONE_YOCTO)
+                .with_static_gas(ADD_LIQUIDITY_GAS)
+                .add_liquidity(
+                    liquidity.pool_id.unwrap(),
+                    amounts.iter().map(|&amount| amount.into()).collect(),
+                    None,
+                )
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(AFTER_ADD_LIQUIDITY_GAS)
+                        .after_add_liquidity(sale_id),
+                );
+            return;
+        }
+
+        log!("{} {}", errors::LIQUIDITY_SEEDING_FAILED, sale_id);
+        self.internal_refund_liquidity(
+            &sale,
+            amounts[0] - used_amounts[0],
+            amounts[1] - used_amounts[1],
+        );
+        let proceeds_receiver_id = sale.proceeds_receiver_id.clone();
+        for (token_account_id, used_amount) in
+            sale.liquidity_token_account_ids().iter().zip(used_amounts)
+        {
+            self.internal_amm_withdraw(
+                &liquidity.amm_contract_id,
+                &proceeds_receiver_id,
+                token_account_id,
+                used_amount,
+            );
+        }
+        let sale_liquidity = sale.liquidity.as_mut().unwrap();
+        sale_liquidity.in_amount = 0;
+        sale_liquidity.out_amount = 0;
+        sale_liquidity.status = LiquidityStatus::Failed;
+        self.internal_save_sale(sale_id, sale);
+    }
+
+    /// Locks the received LP shares. If adding liquidity failed, the deposited tokens are
+    /// withdrawn from the AMM to the proceeds receiver.
+    #[private]
+    pub fn after_add_liquidity(&mut self, sale_id: u64) -> U128 {
+        let mut sale = self.internal_unwrap_sale(sale_id);
+        let shares = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<U128>(&value).ok(),
+            PromiseResult::Failed => None,
+        };
+        let liquidity = sale.liquidity.as_mut().unwrap();
+        if let Some(shares) = shares {
+            liquidity.shares = shares.0;
+            liquidity.unlock_time = Some(env::block_timestamp() + liquidity.lock_duration);
+            liquidity.status = LiquidityStatus::Seeded;
+            self.internal_save_sale(sale_id, sale);
+            return shares;
+        }
+
+        log!("{} {}", errors::LIQUIDITY_SEEDING_FAILED, sale_id);
+        let amm_contract_id = liquidity.amm_contract_id.clone();
+        let amounts = [liquidity.in_amount, liquidity.out_amount];
+        liquidity.in_amount = 0;
+        liquidity.out_amount = 0;
+        liquidity.status = LiquidityStatus::Failed;
+        for (token_account_id, amount) in sale.liquidity_token_account_ids().iter().zip(amounts) {
+            self.internal_amm_withdraw(
+                &amm_contract_id,
+                &sale.proceeds_receiver_id,
+                token_account_id,
+                amount,
+            );
+        }
+        self.internal_save_sale(sale_id, sale);
+        U128(0)
+    }
+
+    #[private]
+    pub fn after_amm_withdraw(
+        &mut self,
+        account_id: AccountId,
+        token_account_id: AccountId,
+        amount: U128,
+    ) -> bool {
+        let promise_success = is_promise_success();
+        if promise_success {
+            let mut account = self.internal_unwrap_account(&account_id);
+            account.internal_token_deposit(&token_account_id, amount.0);
+            self.accounts.insert(&account_id, &account.into());
+        } else {
+            log!(
+                "{} by {} token {} amount {}",
+                errors::TOKEN_WITHDRAW_FAILED,
+                account_id,
+                token_account_id,
+                amount.0
+            );
+        }
+        promise_success
+    }
+
+    /// Transfers the unlocked LP shares to the proceeds receiver. Can be called by the sale owner
+    /// or the proceeds receiver. The proceeds receiver has to be registered for the LP token with
+    /// `mft_register` on the AMM first, otherwise the transfer fails and the shares are returned
+    /// to the sale.
+    #[payable]
+    pub fn sale_withdraw_liquidity_shares(&mut self, sale_id: u64) -> Promise {
+        assert_one_yocto();
+        assert!(
+            !self.pause_flags.withdrawals,
+            "{}",
+            errors::WITHDRAWALS_PAUSED
+        );
+        let mut sale = self.internal_unwrap_sale(sale_id);
+        let account_id = env::predecessor_account_id();
+        assert!(
+            account_id == sale.owner_id || account_id == sale.proceeds_receiver_id,
+            "{}",
+            errors::NOT_SALE_OWNER
+        );
+        let liquidity = sale.liquidity.as_mut().expect(errors::NO_LIQUIDITY);
+        assert!(
+            liquidity.status == LiquidityStatus::Seeded && liquidity.shares > 0,
+            "{}",
+            errors::NOTHING_TO_WITHDRAW
+        );
+        assert!(
+            env::block_timestamp() >= liquidity.unlock_time.unwrap(),
+            "{}",
+            errors::LIQUIDITY_LOCKED
+        );
+        let shares = liquidity.shares;
+        liquidity.shares = 0;
+        let amm_contract_id = liquidity.amm_contract_id.clone();
+        let token_id = format!(":{}", liquidity.pool_id.unwrap());
+        let proceeds_receiver_id = sale.proceeds_receiver_id.clone();
+        self.internal_save_sale(sale_id, sale);
+
+        ext_amm::ext(amm_contract_id)
+            .with_attached_deposit(ONE_YOCTO)
+            .with_static_gas(FT_TRANSFER_CALL_GAS)
+            .mft_transfer(token_id, proceeds_receiver_id, shares.into(), None)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(AFTER_FT_TRANSFER_GAS)
+                    .after_liquidity_shares_transfer(sale_id, shares.into()),
+            )
+    }
+
+    #[private]
+    pub fn after_liquidity_shares_transfer(&mut self, sale_id: u64, shares: U128) -> bool {
+        let promise_success = is_promise_success();
+        if !promise_success {
+            log!("{} {}", errors::TOKEN_WITHDRAW_FAILED, sale_id);
+            let mut sale = self.internal_unwrap_sale(sale_id);
+            sale.liquidity.as_mut().unwrap().shares += shares.0;
+            self.internal_save_sale(sale_id, sale);
+        }
+        promise_success
+    }
+}
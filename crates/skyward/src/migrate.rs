@@ -0,0 +1,83 @@
+use crate::{
+    errors, Contract, ContractExt, PauseFlags, ReferralStats, Sale, SaleIndex, StorageKey,
+    TokenPolicy, Treasury, VAccount, VSale, DEFAULT_REFERRAL_ESCROW_DURATION,
+};
+use near_sdk::{
+    assert_one_yocto,
+    borsh::BorshDeserialize,
+    collections::{LookupMap, UnorderedMap},
+    env, near_bindgen, AccountId,
+};
+
+/// The treasury before the token listing fees.
+#[derive(BorshDeserialize)]
+#[borsh(crate = "near_sdk::borsh")]
+struct OldTreasury {
+    balances: UnorderedMap<AccountId, u128>,
+    listing_fee_near: u128,
+    w_near_token_id: AccountId,
+    locked_attached_deposits: u128,
+}
+
+/// The contract before the pause flags, token policy, sale index, referral tracking, sale history
+/// and refund votes.
+#[derive(BorshDeserialize)]
+#[borsh(crate = "near_sdk::borsh")]
+struct OldContract {
+    dao: AccountId,
+    accounts: LookupMap<AccountId, VAccount>,
+    sales: LookupMap<u64, VSale>,
+    num_sales: u64,
+    treasury: OldTreasury,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Migrates the state of a contract deployed before the pause flags. The existing sales have
+    /// to be added to the sale index with `migrate_sale_index` afterwards.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let old: OldContract = env::state_read().expect(errors::NO_STATE_TO_MIGRATE);
+        let w_near_token_id = old.treasury.w_near_token_id;
+        Self {
+            dao: old.dao,
+            accounts: old.accounts,
+            sales: old.sales,
+            num_sales: old.num_sales,
+            token_policy: TokenPolicy::new(&w_near_token_id),
+            treasury: Treasury {
+                balances: old.treasury.balances,
+                listing_fee_near: old.treasury.listing_fee_near,
+                w_near_token_id,
+                locked_attached_deposits: old.treasury.locked_attached_deposits,
+                listing_fees: UnorderedMap::new(StorageKey::TreasuryListingFees),
+            },
+            pause_flags: PauseFlags::default(),
+            sale_index: SaleIndex::new(),
+            referrals: LookupMap::new(StorageKey::Referrals),
+            referrers: LookupMap::new(StorageKey::Referrers),
+            referral_stats: ReferralStats::new(),
+            referral_escrow: LookupMap::new(StorageKey::ReferralEscrow),
+            referral_escrow_duration: DEFAULT_REFERRAL_ESCROW_DURATION,
+            sale_histories: LookupMap::new(StorageKey::SaleHistories),
+            refund_votes: LookupMap::new(StorageKey::RefundVotes),
+        }
+    }
+
+    /// Adds the sales with ids from `from_index` to the sale index. Sales already in the index are
+    /// kept as is, so a range can be retried. The storage is paid by the contract.
+    #[payable]
+    pub fn migrate_sale_index(&mut self, from_index: u64, limit: u64) {
+        assert_one_yocto();
+        self.assert_called_by_dao();
+        let to_index = std::cmp::min(from_index.saturating_add(limit), self.num_sales);
+        for sale_id in from_index..to_index {
+            if let Some(v_sale) = self.sales.get(&sale_id) {
+                let sale: Sale = v_sale.into();
+                self.sale_index.internal_add_sale(sale_id, &sale);
+                self.sale_index.internal_update_sale(sale_id, &sale);
+            }
+        }
+    }
+}
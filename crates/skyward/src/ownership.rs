@@ -0,0 +1,87 @@
+use crate::{
+    assert_at_least_one_yocto, errors, refund_extra_storage_deposit, Contract, ContractExt,
+};
+use near_sdk::{env, near_bindgen, AccountId};
+
+#[near_bindgen]
+impl Contract {
+    /// Proposes the new owner of the sale, who has to accept the ownership. Proposing the current
+    /// owner cancels the transfer.
+    #[payable]
+    pub fn sale_transfer_ownership(&mut self, sale_id: u64, new_owner_id: AccountId) {
+        assert_at_least_one_yocto();
+        assert!(
+            !self.pause_flags.ownership_transfers,
+            "{}",
+            errors::OWNERSHIP_TRANSFERS_PAUSED
+        );
+        let initial_storage_usage = env::storage_usage();
+        let mut sale = self.internal_unwrap_sale(sale_id);
+        assert_eq!(
+            env::predecessor_account_id(),
+            sale.owner_id,
+            "{}",
+            errors::NOT_SALE_OWNER
+        );
+        sale.pending_owner_id = if new_owner_id == sale.owner_id {
+            None
+        } else {
+            Some(new_owner_id)
+        };
+        self.internal_save_sale(sale_id, sale);
+        refund_extra_storage_deposit(
+            env::storage_usage().saturating_sub(initial_storage_usage),
+            0,
+        );
+    }
+
+    /// Accepts the ownership of the sale. The unclaimed proceeds are distributed before the
+    /// transfer. The proceeds go to the new owner afterwards, unless the sale has a separate
+    /// proceeds receiver.
+    #[payable]
+    pub fn sale_accept_ownership(&mut self, sale_id: u64) {
+        assert_at_least_one_yocto();
+        assert!(
+            !self.pause_flags.ownership_transfers,
+            "{}",
+            errors::OWNERSHIP_TRANSFERS_PAUSED
+        );
+        let initial_storage_usage = env::storage_usage();
+        let new_owner_id = env::predecessor_account_id();
+        let mut sale = self.internal_unwrap_sale(sale_id);
+        assert_eq!(
+            sale.pending_owner_id.as_ref(),
+            Some(&new_owner_id),
+            "{}",
+            errors::NOT_PENDING_OWNER
+        );
+        self.internal_distribute_unclaimed_tokens(&mut sale);
+
+        let old_owner_id = std::mem::replace(&mut sale.owner_id, new_owner_id.clone());
+        sale.pending_owner_id = None;
+        if sale.proceeds_receiver_id == old_owner_id {
+            sale.proceeds_receiver_id = new_owner_id.clone();
+            self.sale_index
+                .internal_remove_proceeds_receiver_sale(sale_id, &old_owner_id);
+            self.sale_index
+                .internal_add_proceeds_receiver_sale(sale_id, &new_owner_id);
+        }
+
+        let mut old_owner = self.internal_unwrap_account(&old_owner_id);
+        old_owner.sales.remove(&sale_id);
+        self.accounts.insert(&old_owner_id, &old_owner.into());
+
+        let mut new_owner = self.internal_unwrap_account(&new_owner_id);
+        new_owner.sales.insert(&sale_id);
+        for token_account_id in sale.token_account_ids() {
+            self.internal_maybe_register_token(&mut new_owner, &token_account_id);
+        }
+        self.accounts.insert(&new_owner_id, &new_owner.into());
+
+        self.internal_save_sale(sale_id, sale);
+        refund_extra_storage_deposit(
+            env::storage_usage().saturating_sub(initial_storage_usage),
+            0,
+        );
+    }
+}
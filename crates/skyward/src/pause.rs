@@ -0,0 +1,74 @@
+use crate::{errors, Contract, ContractExt};
+use near_sdk::{
+    assert_one_yocto,
+    borsh::{BorshDeserialize, BorshSerialize},
+    env, near_bindgen,
+    serde::{Deserialize, Serialize},
+};
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Copy, Default)]
+#[borsh(crate = "near_sdk::borsh")]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct PauseFlags {
+    /// Deposits into sales and accounts, and token registrations.
+    pub deposits: bool,
+    /// Withdrawals from sales and accounts, including liquidity shares.
+    pub withdrawals: bool,
+    /// Claiming out tokens, referral rewards and clawbacks, distributing unclaimed tokens,
+    /// releasing and voting on locked proceeds.
+    pub claims: bool,
+    /// Creating sales.
+    pub sale_creation: bool,
+    pub treasury_claims: bool,
+    /// Proposing and accepting sale ownership transfers.
+    pub ownership_transfers: bool,
+}
+
+impl Contract {
+    pub(crate) fn assert_called_by_dao(&self) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.dao,
+            "{}",
+            errors::NOT_DAO
+        );
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    #[payable]
+    pub fn set_pause_flags(&mut self, pause_flags: PauseFlags) {
+        assert_one_yocto();
+        self.assert_called_by_dao();
+        self.pause_flags = pause_flags;
+    }
+
+    /// Pauses the sale. The sale doesn't stream tokens while paused and its end is delayed by the
+    /// paused duration.
+    #[payable]
+    pub fn sale_pause(&mut self, sale_id: u64) {
+        assert_one_yocto();
+        self.assert_called_by_dao();
+        let mut sale = self.internal_unwrap_sale(sale_id);
+        assert!(sale.paused_at.is_none(), "{}", errors::SALE_PAUSED);
+        assert!(!sale.has_ended(), "{}", errors::SALE_ENDED);
+        sale.paused_at = Some(env::block_timestamp());
+        self.internal_save_sale(sale_id, sale);
+    }
+
+    #[payable]
+    pub fn sale_resume(&mut self, sale_id: u64) {
+        assert_one_yocto();
+        self.assert_called_by_dao();
+        let mut sale = self.internal_unwrap_sale(sale_id);
+        assert!(sale.paused_at.is_some(), "{}", errors::SALE_NOT_PAUSED);
+        sale.resume();
+        self.internal_save_sale(sale_id, sale);
+    }
+
+    pub fn get_pause_flags(&self) -> PauseFlags {
+        self.pause_flags
+    }
+}
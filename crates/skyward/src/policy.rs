@@ -0,0 +1,100 @@
+use crate::{errors, Contract, ContractExt, StorageKey};
+use near_sdk::{
+    assert_one_yocto,
+    borsh::{BorshDeserialize, BorshSerialize},
+    collections::UnorderedSet,
+    near_bindgen, AccountId,
+};
+
+#[derive(BorshDeserialize, BorshSerialize)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct TokenPolicy {
+    /// Tokens that sales can accept as in tokens.
+    pub allowed_in_tokens: UnorderedSet<AccountId>,
+    /// Tokens that can't be sold.
+    pub denied_out_tokens: UnorderedSet<AccountId>,
+}
+
+impl TokenPolicy {
+    pub fn new(w_near_token_id: &AccountId) -> Self {
+        let mut allowed_in_tokens = UnorderedSet::new(StorageKey::AllowedInTokens);
+        allowed_in_tokens.insert(w_near_token_id);
+        Self {
+            allowed_in_tokens,
+            denied_out_tokens: UnorderedSet::new(StorageKey::DeniedOutTokens),
+        }
+    }
+
+    pub fn assert_in_token_allowed(&self, token_account_id: &AccountId) {
+        assert!(
+            self.allowed_in_tokens.contains(token_account_id),
+            "{}",
+            errors::IN_TOKEN_NOT_ALLOWED
+        );
+    }
+
+    pub fn assert_out_token_not_denied(&self, token_account_id: &AccountId) {
+        assert!(
+            !self.denied_out_tokens.contains(token_account_id),
+            "{}",
+            errors::OUT_TOKEN_DENIED
+        );
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    #[payable]
+    pub fn add_allowed_in_tokens(&mut self, token_account_ids: Vec<AccountId>) {
+        assert_one_yocto();
+        self.assert_called_by_dao();
+        for token_account_id in &token_account_ids {
+            self.token_policy.allowed_in_tokens.insert(token_account_id);
+        }
+    }
+
+    #[payable]
+    pub fn remove_allowed_in_tokens(&mut self, token_account_ids: Vec<AccountId>) {
+        assert_one_yocto();
+        self.assert_called_by_dao();
+        for token_account_id in &token_account_ids {
+            self.token_policy.allowed_in_tokens.remove(token_account_id);
+        }
+    }
+
+    #[payable]
+    pub fn add_denied_out_tokens(&mut self, token_account_ids: Vec<AccountId>) {
+        assert_one_yocto();
+        self.assert_called_by_dao();
+        for token_account_id in &token_account_ids {
+            self.token_policy.denied_out_tokens.insert(token_account_id);
+        }
+    }
+
+    #[payable]
+    pub fn remove_denied_out_tokens(&mut self, token_account_ids: Vec<AccountId>) {
+        assert_one_yocto();
+        self.assert_called_by_dao();
+        for token_account_id in &token_account_ids {
+            self.token_policy.denied_out_tokens.remove(token_account_id);
+        }
+    }
+
+    /// Marks the sale as curated by the DAO.
+    #[payable]
+    pub fn sale_set_verified(&mut self, sale_id: u64, verified: bool) {
+        assert_one_yocto();
+        self.assert_called_by_dao();
+        let mut sale = self.internal_unwrap_sale(sale_id);
+        sale.verified = verified;
+        self.internal_save_sale(sale_id, sale);
+    }
+
+    pub fn get_allowed_in_tokens(&self) -> Vec<AccountId> {
+        self.token_policy.allowed_in_tokens.to_vec()
+    }
+
+    pub fn get_denied_out_tokens(&self) -> Vec<AccountId> {
+        self.token_policy.denied_out_tokens.to_vec()
+    }
+}
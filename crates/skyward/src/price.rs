@@ -0,0 +1,211 @@
+use crate::{
+    errors, Account, Contract, ContractExt, ReferralPayout, Sale, Subscription,
+    TREASURY_FEE_DENOMINATOR,
+};
+use near_sdk::{
+    json_types::U128,
+    near_bindgen,
+    serde::{Deserialize, Serialize},
+    AccountId,
+};
+use primitive_types::U256;
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct SalePriceOutput {
+    pub sale_id: u64,
+    pub out_tokens: Vec<SaleOutTokenPriceOutput>,
+}
+
+/// Prices are the amounts of whole first in tokens per one whole out token with `PRICE_DECIMALS`
+/// decimals. Normalized in amounts are converted to the first in token by its weight.
+/// A price is `None` when there are no out tokens to derive it from, or when the sale has multiple
+/// out tokens, since a deposit buys all of them at once and its in amount can't be split between
+/// them.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct SaleOutTokenPriceOutput {
+    pub token_account_id: AccountId,
+    /// The price the remaining out tokens are currently streamed at.
+    pub current_price: Option<U128>,
+    /// The average price of the distributed out tokens.
+    pub average_price: Option<U128>,
+    /// The final price if no further deposits or withdrawals happen.
+    pub projected_price: Option<U128>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct SimulatedDepositOutput {
+    /// The shares the deposit would buy.
+    pub shares: U128,
+    /// The total shares of the account after the deposit.
+    pub total_shares: U128,
+    /// The estimated amount of every out token the deposit would receive by the end of the sale,
+    /// after the treasury and referral fees.
+    pub out_token_amounts: Vec<U128>,
+}
+
+/// The number of decimals of the prices.
+pub(crate) const PRICE_DECIMALS: u8 = 24;
+
+fn price(in_amount: u128, in_decimals: u8, out_amount: u128, out_decimals: u8) -> Option<U128> {
+    if out_amount == 0 {
+        return None;
+    }
+    let exponent = PRICE_DECIMALS as i32 + out_decimals as i32 - in_decimals as i32;
+    let scale = U256::from(10).checked_pow(U256::from(exponent.unsigned_abs()))?;
+    let price = if exponent >= 0 {
+        U256::from(in_amount).checked_mul(scale)? / U256::from(out_amount)
+    } else {
+        U256::from(in_amount) / U256::from(out_amount).checked_mul(scale)?
+    };
+    u128::try_from(price).ok().map(|price| price.into())
+}
+
+impl Sale {
+    pub fn price_output(&self, sale_id: u64) -> SalePriceOutput {
+        let in_token = &self.in_tokens[0];
+        // Sales without cached metadata are priced in the units of the in token.
+        let in_decimals = in_token
+            .metadata
+            .as_ref()
+            .map(|metadata| metadata.decimals)
+            .unwrap_or(PRICE_DECIMALS);
+        let is_priced = self.out_tokens.len() == 1;
+        SalePriceOutput {
+            sale_id,
+            out_tokens: self
+                .out_tokens
+                .iter()
+                .map(|out_token| {
+                    let out_decimals = out_token
+                        .metadata
+                        .as_ref()
+                        .map(|metadata| metadata.decimals)
+                        .unwrap_or(0);
+                    let out_token_price = |in_amount: u128, out_amount: u128| {
+                        if !is_priced {
+                            return None;
+                        }
+                        price(
+                            in_token.from_normalized(in_amount),
+                            in_decimals,
+                            out_amount,
+                            out_decimals,
+                        )
+                    };
+                    SaleOutTokenPriceOutput {
+                        token_account_id: out_token.token_account_id.clone(),
+                        current_price: out_token_price(
+                            self.in_token_remaining,
+                            out_token.remaining,
+                        ),
+                        average_price: out_token_price(self.in_token_paid, out_token.sold()),
+                        projected_price: out_token_price(
+                            self.in_token_paid + self.in_token_remaining,
+                            out_token.distributed + out_token.remaining,
+                        ),
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn get_sale_price(&self, sale_id: u64) -> SalePriceOutput {
+        self.internal_unwrap_sale(sale_id).price_output(sale_id)
+    }
+
+    /// Estimates the result of depositing the given amount of the in token (or the first in token)
+    /// into the sale now. Returns zero shares and out token amounts if the sale no longer accepts
+    /// deposits, e.g. it has ended or is paused.
+    pub fn simulate_deposit(
+        &self,
+        sale_id: u64,
+        account_id: AccountId,
+        amount: U128,
+        in_token_account_id: Option<AccountId>,
+    ) -> SimulatedDepositOutput {
+        let sale = self.internal_unwrap_sale(sale_id);
+        let in_token_index = in_token_account_id
+            .map(|in_token_account_id| {
+                sale.in_token_index(&in_token_account_id)
+                    .expect(errors::UNKNOWN_IN_TOKEN)
+            })
+            .unwrap_or(0);
+        let in_amount = sale.to_normalized_in_amount(in_token_index, amount.0);
+        let accepts_deposits = !sale.has_ended()
+            && sale.paused_at.is_none()
+            && (sale.total_shares == 0 || sale.in_token_remaining > 0);
+        let shares = if accepts_deposits {
+            sale.in_amount_to_shares(in_amount, false)
+        } else {
+            0
+        };
+        let subscription: Option<Subscription> =
+            self.accounts.get(&account_id).and_then(|account| {
+                let account: Account = account.into();
+                account.subs.get(&sale_id).map(|s| s.into())
+            });
+        let (account_shares, second_level_referral_ids) = subscription
+            .as_ref()
+            .map(|subscription| {
+                (
+                    subscription.shares,
+                    subscription
+                        .referral_ids()
+                        .iter()
+                        .map(|referral_id| self.internal_second_level_referrer(referral_id))
+                        .collect(),
+                )
+            })
+            .unwrap_or((0, vec![]));
+        let out_token_amounts = sale
+            .out_tokens
+            .iter()
+            .map(|out_token| {
+                if shares == 0 {
+                    return 0.into();
+                }
+                let mut amount = (U256::from(out_token.remaining) * U256::from(shares)
+                    / U256::from(sale.total_shares + shares))
+                .as_u128();
+                amount -= amount / TREASURY_FEE_DENOMINATOR;
+                if out_token.referral_payout == ReferralPayout::OutToken {
+                    match subscription.as_ref() {
+                        Some(subscription) if !second_level_referral_ids.is_empty() => {
+                            let fees: u128 = subscription
+                                .split_by_referrers(amount)
+                                .into_iter()
+                                .zip(second_level_referral_ids.iter())
+                                .map(|((_, referral_amount), second_level_referral_id)| {
+                                    out_token
+                                        .referral_fees(
+                                            referral_amount,
+                                            true,
+                                            second_level_referral_id.is_some(),
+                                        )
+                                        .total()
+                                })
+                                .sum();
+                            amount -= fees;
+                        }
+                        _ => amount -= out_token.referral_fees(amount, false, false).total(),
+                    }
+                }
+                amount.into()
+            })
+            .collect();
+        SimulatedDepositOutput {
+            shares: shares.into(),
+            total_shares: (account_shares + shares).into(),
+            out_token_amounts,
+        }
+    }
+}
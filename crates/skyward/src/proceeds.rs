@@ -0,0 +1,438 @@
+use crate::{
+    assert_at_least_one_yocto, errors, refund_extra_storage_deposit, refund_released_storage,
+    Account, BasicPoints, Contract, ContractExt, Sale, TREASURY_FEE_DENOMINATOR,
+};
+use near_sdk::{
+    assert_one_yocto,
+    borsh::{BorshDeserialize, BorshSerialize},
+    collections::LookupMap,
+    env,
+    json_types::{U128, U64},
+    log, near_bindgen,
+    serde::{Deserialize, Serialize},
+    AccountId, Duration,
+};
+use primitive_types::U256;
+
+pub(crate) const PROCEEDS_BPT_DENOMINATOR: u128 = 10000;
+
+/// Locks the in token proceeds of the sale after the fees. The locked proceeds are released to
+/// the proceeds receiver linearly after the sale ends, or earlier by milestones approved by the
+/// arbiter. The DAO can claw back the unreleased proceeds to the subscribers.
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct ProceedsLock {
+    pub vesting_duration: Duration,
+    pub arbiter_id: Option<AccountId>,
+    /// The part of the locked proceeds released by the arbiter ahead of the vesting schedule.
+    pub released_bpt: BasicPoints,
+    /// The locked proceeds of every in token.
+    pub locked: Vec<u128>,
+    /// The proceeds of every in token credited to the proceeds receiver.
+    pub released: Vec<u128>,
+    /// The clawed back proceeds of every in token refunded to the subscribers.
+    pub refunded: Vec<u128>,
+    pub clawed_back: bool,
+    /// Enables the refund vote of the subscribers. The proceeds are clawed back once the votes
+    /// reach this part of the first out token sold to the subscribers after the treasury fee.
+    pub refund_quorum_bpt: Option<BasicPoints>,
+    /// The first out token claimed before the referral fees by the subscribers that voted for
+    /// the refund.
+    pub refund_votes: u128,
+    /// The subscriptions that haven't claimed their share of the clawed back proceeds yet.
+    pub num_subscriptions: u64,
+}
+
+/// The vote weights of the subscribers that voted for the refund of a sale.
+pub type RefundVotes = LookupMap<(u64, AccountId), u128>;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProceedsLockInput {
+    /// The proceeds are released linearly during this duration after the sale ends.
+    pub vesting_duration: U64,
+    /// The account that can release the proceeds on milestones.
+    pub arbiter_id: Option<AccountId>,
+    pub refund_quorum_bpt: Option<BasicPoints>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct ProceedsLockOutput {
+    pub vesting_duration: U64,
+    pub arbiter_id: Option<AccountId>,
+    pub released_bpt: BasicPoints,
+    pub clawed_back: bool,
+    pub refund_quorum_bpt: Option<BasicPoints>,
+    pub refund_votes: U128,
+    pub in_tokens: Vec<ProceedsLockOutputInToken>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct ProceedsLockOutputInToken {
+    pub token_account_id: AccountId,
+    /// All proceeds that went into the lock.
+    pub total: U128,
+    /// Proceeds that haven't vested yet.
+    pub locked: U128,
+    /// Vested proceeds credited to the proceeds receiver.
+    pub released: U128,
+    pub refunded: U128,
+}
+
+impl ProceedsLock {
+    pub fn from_input(lock: ProceedsLockInput, num_in_tokens: usize) -> Self {
+        Self {
+            vesting_duration: lock.vesting_duration.0,
+            arbiter_id: lock.arbiter_id,
+            released_bpt: 0,
+            locked: vec![0; num_in_tokens],
+            released: vec![0; num_in_tokens],
+            refunded: vec![0; num_in_tokens],
+            clawed_back: false,
+            refund_quorum_bpt: lock.refund_quorum_bpt,
+            refund_votes: 0,
+            num_subscriptions: 0,
+        }
+    }
+}
+
+impl Sale {
+    /// Returns the vested proceeds of the in token at the given index.
+    pub fn vested_proceeds(&self, index: usize) -> u128 {
+        match &self.proceeds_lock {
+            Some(lock) => self.vested_amount(lock, lock.locked[index]),
+            None => 0,
+        }
+    }
+
+    fn vested_amount(&self, lock: &ProceedsLock, locked: u128) -> u128 {
+        let locked = U256::from(locked);
+        let released_by_arbiter =
+            locked * U256::from(lock.released_bpt) / U256::from(PROCEEDS_BPT_DENOMINATOR);
+        if !self.has_ended() {
+            return released_by_arbiter.as_u128();
+        }
+        let elapsed = env::block_timestamp().saturating_sub(self.start_time + self.duration);
+        let vested = if elapsed >= lock.vesting_duration {
+            locked
+        } else {
+            locked * U256::from(elapsed) / U256::from(lock.vesting_duration)
+        };
+        std::cmp::max(vested, released_by_arbiter).as_u128()
+    }
+
+    /// Returns the proceeds of the in token at the given index that the proceeds receiver would be
+    /// credited with if the sale was saved now, after the treasury fee, the liquidity reserve and
+    /// the locked part of the proceeds.
+    pub fn claimable_proceeds(&self, index: usize) -> u128 {
+        let mut amount = self.in_tokens[index].paid_unclaimed;
+        amount -= amount / TREASURY_FEE_DENOMINATOR;
+        if let Some(liquidity) = self.liquidity.as_ref().filter(|_| index == 0) {
+            amount -= liquidity.reserve_amount(amount);
+        }
+        match &self.proceeds_lock {
+            Some(lock) if lock.clawed_back => 0,
+            Some(lock) => {
+                self.vested_amount(lock, lock.locked[index] + amount) - lock.released[index]
+            }
+            None => amount,
+        }
+    }
+
+    /// Whether the subscriptions have to be kept for a clawback.
+    pub fn is_proceeds_lock_active(&self) -> bool {
+        match &self.proceeds_lock {
+            Some(lock) => {
+                lock.clawed_back
+                    || (0..lock.locked.len())
+                        .any(|index| self.vested_proceeds(index) < lock.locked[index])
+                    || !self.has_ended()
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_refunded(&self) -> bool {
+        self.proceeds_lock
+            .as_ref()
+            .map(|lock| lock.clawed_back)
+            .unwrap_or(false)
+    }
+
+    pub fn proceeds_lock_output(&self) -> Option<ProceedsLockOutput> {
+        self.proceeds_lock.as_ref().map(|lock| ProceedsLockOutput {
+            vesting_duration: lock.vesting_duration.into(),
+            arbiter_id: lock.arbiter_id.clone(),
+            released_bpt: lock.released_bpt,
+            clawed_back: lock.clawed_back,
+            refund_quorum_bpt: lock.refund_quorum_bpt,
+            refund_votes: lock.refund_votes.into(),
+            in_tokens: self
+                .in_tokens
+                .iter()
+                .enumerate()
+                .map(|(index, in_token)| ProceedsLockOutputInToken {
+                    token_account_id: in_token.token_account_id.clone(),
+                    total: lock.locked[index].into(),
+                    locked: if lock.clawed_back {
+                        0
+                    } else {
+                        lock.locked[index] - self.vested_proceeds(index)
+                    }
+                    .into(),
+                    released: lock.released[index].into(),
+                    refunded: lock.refunded[index].into(),
+                })
+                .collect(),
+        })
+    }
+}
+
+impl Contract {
+    /// Credits the vested proceeds to the proceeds receiver and returns the released amounts.
+    pub(crate) fn internal_release_proceeds(&mut self, sale: &mut Sale) -> Vec<(AccountId, U128)> {
+        let vested: Vec<u128> = (0..sale.in_tokens.len())
+            .map(|index| sale.vested_proceeds(index))
+            .collect();
+        let lock = match sale.proceeds_lock.as_mut() {
+            Some(lock) if !lock.clawed_back => lock,
+            _ => return vec![],
+        };
+        let mut amounts = vec![];
+        let mut account = self.internal_unwrap_account(&sale.proceeds_receiver_id);
+        for (index, in_token) in sale.in_tokens.iter().enumerate() {
+            let amount = vested[index] - lock.released[index];
+            if amount > 0 {
+                lock.released[index] += amount;
+                account.internal_token_deposit(&in_token.token_account_id, amount);
+                amounts.push((in_token.token_account_id.clone(), amount.into()));
+            }
+        }
+        self.accounts
+            .insert(&sale.proceeds_receiver_id, &account.into());
+        amounts
+    }
+
+    /// Refunds the subscriber's share of the clawed back proceeds by the spent in tokens and
+    /// removes the subscription. The subscriber returns the clawed back part of the claimed out
+    /// tokens to the proceeds receiver, so they have to be in the subscriber's balance. The last
+    /// subscription to claim also receives the rounding remainder.
+    fn internal_claim_clawback(
+        &mut self,
+        account: &mut Account,
+        account_id: &AccountId,
+        sale_id: u64,
+    ) {
+        let mut sale = self.internal_unwrap_sale(sale_id);
+        self.internal_distribute_unclaimed_tokens(&mut sale);
+        let subscription =
+            self.internal_update_subscription(account, sale_id, &mut sale, None, false);
+        let lock = sale.proceeds_lock.as_ref().unwrap();
+        let (clawed_back, locked) = sale.in_tokens.iter().enumerate().fold(
+            (U256::zero(), U256::zero()),
+            |(clawed_back, locked), (index, in_token)| {
+                (
+                    clawed_back
+                        + U256::from(
+                            in_token.to_normalized(lock.locked[index] - lock.released[index]),
+                        ),
+                    locked + U256::from(in_token.to_normalized(lock.locked[index])),
+                )
+            },
+        );
+        if !locked.is_zero() && &sale.proceeds_receiver_id != account_id {
+            let mut proceeds_receiver = self.internal_unwrap_account(&sale.proceeds_receiver_id);
+            for (out_token, claimed) in sale
+                .out_tokens
+                .iter()
+                .zip(subscription.claimed_out_balance.iter())
+            {
+                let amount = (U256::from(*claimed) * clawed_back / locked).as_u128();
+                if amount > 0 {
+                    account.internal_token_withdraw(&out_token.token_account_id, amount);
+                    proceeds_receiver.internal_token_deposit(&out_token.token_account_id, amount);
+                }
+            }
+            self.accounts
+                .insert(&sale.proceeds_receiver_id, &proceeds_receiver.into());
+        }
+        let lock = sale.proceeds_lock.as_mut().unwrap();
+        let is_last = lock.num_subscriptions == 1;
+        lock.num_subscriptions -= 1;
+        for (index, in_token) in sale.in_tokens.iter().enumerate() {
+            if in_token.paid == 0 {
+                continue;
+            }
+            let clawed_back = lock.locked[index] - lock.released[index];
+            let unrefunded = clawed_back - lock.refunded[index];
+            let amount = if is_last {
+                unrefunded
+            } else {
+                std::cmp::min(
+                    (U256::from(clawed_back) * U256::from(subscription.spent_in_balances[index])
+                        / U256::from(in_token.paid))
+                    .as_u128(),
+                    unrefunded,
+                )
+            };
+            if amount > 0 {
+                lock.refunded[index] += amount;
+                account.internal_token_deposit(&in_token.token_account_id, amount);
+            }
+        }
+        self.internal_remove_subscription(account, account_id, sale_id, subscription);
+        self.internal_save_sale(sale_id, sale);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Releases the given part of the locked proceeds ahead of the vesting schedule. Can only be
+    /// called by the arbiter of the sale.
+    #[payable]
+    pub fn sale_approve_milestone(&mut self, sale_id: u64, released_bpt: BasicPoints) {
+        assert_one_yocto();
+        assert!(!self.pause_flags.claims, "{}", errors::CLAIMS_PAUSED);
+        let mut sale = self.internal_unwrap_sale(sale_id);
+        let lock = sale.proceeds_lock.as_mut().expect(errors::NO_PROCEEDS_LOCK);
+        assert_eq!(
+            lock.arbiter_id.as_ref(),
+            Some(&env::predecessor_account_id()),
+            "{}",
+            errors::NOT_ARBITER
+        );
+        assert!(!lock.clawed_back, "{}", errors::PROCEEDS_CLAWED_BACK);
+        assert!(
+            released_bpt > lock.released_bpt && released_bpt as u128 <= PROCEEDS_BPT_DENOMINATOR,
+            "{}",
+            errors::INVALID_MILESTONE
+        );
+        lock.released_bpt = released_bpt;
+        self.internal_distribute_unclaimed_tokens(&mut sale);
+        self.internal_save_sale(sale_id, sale);
+    }
+
+    /// Claws back the proceeds that weren't released yet, including the future proceeds of the
+    /// sale. Subscribers claim their share by the amounts they spent once the sale ends, and return
+    /// the same part of the out tokens they bought. Fails once all proceeds are released.
+    #[payable]
+    pub fn sale_clawback_proceeds(&mut self, sale_id: u64) {
+        assert_one_yocto();
+        self.assert_called_by_dao();
+        let mut sale = self.internal_unwrap_sale(sale_id);
+        self.internal_distribute_unclaimed_tokens(&mut sale);
+        assert!(
+            sale.is_proceeds_lock_active(),
+            "{}",
+            errors::PROCEEDS_RELEASED
+        );
+        let lock = sale.proceeds_lock.as_mut().expect(errors::NO_PROCEEDS_LOCK);
+        assert!(!lock.clawed_back, "{}", errors::PROCEEDS_CLAWED_BACK);
+        lock.clawed_back = true;
+        self.internal_save_sale(sale_id, sale);
+    }
+
+    #[payable]
+    pub fn sale_claim_clawback(&mut self, sale_id: u64) {
+        assert_one_yocto();
+        assert!(!self.pause_flags.claims, "{}", errors::CLAIMS_PAUSED);
+        let sale = self.internal_unwrap_sale(sale_id);
+        assert!(sale.is_refunded(), "{}", errors::PROCEEDS_NOT_CLAWED_BACK);
+        assert!(sale.has_ended(), "{}", errors::SALE_NOT_ENDED);
+        let account_id = env::predecessor_account_id();
+        let initial_storage_usage = env::storage_usage();
+        let mut account = self.internal_unwrap_account(&account_id);
+        assert!(
+            account.subs.get(&sale_id).is_some(),
+            "{}",
+            errors::NOTHING_TO_WITHDRAW
+        );
+        self.internal_claim_clawback(&mut account, &account_id, sale_id);
+        self.accounts.insert(&account_id, &account.into());
+        refund_released_storage(
+            &account_id,
+            initial_storage_usage.saturating_sub(env::storage_usage()),
+        );
+    }
+
+    /// Votes for the refund of the locked proceeds with the claimed first out token of the
+    /// subscription before the referral fees. The out tokens are claimed first. Once the votes
+    /// reach the quorum of the out tokens sold to the subscribers after the treasury fee, the
+    /// proceeds are clawed back to the subscribers.
+    #[payable]
+    pub fn sale_vote_refund(&mut self, sale_id: u64) {
+        assert_at_least_one_yocto();
+        assert!(!self.pause_flags.claims, "{}", errors::CLAIMS_PAUSED);
+        let account_id = env::predecessor_account_id();
+        let mut sale = self.internal_unwrap_sale(sale_id);
+        assert!(sale.has_ended(), "{}", errors::SALE_NOT_ENDED);
+        self.internal_distribute_unclaimed_tokens(&mut sale);
+        let lock = sale.proceeds_lock.as_ref().expect(errors::NO_PROCEEDS_LOCK);
+        let refund_quorum_bpt = lock.refund_quorum_bpt.expect(errors::NO_REFUND_VOTE);
+        assert!(!lock.clawed_back, "{}", errors::PROCEEDS_CLAWED_BACK);
+        assert!(
+            sale.is_proceeds_lock_active(),
+            "{}",
+            errors::PROCEEDS_RELEASED
+        );
+        let vote_key = (sale_id, account_id.clone());
+        assert!(
+            self.refund_votes.get(&vote_key).is_none(),
+            "{}",
+            errors::ALREADY_VOTED
+        );
+
+        let mut account = self.internal_unwrap_account(&account_id);
+        assert!(
+            account.subs.get(&sale_id).is_some(),
+            "{}",
+            errors::NO_VOTING_POWER
+        );
+        let subscription =
+            self.internal_update_subscription(&mut account, sale_id, &mut sale, None, false);
+        let weight = subscription.gross_claimed_out_balance[0];
+        assert!(weight > 0, "{}", errors::NO_VOTING_POWER);
+        self.internal_save_subscription(
+            &mut account,
+            &account_id,
+            sale_id,
+            &mut sale,
+            subscription,
+        );
+        self.accounts.insert(&account_id, &account.into());
+        // The voter only pays for the vote. Referral records created by the claim are paid by
+        // the contract.
+        let initial_storage_usage = env::storage_usage();
+        self.refund_votes.insert(&vote_key, &weight);
+        refund_extra_storage_deposit(env::storage_usage() - initial_storage_usage, 0);
+
+        let sold = sale.out_tokens[0].sold();
+        let quorum = U256::from(sold - sold / TREASURY_FEE_DENOMINATOR)
+            * U256::from(refund_quorum_bpt)
+            / U256::from(PROCEEDS_BPT_DENOMINATOR);
+        let lock = sale.proceeds_lock.as_mut().unwrap();
+        lock.refund_votes += weight;
+        if U256::from(lock.refund_votes) >= quorum {
+            log!("Refund vote of sale {} passed", sale_id);
+            lock.clawed_back = true;
+        }
+        self.internal_save_sale(sale_id, sale);
+    }
+
+    pub fn get_refund_vote(&self, sale_id: u64, account_id: AccountId) -> Option<U128> {
+        self.refund_votes
+            .get(&(sale_id, account_id))
+            .map(|weight| weight.into())
+    }
+
+    pub fn get_sale_proceeds(&self, sale_id: u64) -> Option<ProceedsLockOutput> {
+        self.sales.get(&sale_id).and_then(|v_sale| {
+            let sale: Sale = v_sale.into();
+            sale.proceeds_lock_output()
+        })
+    }
+}
@@ -0,0 +1,580 @@
+use crate::{
+    errors, Account, Contract, ContractExt, ReferralAttribution, ReferralPayout, StorageKey,
+    Subscription,
+};
+use near_sdk::{
+    assert_one_yocto,
+    borsh::{BorshDeserialize, BorshSerialize},
+    collections::{LookupMap, UnorderedMap, UnorderedSet},
+    env,
+    json_types::{U128, U64},
+    near_bindgen,
+    serde::{Deserialize, Serialize},
+    AccountId, Duration, Timestamp,
+};
+
+pub(crate) const MAX_NUM_REFERRERS: usize = 4;
+/// The number of top referrers kept for every sale and token.
+pub(crate) const MAX_LEADERBOARD_SIZE: usize = 50;
+pub(crate) const DEFAULT_REFERRAL_ESCROW_DURATION: Duration = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Subscriptions that named the referrer, as `(sale_id, account_id)` pairs.
+pub type Referrals = LookupMap<AccountId, UnorderedSet<(u64, AccountId)>>;
+
+#[derive(BorshSerialize, BorshDeserialize, Default)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct ReferralEarning {
+    pub earned: u128,
+    /// Sent to the treasury because the referrer didn't have the token registered.
+    pub lost: u128,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct ReferralStats {
+    /// Referral fees by referrer, keyed by `(sale_id, token_account_id)`.
+    pub earnings: LookupMap<AccountId, UnorderedMap<(u64, AccountId), ReferralEarning>>,
+    /// The referrers with the most fees earned, keyed by `(sale_id, token_account_id)` and
+    /// ordered by the earned amount.
+    pub leaderboards: LookupMap<(u64, AccountId), Vec<(AccountId, u128)>>,
+    /// Referral fees sent to the treasury by token.
+    pub lost: UnorderedMap<AccountId, u128>,
+}
+
+/// Referral fees of a referrer that didn't have the token registered.
+#[derive(BorshSerialize, BorshDeserialize)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct EscrowedReferralReward {
+    pub amount: u128,
+    /// Extended with every new reward. The reward can be swept to the treasury afterwards.
+    pub expires_at: Timestamp,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct EscrowedReferralRewardOutput {
+    pub token_account_id: AccountId,
+    pub amount: U128,
+    pub expires_at: U64,
+}
+
+impl ReferralStats {
+    pub fn new() -> Self {
+        Self {
+            earnings: LookupMap::new(StorageKey::ReferralEarnings),
+            leaderboards: LookupMap::new(StorageKey::ReferralLeaderboards),
+            lost: UnorderedMap::new(StorageKey::ReferralLost),
+        }
+    }
+
+    pub fn internal_record_lost(&mut self, token_account_id: &AccountId, amount: u128) {
+        let lost = self.lost.get(token_account_id).unwrap_or(0);
+        self.lost.insert(token_account_id, &(lost + amount));
+    }
+
+    pub fn internal_record(
+        &mut self,
+        referral_id: &AccountId,
+        sale_id: u64,
+        token_account_id: &AccountId,
+        earned: u128,
+        lost: u128,
+    ) {
+        let mut earnings = self.earnings.get(referral_id).unwrap_or_else(|| {
+            UnorderedMap::new(StorageKey::AccountReferralEarnings {
+                account_id: referral_id.clone(),
+            })
+        });
+        let key = (sale_id, token_account_id.clone());
+        let mut earning = earnings.get(&key).unwrap_or_default();
+        earning.earned += earned;
+        earning.lost += lost;
+        earnings.insert(&key, &earning);
+        self.earnings.insert(referral_id, &earnings);
+
+        // Earnings only grow, so only the recorded referrer can enter the leaderboard.
+        let mut leaderboard = self.leaderboards.get(&key).unwrap_or_default();
+        if let Some(entry) = leaderboard
+            .iter_mut()
+            .find(|(account_id, _)| account_id == referral_id)
+        {
+            entry.1 = earning.earned;
+        } else if leaderboard.len() < MAX_LEADERBOARD_SIZE {
+            leaderboard.push((referral_id.clone(), earning.earned));
+        } else if leaderboard.last().unwrap().1 < earning.earned {
+            *leaderboard.last_mut().unwrap() = (referral_id.clone(), earning.earned);
+        } else {
+            return;
+        }
+        leaderboard.sort_by_key(|(_, earned)| std::cmp::Reverse(*earned));
+        self.leaderboards.insert(&key, &leaderboard);
+    }
+
+    pub fn internal_get(
+        &self,
+        referral_id: &AccountId,
+        sale_id: u64,
+        token_account_id: &AccountId,
+    ) -> ReferralEarning {
+        self.earnings
+            .get(referral_id)
+            .and_then(|earnings| earnings.get(&(sale_id, token_account_id.clone())))
+            .unwrap_or_default()
+    }
+}
+
+impl Default for ReferralStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct ReferralEarningOutput {
+    pub sale_id: u64,
+    pub token_account_id: AccountId,
+    pub earned: U128,
+    pub lost: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct ReferralStatsOutput {
+    /// The number of subscriptions that currently name the account as the referrer.
+    pub num_referrals: u64,
+    pub earnings: Vec<ReferralEarningOutput>,
+    pub pending_earnings: Vec<(AccountId, U128)>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct ReferralLeaderboardEntry {
+    pub account_id: AccountId,
+    pub earned: U128,
+    pub lost: U128,
+}
+
+impl Contract {
+    pub fn internal_add_referral(
+        &mut self,
+        referral_id: &AccountId,
+        sale_id: u64,
+        account_id: &AccountId,
+    ) {
+        let mut referrals = self.referrals.get(referral_id).unwrap_or_else(|| {
+            UnorderedSet::new(StorageKey::AccountReferrals {
+                account_id: referral_id.clone(),
+            })
+        });
+        referrals.insert(&(sale_id, account_id.clone()));
+        self.referrals.insert(referral_id, &referrals);
+        // Referral cycles of two accounts would pay the subscriber as the second level referrer.
+        if self.referrers.get(account_id).is_none()
+            && self.referrers.get(referral_id).as_ref() != Some(account_id)
+        {
+            self.referrers.insert(account_id, referral_id);
+        }
+    }
+
+    /// Attributes a deposit that named the referrer according to the attribution of the sale.
+    pub fn internal_attribute_referral(
+        &mut self,
+        sale_id: u64,
+        account_id: &AccountId,
+        referral_attribution: ReferralAttribution,
+        subscription: &mut Subscription,
+        referral_id: &AccountId,
+        in_amount: u128,
+    ) {
+        match referral_attribution {
+            ReferralAttribution::FirstReferrer => {
+                if subscription.referral_id.is_none() {
+                    subscription.referral_id = Some(referral_id.clone());
+                }
+            }
+            ReferralAttribution::LastReferrer => {
+                if let Some(previous_referral_id) =
+                    subscription.referral_id.replace(referral_id.clone())
+                {
+                    if &previous_referral_id != referral_id {
+                        self.internal_remove_referral(&previous_referral_id, sale_id, account_id);
+                    }
+                }
+            }
+            ReferralAttribution::AmountWeighted => {
+                if subscription.referral_id.is_none() {
+                    subscription.referral_id = Some(referral_id.clone());
+                }
+                let num_referrers = subscription.referral_weights.len();
+                match subscription
+                    .referral_weights
+                    .iter_mut()
+                    .find(|(weighted_referral_id, _)| weighted_referral_id == referral_id)
+                {
+                    Some((_, weight)) => *weight += in_amount,
+                    // Deposits with new referrers beyond the limit are not attributed.
+                    None if num_referrers >= MAX_NUM_REFERRERS => return,
+                    None => subscription
+                        .referral_weights
+                        .push((referral_id.clone(), in_amount)),
+                }
+            }
+        }
+        if subscription.referral_id.as_ref() == Some(referral_id)
+            || subscription
+                .referral_weights
+                .iter()
+                .any(|(weighted_referral_id, _)| weighted_referral_id == referral_id)
+        {
+            self.internal_add_referral(referral_id, sale_id, account_id);
+        }
+    }
+
+    pub fn internal_second_level_referrer(&self, referral_id: &AccountId) -> Option<AccountId> {
+        self.referrers.get(referral_id)
+    }
+
+    /// Deposits the referral fee to the referrer. If the referrer doesn't have the token registered,
+    /// the fee is escrowed, or sent to the treasury when escrow is disabled.
+    /// The storage of the stats and escrow entries is paid by the depositor when a deposit pays the
+    /// fee, and by the contract when a claim or a withdrawal pays it.
+    pub fn internal_pay_referral(
+        &mut self,
+        referral_id: &AccountId,
+        sale_id: u64,
+        token_account_id: &AccountId,
+        amount: u128,
+    ) {
+        if amount == 0 {
+            return;
+        }
+        if let Some(referral) = self.accounts.get(referral_id) {
+            let mut referral: Account = referral.into();
+            if referral.balances.get(token_account_id).is_some() {
+                referral.internal_token_deposit(token_account_id, amount);
+                self.accounts.insert(referral_id, &referral.into());
+                self.referral_stats.internal_record(
+                    referral_id,
+                    sale_id,
+                    token_account_id,
+                    amount,
+                    0,
+                );
+                return;
+            }
+        }
+        if self.referral_escrow_duration > 0 {
+            self.internal_escrow_referral_reward(referral_id, token_account_id, amount);
+            self.referral_stats
+                .internal_record(referral_id, sale_id, token_account_id, amount, 0);
+            return;
+        }
+        self.treasury.internal_deposit(token_account_id, amount);
+        self.referral_stats
+            .internal_record(referral_id, sale_id, token_account_id, 0, amount);
+        self.referral_stats
+            .internal_record_lost(token_account_id, amount);
+    }
+
+    fn internal_escrow_referral_reward(
+        &mut self,
+        referral_id: &AccountId,
+        token_account_id: &AccountId,
+        amount: u128,
+    ) {
+        let mut rewards = self.referral_escrow.get(referral_id).unwrap_or_else(|| {
+            UnorderedMap::new(StorageKey::AccountReferralEscrow {
+                account_id: referral_id.clone(),
+            })
+        });
+        let previous_amount = rewards
+            .get(token_account_id)
+            .map(|reward| reward.amount)
+            .unwrap_or(0);
+        rewards.insert(
+            token_account_id,
+            &EscrowedReferralReward {
+                amount: previous_amount + amount,
+                expires_at: env::block_timestamp() + self.referral_escrow_duration,
+            },
+        );
+        self.referral_escrow.insert(referral_id, &rewards);
+    }
+
+    /// Removes the escrowed reward and returns it.
+    fn internal_remove_escrowed_referral_reward(
+        &mut self,
+        referral_id: &AccountId,
+        token_account_id: &AccountId,
+    ) -> EscrowedReferralReward {
+        let mut rewards = self
+            .referral_escrow
+            .get(referral_id)
+            .expect(errors::NO_ESCROWED_REFERRAL_REWARD);
+        let reward = rewards
+            .remove(token_account_id)
+            .expect(errors::NO_ESCROWED_REFERRAL_REWARD);
+        if rewards.is_empty() {
+            self.referral_escrow.remove(referral_id);
+        } else {
+            self.referral_escrow.insert(referral_id, &rewards);
+        }
+        reward
+    }
+
+    pub fn internal_remove_referral(
+        &mut self,
+        referral_id: &AccountId,
+        sale_id: u64,
+        account_id: &AccountId,
+    ) {
+        if let Some(mut referrals) = self.referrals.get(referral_id) {
+            referrals.remove(&(sale_id, account_id.clone()));
+            if referrals.is_empty() {
+                self.referrals.remove(referral_id);
+            } else {
+                self.referrals.insert(referral_id, &referrals);
+            }
+        }
+    }
+
+    /// Returns the referral fees that the given page of the referred subscribers haven't claimed
+    /// yet.
+    pub fn internal_pending_referral_earnings(
+        &self,
+        referral_id: &AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<(AccountId, u128)> {
+        let mut earnings: Vec<(AccountId, u128)> = vec![];
+        let referrals = match self.referrals.get(referral_id) {
+            Some(referrals) => referrals,
+            None => return earnings,
+        };
+        let referrals = referrals.as_vector();
+        let mut add_earning = |token_account_id: &AccountId, amount: u128| {
+            if amount == 0 {
+                return;
+            }
+            match earnings
+                .iter_mut()
+                .find(|(earned_token_account_id, _)| earned_token_account_id == token_account_id)
+            {
+                Some((_, earned)) => *earned += amount,
+                None => earnings.push((token_account_id.clone(), amount)),
+            }
+        };
+        let from_index = from_index.unwrap_or(0);
+        let to_index = std::cmp::min(
+            from_index + limit.unwrap_or(referrals.len()),
+            referrals.len(),
+        );
+        for (sale_id, account_id) in
+            (from_index..to_index).map(|index| referrals.get(index).unwrap())
+        {
+            let account: Account = match self.accounts.get(&account_id) {
+                Some(account) => account.into(),
+                None => continue,
+            };
+            let mut subscription: Subscription = match account.subs.get(&sale_id) {
+                Some(subscription) => subscription.into(),
+                None => continue,
+            };
+            let sale = self.internal_unwrap_sale(sale_id);
+            let spent_in_balances = subscription.spent_in_balances.clone();
+            let out_token_amounts = subscription.touch(&sale);
+            let referral_amount = |amount: u128| {
+                subscription
+                    .split_by_referrers(amount)
+                    .into_iter()
+                    .find(|(subscription_referral_id, _)| subscription_referral_id == referral_id)
+                    .map(|(_, amount)| amount)
+                    .unwrap_or(0)
+            };
+            for (amount, out_token) in out_token_amounts.into_iter().zip(sale.out_tokens.iter()) {
+                if out_token.referral_payout == ReferralPayout::OutToken {
+                    add_earning(
+                        &out_token.token_account_id,
+                        out_token
+                            .referral_fees(referral_amount(amount), true, false)
+                            .referrer,
+                    );
+                }
+            }
+            for (i, in_token) in sale.in_tokens.iter().enumerate() {
+                let spent_amount = spent_in_balances
+                    .get(i)
+                    .map(|spent| subscription.spent_in_balances[i] - spent)
+                    .unwrap_or(0);
+                let amount = sale
+                    .out_tokens
+                    .iter()
+                    .filter(|out_token| out_token.referral_payout == ReferralPayout::InToken)
+                    .map(|out_token| {
+                        out_token
+                            .referral_fees(referral_amount(spent_amount), true, false)
+                            .referrer
+                    })
+                    .sum();
+                add_earning(&in_token.token_account_id, amount);
+            }
+        }
+        earnings
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Claims the escrowed referral rewards of the given tokens. The tokens have to be registered
+    /// first.
+    pub fn claim_referral_rewards(&mut self, token_account_ids: Vec<AccountId>) {
+        assert!(!self.pause_flags.claims, "{}", errors::CLAIMS_PAUSED);
+        let account_id = env::predecessor_account_id();
+        let mut account = self.internal_unwrap_account(&account_id);
+        for token_account_id in token_account_ids {
+            let reward =
+                self.internal_remove_escrowed_referral_reward(&account_id, &token_account_id);
+            assert!(
+                reward.expires_at > env::block_timestamp(),
+                "{}",
+                errors::REFERRAL_ESCROW_EXPIRED
+            );
+            account.internal_token_deposit(&token_account_id, reward.amount);
+        }
+        self.accounts.insert(&account_id, &account.into());
+    }
+
+    /// Sends an expired escrowed referral reward to the treasury.
+    pub fn sweep_referral_rewards(&mut self, referral_id: AccountId, token_account_id: AccountId) {
+        assert!(!self.pause_flags.claims, "{}", errors::CLAIMS_PAUSED);
+        let reward = self.internal_remove_escrowed_referral_reward(&referral_id, &token_account_id);
+        assert!(
+            reward.expires_at <= env::block_timestamp(),
+            "{}",
+            errors::REFERRAL_ESCROW_NOT_EXPIRED
+        );
+        self.treasury
+            .internal_deposit(&token_account_id, reward.amount);
+        self.referral_stats
+            .internal_record_lost(&token_account_id, reward.amount);
+    }
+
+    #[payable]
+    pub fn set_referral_escrow_duration(&mut self, duration: U64) {
+        assert_one_yocto();
+        self.assert_called_by_dao();
+        self.referral_escrow_duration = duration.0;
+    }
+
+    pub fn get_referral_escrow_duration(&self) -> U64 {
+        self.referral_escrow_duration.into()
+    }
+
+    pub fn get_escrowed_referral_rewards(
+        &self,
+        account_id: AccountId,
+    ) -> Vec<EscrowedReferralRewardOutput> {
+        self.referral_escrow
+            .get(&account_id)
+            .map(|rewards| {
+                rewards
+                    .iter()
+                    .map(|(token_account_id, reward)| EscrowedReferralRewardOutput {
+                        token_account_id,
+                        amount: reward.amount.into(),
+                        expires_at: reward.expires_at.into(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the referral fees sent to the treasury by token.
+    pub fn get_referral_lost_amounts(&self) -> Vec<(AccountId, U128)> {
+        self.referral_stats
+            .lost
+            .iter()
+            .map(|(token_account_id, amount)| (token_account_id, amount.into()))
+            .collect()
+    }
+
+    /// The `from_index` and `limit` are applied to the earnings and to the referred subscriptions
+    /// that the pending earnings are summed from separately.
+    pub fn get_referral_stats(
+        &self,
+        account_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> ReferralStatsOutput {
+        ReferralStatsOutput {
+            num_referrals: self
+                .referrals
+                .get(&account_id)
+                .map(|referrals| referrals.len())
+                .unwrap_or(0),
+            earnings: self
+                .referral_stats
+                .earnings
+                .get(&account_id)
+                .map(|earnings| {
+                    let keys = earnings.keys_as_vector();
+                    let values = earnings.values_as_vector();
+                    let from_index = from_index.unwrap_or(0);
+                    (from_index
+                        ..std::cmp::min(from_index + limit.unwrap_or(keys.len()), keys.len()))
+                        .map(|index| {
+                            let (sale_id, token_account_id) = keys.get(index).unwrap();
+                            let earning = values.get(index).unwrap();
+                            ReferralEarningOutput {
+                                sale_id,
+                                token_account_id,
+                                earned: earning.earned.into(),
+                                lost: earning.lost.into(),
+                            }
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            pending_earnings: self
+                .internal_pending_referral_earnings(&account_id, from_index, limit)
+                .into_iter()
+                .map(|(token_account_id, amount)| (token_account_id, amount.into()))
+                .collect(),
+        }
+    }
+
+    /// Returns the referrers of the sale with the most referral fees earned in the given token,
+    /// up to the top 50.
+    pub fn get_sale_referral_leaderboard(
+        &self,
+        sale_id: u64,
+        token_account_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<ReferralLeaderboardEntry> {
+        self.referral_stats
+            .leaderboards
+            .get(&(sale_id, token_account_id.clone()))
+            .unwrap_or_default()
+            .into_iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.map(|limit| limit as usize).unwrap_or(usize::MAX))
+            .map(|(account_id, earned)| {
+                let lost = self
+                    .referral_stats
+                    .internal_get(&account_id, sale_id, &token_account_id)
+                    .lost;
+                ReferralLeaderboardEntry {
+                    account_id,
+                    earned: earned.into(),
+                    lost: lost.into(),
+                }
+            })
+            .collect()
+    }
+}
@@ -1,16 +1,23 @@
 use crate::{
     assert_at_least_one_yocto, errors, ext_permission_contract, refund_extra_storage_deposit,
-    refund_released_storage, Account, BasicPoints, Contract, ContractExt, SubscriptionOutput,
-    AFTER_IS_APPROVED_GAS, MAYBE_REFUND_DEPOSIT_GAS, PERMISSION_CONTRACT_GAS,
+    refund_released_storage, Account, BasicPoints, Contract, ContractExt, ListingFee, ProceedsLock,
+    ProceedsLockInput, ProceedsLockOutput, SaleCheckpoint, SaleHook, SaleInputLiquidity,
+    SaleLiquidity, SaleLiquidityOutput, SaleStatus, SubscriptionOutput, AFTER_IS_APPROVED_GAS,
+    AFTER_SALE_CREATE_GAS, CLAIM_OUT_TOKENS_GAS, FT_METADATA_GAS, MAX_SALE_HISTORY_LENGTH,
+    MAYBE_REFUND_DEPOSIT_GAS, PERMISSION_CONTRACT_GAS, PROCEEDS_BPT_DENOMINATOR,
+    REFERRAL_FEE_DENOMINATOR, STORAGE_BALANCE_OF_GAS, WITHDRAW_TOKEN_GAS,
 };
+use near_contract_standards::fungible_token::metadata::{ext_ft_metadata, FungibleTokenMetadata};
+use near_contract_standards::storage_management::{ext_storage_management, StorageBalance};
 use near_sdk::{
     assert_one_yocto,
     borsh::{BorshDeserialize, BorshSerialize},
     env,
     json_types::{U128, U64},
-    near_bindgen,
+    log, near_bindgen,
     serde::{Deserialize, Serialize},
-    AccountId, BlockHeight, Duration, Timestamp,
+    serde_json, AccountId, BlockHeight, Duration, Gas, NearToken, Promise, PromiseResult,
+    StorageUsage, Timestamp,
 };
 use primitive_types::U256;
 
@@ -26,9 +33,12 @@ const MIN_DURATION: Duration = 1;
 pub(crate) const MULTIPLIER: u128 = 10u128.pow(38);
 pub(crate) const TREASURY_FEE_DENOMINATOR: u128 = 100;
 pub(crate) const MAX_NUM_OUT_TOKENS: usize = 4;
+pub(crate) const MAX_NUM_IN_TOKENS: usize = 4;
 pub(crate) const MAX_TITLE_LENGTH: usize = 250;
 pub(crate) const MAX_URL_LENGTH: usize = 250;
 pub(crate) const MAX_REFERRAL_BPT: u16 = 500;
+pub(crate) const MAX_EARLY_EXIT_PENALTY_BPT: u16 = 5000;
+pub(crate) const EARLY_EXIT_PENALTY_DENOMINATOR: u128 = 10000;
 
 #[derive(BorshSerialize, BorshDeserialize)]
 #[borsh(crate = "near_sdk::borsh")]
@@ -39,7 +49,7 @@ pub struct OldSale {
     pub url: Option<String>,
     pub permissions_contract_id: Option<AccountId>,
 
-    pub out_tokens: Vec<SaleOutToken>,
+    pub out_tokens: Vec<OldSaleOutToken>,
 
     pub in_token_account_id: AccountId,
     pub in_token_remaining: u128,
@@ -53,22 +63,79 @@ pub struct OldSale {
     pub last_timestamp: Timestamp,
 }
 
+#[derive(BorshSerialize, BorshDeserialize)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct SaleV2 {
+    pub owner_id: AccountId,
+
+    pub title: String,
+    pub url: Option<String>,
+    pub permissions_contract_id: Option<AccountId>,
+
+    pub out_tokens: Vec<OldSaleOutToken>,
+
+    pub in_token_account_id: AccountId,
+    pub in_token_remaining: u128,
+    pub in_token_paid_unclaimed: u128,
+    pub in_token_paid: u128,
+
+    pub start_time: Timestamp,
+    pub duration: Duration,
+
+    pub total_shares: u128,
+    pub last_timestamp: Timestamp,
+
+    pub start_block_height: BlockHeight,
+    pub end_block_height: Option<BlockHeight>,
+}
+
 #[derive(BorshSerialize, BorshDeserialize)]
 #[borsh(crate = "near_sdk::borsh", init = touch)]
 pub struct Sale {
     pub owner_id: AccountId,
+    /// The account the ownership is being transferred to.
+    pub pending_owner_id: Option<AccountId>,
+    /// Receives the in tokens paid for the out tokens and the unsold out tokens.
+    pub proceeds_receiver_id: AccountId,
 
     pub title: String,
     pub url: Option<String>,
     pub permissions_contract_id: Option<AccountId>,
+    /// Receives calls when the sale ends and when the proceeds are distributed.
+    pub hooks_contract_id: Option<AccountId>,
 
     pub out_tokens: Vec<SaleOutToken>,
 
-    pub in_token_account_id: AccountId,
+    pub in_tokens: Vec<SaleInToken>,
+    /// The remaining in tokens in normalized units, which is the sum of the remaining amount of
+    /// every in token multiplied by its weight, rounded down.
     pub in_token_remaining: u128,
     pub in_token_paid_unclaimed: u128,
     pub in_token_paid: u128,
 
+    /// The minimum normalized in amount of a single deposit.
+    pub min_deposit: Option<u128>,
+    /// The maximum normalized in amount a single account can deposit in total, net of
+    /// withdrawals.
+    pub max_account_in_amount: Option<u128>,
+
+    /// Withdrawals are not allowed during this duration before the end of the sale.
+    pub withdrawal_lock_duration: Option<Duration>,
+    pub early_exit_penalty: Option<EarlyExitPenalty>,
+
+    /// How referral fees are attributed when deposits name different referrers.
+    pub referral_attribution: ReferralAttribution,
+
+    /// Seeds an AMM pool with a part of the proceeds when the sale ends.
+    pub liquidity: Option<SaleLiquidity>,
+    /// Locks the proceeds instead of crediting them to the proceeds receiver.
+    pub proceeds_lock: Option<ProceedsLock>,
+
+    pub paused_at: Option<Timestamp>,
+
+    /// Whether the sale is curated by the DAO.
+    pub verified: bool,
+
     pub start_time: Timestamp,
     pub duration: Duration,
 
@@ -77,6 +144,27 @@ pub struct Sale {
 
     pub start_block_height: BlockHeight,
     pub end_block_height: Option<BlockHeight>,
+
+    /// The minimum duration between checkpoints of the sale history.
+    pub history_interval: Duration,
+    pub last_checkpoint_time: Timestamp,
+    /// Recorded by `touch` and moved to the sale history when the sale is saved.
+    #[borsh(skip)]
+    pub pending_checkpoint: Option<Box<SaleCheckpoint>>,
+    /// Hooks that are called when the sale is saved.
+    #[borsh(skip)]
+    pub pending_hooks: Vec<SaleHook>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct OldSaleOutToken {
+    pub token_account_id: AccountId,
+    pub remaining: u128,
+    pub distributed: u128,
+    pub treasury_unclaimed: u128,
+    pub per_share: [u64; 4],
+    pub referral_bpt: Option<BasicPoints>,
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Clone)]
@@ -85,15 +173,161 @@ pub struct SaleOutToken {
     pub token_account_id: AccountId,
     pub remaining: u128,
     pub distributed: u128,
+    /// The part of `distributed` returned to the proceeds receiver because no one subscribed at
+    /// the end of the sale.
+    pub returned: u128,
     pub treasury_unclaimed: u128,
     pub per_share: [u64; 4],
     pub referral_bpt: Option<BasicPoints>,
+    /// The part of `referral_bpt` paid to the referrer. The rest is kept by the subscriber.
+    /// Defaults to half of `referral_bpt`.
+    pub referrer_bpt: Option<BasicPoints>,
+    /// The part of `referral_bpt` paid to the account that referred the referrer.
+    pub second_level_referrer_bpt: Option<BasicPoints>,
+    pub referral_payout: ReferralPayout,
+    pub metadata: Option<TokenMetadata>,
+}
+
+impl From<OldSaleOutToken> for SaleOutToken {
+    fn from(token: OldSaleOutToken) -> Self {
+        Self {
+            token_account_id: token.token_account_id,
+            remaining: token.remaining,
+            distributed: token.distributed,
+            returned: 0,
+            treasury_unclaimed: token.treasury_unclaimed,
+            per_share: token.per_share,
+            referral_bpt: token.referral_bpt,
+            referrer_bpt: None,
+            second_level_referrer_bpt: None,
+            referral_payout: ReferralPayout::OutToken,
+            metadata: None,
+        }
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[borsh(crate = "near_sdk::borsh")]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum ReferralPayout {
+    /// Referral fees are deducted from the out tokens claimed by the subscriber.
+    OutToken,
+    /// Referral fees are paid in the in tokens spent by the subscriber out of the sale proceeds.
+    /// The subscriber receives all of the out tokens.
+    InToken,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[borsh(crate = "near_sdk::borsh")]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum ReferralAttribution {
+    /// The referrer of the first deposit earns all referral fees.
+    FirstReferrer,
+    /// The referrer of the latest deposit that named one earns all future referral fees.
+    LastReferrer,
+    /// Referral fees are split between the referrers by the in amounts deposited with them.
+    AmountWeighted,
+}
+
+/// Referral fees of a claimed amount.
+#[derive(Default)]
+pub struct ReferralFees {
+    /// Goes to the proceeds receiver when the subscription doesn't have a referrer.
+    pub owner: u128,
+    pub referrer: u128,
+    pub second_level_referrer: u128,
+}
+
+impl ReferralFees {
+    pub fn total(&self) -> u128 {
+        self.owner + self.referrer + self.second_level_referrer
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct SaleInToken {
+    pub token_account_id: AccountId,
+    pub weight: InTokenWeight,
+    pub remaining: u128,
+    /// The shares bought with this token. The remaining amount of the token belongs to them, so
+    /// withdrawals return the tokens that were deposited.
+    pub shares: u128,
+    pub paid_unclaimed: u128,
+    pub paid: u128,
+    pub paid_per_share: [u64; 4],
+    /// Early exit penalties distributed to the remaining subscribers.
+    pub penalty_per_share: [u64; 4],
+    /// Proceeds reserved for the referral fees paid in this token.
+    pub referral_reserve: u128,
+    pub metadata: Option<TokenMetadata>,
+}
+
+/// The number of normalized units one in token is worth, as a ratio. For example, wNEAR with the
+/// weight 1/1 and stNEAR with the weight 6/5 convert at 1:1.2.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Copy)]
+#[borsh(crate = "near_sdk::borsh")]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct InTokenWeight {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl InTokenWeight {
+    pub const ONE: Self = Self {
+        numerator: 1,
+        denominator: 1,
+    };
+}
+
+/// Token metadata cached when the sale is created.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[borsh(crate = "near_sdk::borsh")]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct TokenMetadata {
+    pub symbol: String,
+    pub decimals: u8,
+    pub icon: Option<String>,
+}
+
+impl From<FungibleTokenMetadata> for TokenMetadata {
+    fn from(metadata: FungibleTokenMetadata) -> Self {
+        Self {
+            symbol: metadata.symbol,
+            decimals: metadata.decimals,
+            icon: metadata.icon,
+        }
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[borsh(crate = "near_sdk::borsh")]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum PenaltyReceiver {
+    Treasury,
+    Subscribers,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Copy)]
+#[borsh(crate = "near_sdk::borsh")]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct EarlyExitPenalty {
+    pub penalty_bpt: BasicPoints,
+    pub receiver: PenaltyReceiver,
 }
 
 #[derive(BorshDeserialize, BorshSerialize)]
 #[borsh(crate = "near_sdk::borsh")]
+#[allow(clippy::large_enum_variant)]
 pub enum VSale {
     First(OldSale),
+    Second(SaleV2),
     Current(Sale),
 }
 
@@ -103,27 +337,91 @@ impl From<Sale> for VSale {
     }
 }
 
+impl From<OldSale> for SaleV2 {
+    fn from(old_sale: OldSale) -> Self {
+        Self {
+            owner_id: old_sale.owner_id,
+            title: old_sale.title,
+            url: old_sale.url,
+            permissions_contract_id: old_sale.permissions_contract_id,
+            out_tokens: old_sale.out_tokens,
+            in_token_account_id: old_sale.in_token_account_id,
+            in_token_remaining: old_sale.in_token_remaining,
+            in_token_paid_unclaimed: old_sale.in_token_paid_unclaimed,
+            in_token_paid: old_sale.in_token_paid,
+            start_time: old_sale.start_time,
+            duration: old_sale.duration,
+            total_shares: old_sale.total_shares,
+            last_timestamp: old_sale.last_timestamp,
+            start_block_height: 0,
+            end_block_height: None,
+        }
+    }
+}
+
+impl From<SaleV2> for Sale {
+    fn from(sale: SaleV2) -> Self {
+        Self {
+            proceeds_receiver_id: sale.owner_id.clone(),
+            owner_id: sale.owner_id,
+            pending_owner_id: None,
+            title: sale.title,
+            url: sale.url,
+            permissions_contract_id: sale.permissions_contract_id,
+            hooks_contract_id: None,
+            out_tokens: sale
+                .out_tokens
+                .into_iter()
+                .map(|token| token.into())
+                .collect(),
+            in_tokens: vec![SaleInToken {
+                token_account_id: sale.in_token_account_id,
+                weight: InTokenWeight::ONE,
+                remaining: sale.in_token_remaining,
+                shares: sale.total_shares,
+                paid_unclaimed: sale.in_token_paid_unclaimed,
+                paid: sale.in_token_paid,
+                paid_per_share: U256::zero().0,
+                penalty_per_share: U256::zero().0,
+                referral_reserve: 0,
+                metadata: None,
+            }],
+            in_token_remaining: sale.in_token_remaining,
+            in_token_paid_unclaimed: sale.in_token_paid_unclaimed,
+            in_token_paid: sale.in_token_paid,
+            min_deposit: None,
+            max_account_in_amount: None,
+            withdrawal_lock_duration: None,
+            early_exit_penalty: None,
+            referral_attribution: ReferralAttribution::FirstReferrer,
+            liquidity: None,
+            proceeds_lock: None,
+            paused_at: None,
+            verified: false,
+            start_time: sale.start_time,
+            duration: sale.duration,
+            total_shares: sale.total_shares,
+            last_timestamp: sale.last_timestamp,
+            start_block_height: sale.start_block_height,
+            end_block_height: sale.end_block_height,
+            history_interval: sale.duration / MAX_SALE_HISTORY_LENGTH,
+            last_checkpoint_time: 0,
+            pending_checkpoint: None,
+            pending_hooks: vec![],
+        }
+    }
+}
+
 impl From<VSale> for Sale {
     fn from(v_sale: VSale) -> Self {
         match v_sale {
             VSale::First(old_sale) => {
-                let mut sale = Sale {
-                    owner_id: old_sale.owner_id,
-                    title: old_sale.title,
-                    url: old_sale.url,
-                    permissions_contract_id: old_sale.permissions_contract_id,
-                    out_tokens: old_sale.out_tokens,
-                    in_token_account_id: old_sale.in_token_account_id,
-                    in_token_remaining: old_sale.in_token_remaining,
-                    in_token_paid_unclaimed: old_sale.in_token_paid_unclaimed,
-                    in_token_paid: old_sale.in_token_paid,
-                    start_time: old_sale.start_time,
-                    duration: old_sale.duration,
-                    total_shares: old_sale.total_shares,
-                    last_timestamp: old_sale.last_timestamp,
-                    start_block_height: 0,
-                    end_block_height: None,
-                };
+                let mut sale: Sale = SaleV2::from(old_sale).into();
+                sale.touch();
+                sale
+            }
+            VSale::Second(sale) => {
+                let mut sale: Sale = sale.into();
                 sale.touch();
                 sale
             }
@@ -132,27 +430,104 @@ impl From<VSale> for Sale {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub struct SaleInput {
     pub title: String,
     pub url: Option<String>,
     pub permissions_contract_id: Option<AccountId>,
+    pub hooks_contract_id: Option<AccountId>,
+
+    /// The proposed owner of the sale. The creator owns the sale until the proposed owner accepts
+    /// the ownership with `sale_accept_ownership`. The creator funds the sale either way.
+    pub owner_id: Option<AccountId>,
+    /// Defaults to the proposed owner, or the creator if there is none.
+    pub proceeds_receiver_id: Option<AccountId>,
 
     pub out_tokens: Vec<SaleInputOutToken>,
 
-    pub in_token_account_id: AccountId,
+    pub in_tokens: Vec<SaleInputInToken>,
+
+    /// The token to pay the listing fee in. The NEAR listing fee is charged if the token doesn't
+    /// have a listing fee.
+    pub listing_fee_token_account_id: Option<AccountId>,
+
+    pub min_deposit: Option<U128>,
+    pub max_account_in_amount: Option<U128>,
+
+    pub withdrawal_lock_duration: Option<U64>,
+    pub early_exit_penalty: Option<EarlyExitPenalty>,
+
+    /// Defaults to `FirstReferrer`.
+    pub referral_attribution: Option<ReferralAttribution>,
+
+    /// Pairs the first in token with the first out token.
+    pub liquidity: Option<SaleInputLiquidity>,
+    pub proceeds_lock: Option<ProceedsLockInput>,
+
+    /// The minimum duration between checkpoints of the sale history. Defaults to the duration
+    /// that lets the history cover the whole sale.
+    pub history_interval: Option<U64>,
 
     pub start_time: U64,
     pub duration: U64,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SaleInputInToken {
+    pub token_account_id: AccountId,
+    /// The number of normalized units one in token is worth. The minimum deposit, the
+    /// per-account limit and the prices of the sale are in normalized units.
+    pub weight: InTokenWeight,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub struct SaleInputOutToken {
     pub token_account_id: AccountId,
     pub balance: U128,
     pub referral_bpt: Option<BasicPoints>,
+    pub referrer_bpt: Option<BasicPoints>,
+    pub second_level_referrer_bpt: Option<BasicPoints>,
+    /// Defaults to `OutToken`.
+    pub referral_payout: Option<ReferralPayout>,
+}
+
+impl SaleInToken {
+    pub fn from_input(token: SaleInputInToken) -> Self {
+        Self {
+            token_account_id: token.token_account_id,
+            weight: token.weight,
+            remaining: 0,
+            shares: 0,
+            paid_unclaimed: 0,
+            paid: 0,
+            paid_per_share: U256::zero().0,
+            penalty_per_share: U256::zero().0,
+            referral_reserve: 0,
+            metadata: None,
+        }
+    }
+
+    /// Returns the normalized amount of the given amount of this token, rounded down.
+    pub fn to_normalized(&self, amount: u128) -> u128 {
+        let normalized = U256::from(amount) * U256::from(self.weight.numerator)
+            / U256::from(self.weight.denominator);
+        assert!(
+            normalized <= U256::from(u128::MAX),
+            "{}",
+            errors::BALANCE_OVERFLOW
+        );
+        normalized.as_u128()
+    }
+
+    /// Returns the amount of this token the given normalized amount is worth, rounded down.
+    pub fn from_normalized(&self, amount: u128) -> u128 {
+        (U256::from(amount) * U256::from(self.weight.denominator)
+            / U256::from(self.weight.numerator))
+        .as_u128()
+    }
 }
 
 impl SaleOutToken {
@@ -161,9 +536,54 @@ impl SaleOutToken {
             token_account_id: token.token_account_id,
             remaining: token.balance.into(),
             distributed: 0,
+            returned: 0,
             treasury_unclaimed: 0,
             per_share: U256::zero().0,
             referral_bpt: token.referral_bpt,
+            referrer_bpt: token.referrer_bpt,
+            second_level_referrer_bpt: token.second_level_referrer_bpt,
+            referral_payout: token.referral_payout.unwrap_or(ReferralPayout::OutToken),
+            metadata: None,
+        }
+    }
+
+    /// Returns the amount sold to the subscribers, including the treasury fee.
+    pub fn sold(&self) -> u128 {
+        self.distributed - self.returned
+    }
+
+    /// Splits the referral fee of the given amount. Without a referrer the whole fee goes to the
+    /// owner.
+    pub fn referral_fees(
+        &self,
+        amount: u128,
+        has_referrer: bool,
+        has_second_level_referrer: bool,
+    ) -> ReferralFees {
+        let referral_bpt = match self.referral_bpt {
+            Some(referral_bpt) => referral_bpt,
+            None => return ReferralFees::default(),
+        };
+        let bpt_amount = |bpt: BasicPoints| {
+            (U256::from(amount) * U256::from(bpt) / U256::from(REFERRAL_FEE_DENOMINATOR)).as_u128()
+        };
+        if !has_referrer {
+            return ReferralFees {
+                owner: bpt_amount(referral_bpt),
+                ..Default::default()
+            };
+        }
+        ReferralFees {
+            owner: 0,
+            referrer: self
+                .referrer_bpt
+                .map(bpt_amount)
+                .unwrap_or_else(|| bpt_amount(referral_bpt) / 2),
+            second_level_referrer: if has_second_level_referrer {
+                self.second_level_referrer_bpt.map(bpt_amount).unwrap_or(0)
+            } else {
+                0
+            },
         }
     }
 }
@@ -177,16 +597,37 @@ pub struct SaleOutput {
     pub title: String,
     pub url: Option<String>,
     pub permissions_contract_id: Option<AccountId>,
+    pub hooks_contract_id: Option<AccountId>,
 
     pub owner_id: AccountId,
+    pub pending_owner_id: Option<AccountId>,
+    pub proceeds_receiver_id: AccountId,
 
     pub out_tokens: Vec<SaleOutputOutToken>,
 
-    pub in_token_account_id: AccountId,
+    pub in_tokens: Vec<SaleOutputInToken>,
     pub in_token_remaining: U128,
     pub in_token_paid_unclaimed: U128,
     pub in_token_paid: U128,
 
+    pub min_deposit: Option<U128>,
+    pub max_account_in_amount: Option<U128>,
+
+    pub withdrawal_lock_duration: Option<U64>,
+    pub early_exit_penalty: Option<EarlyExitPenalty>,
+
+    pub referral_attribution: ReferralAttribution,
+
+    pub liquidity: Option<SaleLiquidityOutput>,
+
+    pub proceeds_lock: Option<ProceedsLockOutput>,
+
+    pub history_interval: U64,
+
+    pub paused_at: Option<U64>,
+
+    pub verified: bool,
+
     pub total_shares: U128,
 
     pub start_time: U64,
@@ -210,6 +651,47 @@ pub struct SaleOutputOutToken {
     pub distributed: U128,
     pub treasury_unclaimed: U128,
     pub referral_bpt: Option<BasicPoints>,
+    pub referrer_bpt: Option<BasicPoints>,
+    pub second_level_referrer_bpt: Option<BasicPoints>,
+    pub referral_payout: ReferralPayout,
+    pub metadata: Option<TokenMetadata>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct SaleOutputInToken {
+    pub token_account_id: AccountId,
+    pub weight: InTokenWeight,
+    pub remaining: U128,
+    pub paid_unclaimed: U128,
+    pub paid: U128,
+    pub referral_reserve: U128,
+    pub metadata: Option<TokenMetadata>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct ClaimOutTokensBatchOutput {
+    pub claimed_sale_ids: Vec<u64>,
+    /// Sales that weren't claimed because there wasn't enough gas. Pass them as `sale_ids` to
+    /// continue.
+    pub remaining_sale_ids: Vec<u64>,
+}
+
+impl From<SaleInToken> for SaleOutputInToken {
+    fn from(token: SaleInToken) -> Self {
+        Self {
+            token_account_id: token.token_account_id,
+            weight: token.weight,
+            remaining: token.remaining.into(),
+            paid_unclaimed: token.paid_unclaimed.into(),
+            paid: token.paid.into(),
+            referral_reserve: token.referral_reserve.into(),
+            metadata: token.metadata,
+        }
+    }
 }
 
 impl From<SaleOutToken> for SaleOutputOutToken {
@@ -220,12 +702,19 @@ impl From<SaleOutToken> for SaleOutputOutToken {
             distributed: token.distributed.into(),
             treasury_unclaimed: token.treasury_unclaimed.into(),
             referral_bpt: token.referral_bpt,
+            referrer_bpt: token.referrer_bpt,
+            second_level_referrer_bpt: token.second_level_referrer_bpt,
+            referral_payout: token.referral_payout,
+            metadata: token.metadata,
         }
     }
 }
 
 impl Sale {
     pub fn touch(&mut self) {
+        if self.paused_at.is_some() {
+            return;
+        }
         let end_time = self.start_time + self.duration;
         let timestamp = std::cmp::min(end_time, env::block_timestamp());
         if timestamp <= self.last_timestamp {
@@ -238,9 +727,11 @@ impl Sale {
         }
         if timestamp >= end_time {
             self.end_block_height = Some(env::block_height());
+            self.add_hook(SaleHook::Ended);
         }
         if self.total_shares == 0 {
             self.last_timestamp = timestamp;
+            self.maybe_checkpoint(timestamp);
             return;
         }
         let time_diff = U256::from(timestamp - self.last_timestamp);
@@ -261,13 +752,52 @@ impl Sale {
             }
         }
 
-        let in_token_amount =
-            (U256::from(self.in_token_remaining) * time_diff / remaining_duration).as_u128();
-        self.in_token_paid_unclaimed += in_token_amount;
+        let mut in_token_amount = 0;
+        let mut in_token_paid_unclaimed = 0;
+        for i in 0..self.in_tokens.len() {
+            let amount = (U256::from(self.in_tokens[i].remaining) * time_diff / remaining_duration)
+                .as_u128();
+            if amount > 0 {
+                let referral_reserve = self.in_token_referral_fees(amount, true, true);
+                let in_token = &mut self.in_tokens[i];
+                in_token.referral_reserve += referral_reserve;
+                in_token.paid_unclaimed += amount - referral_reserve;
+                in_token.paid += amount;
+                in_token.remaining -= amount;
+                if in_token.shares > 0 {
+                    in_token.paid_per_share = (U256(in_token.paid_per_share)
+                        + U256::from(amount) * U256::from(MULTIPLIER)
+                            / U256::from(in_token.shares))
+                    .0;
+                }
+                in_token_amount += in_token.to_normalized(amount);
+                in_token_paid_unclaimed += in_token.to_normalized(amount - referral_reserve);
+            }
+        }
+        self.in_token_paid_unclaimed += in_token_paid_unclaimed;
         self.in_token_paid += in_token_amount;
-        self.in_token_remaining -= in_token_amount;
+        self.update_in_token_remaining();
 
         self.last_timestamp = timestamp;
+        self.maybe_checkpoint(timestamp);
+    }
+
+    /// Returns the referral fees paid in the in token for the given spent amount.
+    pub fn in_token_referral_fees(
+        &self,
+        amount: u128,
+        has_referrer: bool,
+        has_second_level_referrer: bool,
+    ) -> u128 {
+        self.out_tokens
+            .iter()
+            .filter(|out_token| out_token.referral_payout == ReferralPayout::InToken)
+            .map(|out_token| {
+                out_token
+                    .referral_fees(amount, has_referrer, has_second_level_referrer)
+                    .total()
+            })
+            .sum()
     }
 
     pub fn assert_valid_not_started(&self) {
@@ -300,12 +830,59 @@ impl Sale {
             errors::TOO_LONG_URL
         );
 
+        assert!(!self.in_tokens.is_empty(), "{}", errors::NO_IN_TOKENS);
+        assert!(
+            self.in_tokens.len() <= MAX_NUM_IN_TOKENS,
+            "{}",
+            errors::MAX_NUM_IN_TOKENS
+        );
+
+        let mut unique_tokens = Vec::with_capacity(self.in_tokens.len());
+        for in_token in &self.in_tokens {
+            assert!(
+                in_token.weight.numerator > 0 && in_token.weight.denominator > 0,
+                "{}",
+                errors::ZERO_IN_TOKEN_WEIGHT
+            );
+            unique_tokens.push(in_token.token_account_id.clone());
+        }
+        unique_tokens.sort();
+        unique_tokens.dedup();
+        assert_eq!(
+            unique_tokens.len(),
+            self.in_tokens.len(),
+            "{}",
+            errors::NON_UNIQUE_IN_TOKENS
+        );
+
+        if let Some(max_account_in_amount) = self.max_account_in_amount {
+            assert!(
+                max_account_in_amount > 0 && self.min_deposit.unwrap_or(0) <= max_account_in_amount,
+                "{}",
+                errors::INVALID_DEPOSIT_LIMITS
+            );
+        }
+
+        if let Some(withdrawal_lock_duration) = self.withdrawal_lock_duration {
+            assert!(
+                withdrawal_lock_duration <= self.duration,
+                "{}",
+                errors::INVALID_WITHDRAWAL_LOCK_DURATION
+            );
+        }
+        if let Some(early_exit_penalty) = &self.early_exit_penalty {
+            assert!(
+                early_exit_penalty.penalty_bpt <= MAX_EARLY_EXIT_PENALTY_BPT,
+                "{}",
+                errors::MAX_EARLY_EXIT_PENALTY_BPT
+            );
+        }
+
         let mut unique_tokens = Vec::with_capacity(self.out_tokens.len());
         for out_token in &self.out_tokens {
             assert!(out_token.remaining > 0, "{}", errors::ZERO_OUT_AMOUNT);
-            assert_ne!(
-                self.in_token_account_id,
-                out_token.token_account_id,
+            assert!(
+                self.in_token_index(&out_token.token_account_id).is_none(),
                 "{}",
                 errors::SAME_TOKENS
             );
@@ -315,6 +892,20 @@ impl Sale {
                     "{}",
                     errors::MAX_REFERRAL_BPT
                 );
+                let referrer_bpt = out_token.referrer_bpt.unwrap_or(referral_bpt / 2);
+                assert!(
+                    referrer_bpt + out_token.second_level_referrer_bpt.unwrap_or(0) <= referral_bpt,
+                    "{}",
+                    errors::INVALID_REFERRAL_CONFIG
+                );
+            } else {
+                assert!(
+                    out_token.referrer_bpt.is_none()
+                        && out_token.second_level_referrer_bpt.is_none()
+                        && out_token.referral_payout == ReferralPayout::OutToken,
+                    "{}",
+                    errors::INVALID_REFERRAL_CONFIG
+                );
             }
             unique_tokens.push(out_token.token_account_id.clone());
         }
@@ -326,30 +917,95 @@ impl Sale {
             "{}",
             errors::NON_UNIQUE_OUT_TOKENS
         );
+
+        if let Some(liquidity) = &self.liquidity {
+            assert!(
+                !self.out_tokens.is_empty(),
+                "{}",
+                errors::INVALID_LIQUIDITY_CONFIG
+            );
+            liquidity.assert_valid();
+        }
+        if let Some(proceeds_lock) = &self.proceeds_lock {
+            assert!(
+                proceeds_lock.vesting_duration <= MAX_DURATION
+                    && proceeds_lock
+                        .refund_quorum_bpt
+                        .map(|bpt| bpt > 0 && bpt as u128 <= PROCEEDS_BPT_DENOMINATOR)
+                        .unwrap_or(true)
+                    && (proceeds_lock.refund_quorum_bpt.is_none() || !self.out_tokens.is_empty()),
+                "{}",
+                errors::INVALID_PROCEEDS_LOCK
+            );
+        }
+    }
+
+    /// Returns the out token amounts that are withdrawn from the creator.
+    pub fn out_token_reserves(&self) -> Vec<u128> {
+        self.out_tokens
+            .iter()
+            .enumerate()
+            .map(|(index, out_token)| match &self.liquidity {
+                Some(liquidity) if index == 0 => out_token.remaining + liquidity.out_amount,
+                _ => out_token.remaining,
+            })
+            .collect()
     }
 
-    pub fn from_input(sale: SaleInput, owner_id: AccountId) -> Self {
+    pub fn from_input(sale: SaleInput, creator_id: AccountId) -> Self {
         let start_time = sale.start_time.0;
+        let pending_owner_id = sale.owner_id.filter(|owner_id| owner_id != &creator_id);
+        let num_in_tokens = sale.in_tokens.len();
         Sale {
-            owner_id,
+            proceeds_receiver_id: sale
+                .proceeds_receiver_id
+                .or_else(|| pending_owner_id.clone())
+                .unwrap_or_else(|| creator_id.clone()),
+            owner_id: creator_id,
+            pending_owner_id,
             title: sale.title,
             url: sale.url,
             permissions_contract_id: sale.permissions_contract_id,
+            hooks_contract_id: sale.hooks_contract_id,
             out_tokens: sale
                 .out_tokens
                 .into_iter()
                 .map(SaleOutToken::from_input)
                 .collect(),
-            in_token_account_id: sale.in_token_account_id,
+            in_tokens: sale
+                .in_tokens
+                .into_iter()
+                .map(SaleInToken::from_input)
+                .collect(),
             in_token_remaining: 0,
             in_token_paid_unclaimed: 0,
             in_token_paid: 0,
+            min_deposit: sale.min_deposit.map(|a| a.0),
+            max_account_in_amount: sale.max_account_in_amount.map(|a| a.0),
+            withdrawal_lock_duration: sale.withdrawal_lock_duration.map(|d| d.0),
+            early_exit_penalty: sale.early_exit_penalty,
+            referral_attribution: sale
+                .referral_attribution
+                .unwrap_or(ReferralAttribution::FirstReferrer),
+            liquidity: sale.liquidity.map(SaleLiquidity::from_input),
+            proceeds_lock: sale
+                .proceeds_lock
+                .map(|lock| ProceedsLock::from_input(lock, num_in_tokens)),
+            paused_at: None,
+            verified: false,
             total_shares: 0,
             start_time,
             duration: sale.duration.0,
             last_timestamp: start_time,
             start_block_height: env::block_height(),
             end_block_height: None,
+            history_interval: sale
+                .history_interval
+                .map(|interval| interval.0)
+                .unwrap_or(sale.duration.0 / MAX_SALE_HISTORY_LENGTH),
+            last_checkpoint_time: 0,
+            pending_checkpoint: None,
+            pending_hooks: vec![],
         }
     }
 
@@ -357,17 +1013,31 @@ impl Sale {
         let remaining_duration = self.start_time + self.duration - self.last_timestamp;
         let subscription =
             account.and_then(|account| account.internal_subscription_output(sale_id, &self));
+        let proceeds_lock = self.proceeds_lock_output();
         SaleOutput {
             sale_id,
             owner_id: self.owner_id,
+            pending_owner_id: self.pending_owner_id,
+            proceeds_receiver_id: self.proceeds_receiver_id,
             title: self.title,
             url: self.url,
             permissions_contract_id: self.permissions_contract_id,
+            hooks_contract_id: self.hooks_contract_id,
             out_tokens: self.out_tokens.into_iter().map(|o| o.into()).collect(),
-            in_token_account_id: self.in_token_account_id,
+            in_tokens: self.in_tokens.into_iter().map(|i| i.into()).collect(),
             in_token_remaining: self.in_token_remaining.into(),
             in_token_paid_unclaimed: self.in_token_paid_unclaimed.into(),
             in_token_paid: self.in_token_paid.into(),
+            min_deposit: self.min_deposit.map(|a| a.into()),
+            max_account_in_amount: self.max_account_in_amount.map(|a| a.into()),
+            withdrawal_lock_duration: self.withdrawal_lock_duration.map(|d| d.into()),
+            early_exit_penalty: self.early_exit_penalty,
+            referral_attribution: self.referral_attribution,
+            liquidity: self.liquidity.map(|liquidity| liquidity.into()),
+            proceeds_lock,
+            history_interval: self.history_interval.into(),
+            paused_at: self.paused_at.map(|t| t.into()),
+            verified: self.verified,
             total_shares: self.total_shares.into(),
             start_time: self.start_time.into(),
             duration: self.duration.into(),
@@ -389,6 +1059,85 @@ impl Sale {
             .as_u128()
     }
 
+    /// Returns the remaining balance of every in token that belongs to the given shares of every
+    /// in token.
+    pub fn token_shares_to_in_balances(&self, token_shares: &[u128]) -> Vec<u128> {
+        self.in_tokens
+            .iter()
+            .zip(token_shares.iter())
+            .map(|(in_token, &shares)| {
+                if shares == 0 {
+                    return 0;
+                }
+                (U256::from(in_token.remaining) * U256::from(shares) / U256::from(in_token.shares))
+                    .as_u128()
+            })
+            .collect()
+    }
+
+    /// Removes the given shares of every in token from the sale.
+    pub fn remove_token_shares(&mut self, token_shares: &[u128]) {
+        for (in_token, &shares) in self.in_tokens.iter_mut().zip(token_shares.iter()) {
+            in_token.shares -= shares;
+            self.total_shares -= shares;
+        }
+    }
+
+    /// Returns the normalized in_amount of the given amount of the in token at the given index.
+    pub fn to_normalized_in_amount(&self, index: usize, amount: u128) -> u128 {
+        self.in_tokens[index].to_normalized(amount)
+    }
+
+    /// Removes the given in token balances from the sale and returns their normalized amount.
+    pub fn withdraw_in_balances(&mut self, in_balances: &[u128]) -> u128 {
+        for (in_token, &in_balance) in self.in_tokens.iter_mut().zip(in_balances.iter()) {
+            in_token.remaining -= in_balance;
+        }
+        let in_token_remaining = self.in_token_remaining;
+        self.update_in_token_remaining();
+        in_token_remaining - self.in_token_remaining
+    }
+
+    /// Recomputes the normalized remaining in tokens from the remaining amount of every in token.
+    /// Summing the rounded down amounts keeps it from drifting away from the in token balances.
+    pub fn update_in_token_remaining(&mut self) {
+        self.in_token_remaining = self
+            .in_tokens
+            .iter()
+            .map(|in_token| in_token.to_normalized(in_token.remaining))
+            .sum();
+    }
+
+    /// Returns the in tokens followed by the out tokens.
+    pub fn token_account_ids(&self) -> Vec<AccountId> {
+        self.in_tokens
+            .iter()
+            .map(|in_token| in_token.token_account_id.clone())
+            .chain(
+                self.out_tokens
+                    .iter()
+                    .map(|out_token| out_token.token_account_id.clone()),
+            )
+            .collect()
+    }
+
+    /// Caches the given metadata in the order of `token_account_ids`.
+    pub fn set_token_metadata(&mut self, metadata: Vec<TokenMetadata>) {
+        let mut metadata = metadata.into_iter();
+        for in_token in &mut self.in_tokens {
+            in_token.metadata = metadata.next();
+        }
+        for out_token in &mut self.out_tokens {
+            out_token.metadata = metadata.next();
+        }
+    }
+
+    pub fn in_token_index(&self, token_account_id: &AccountId) -> Option<usize> {
+        self.in_tokens
+            .iter()
+            .position(|in_token| &in_token.token_account_id == token_account_id)
+    }
+
     pub fn in_amount_to_shares(&self, in_amount: u128, round_up: bool) -> u128 {
         if self.total_shares == 0 {
             return in_amount;
@@ -415,9 +1164,61 @@ impl Sale {
         num_shares.as_u128()
     }
 
+    /// Unpauses the sale and delays its end by the time it was frozen, up to `MAX_DURATION`. If the
+    /// delay is capped, the frozen time beyond the cap is streamed as if the sale wasn't paused.
+    pub fn resume(&mut self) {
+        self.paused_at = None;
+        let timestamp = env::block_timestamp();
+        if timestamp > self.last_timestamp && !self.has_ended() {
+            let remaining_duration = self.start_time + self.duration - self.last_timestamp;
+            self.duration = std::cmp::min(
+                self.duration + (timestamp - self.last_timestamp),
+                MAX_DURATION,
+            );
+            self.last_timestamp = std::cmp::min(
+                timestamp,
+                self.start_time + self.duration - remaining_duration,
+            );
+        }
+    }
+
+    pub fn assert_withdrawals_unlocked(&self) {
+        if let Some(withdrawal_lock_duration) = self.withdrawal_lock_duration {
+            let end_time = self.start_time + self.duration;
+            assert!(
+                env::block_timestamp() < end_time.saturating_sub(withdrawal_lock_duration),
+                "{}",
+                errors::WITHDRAWALS_LOCKED
+            );
+        }
+    }
+
+    /// Returns the early exit penalty for the given in balance.
+    pub fn early_exit_penalty_amount(&self, in_balance: u128) -> u128 {
+        self.early_exit_penalty
+            .map(|early_exit_penalty| {
+                (U256::from(in_balance) * U256::from(early_exit_penalty.penalty_bpt)
+                    / U256::from(EARLY_EXIT_PENALTY_DENOMINATOR))
+                .as_u128()
+            })
+            .unwrap_or(0)
+    }
+
     pub fn has_ended(&self) -> bool {
         self.last_timestamp >= self.start_time + self.duration
     }
+
+    pub fn status(&self) -> SaleStatus {
+        if self.is_refunded() {
+            SaleStatus::Cancelled
+        } else if self.has_ended() {
+            SaleStatus::Ended
+        } else if env::block_timestamp() < self.start_time {
+            SaleStatus::Upcoming
+        } else {
+            SaleStatus::Active
+        }
+    }
 }
 
 impl Contract {
@@ -435,18 +1236,74 @@ impl Contract {
         })
     }
 
+    /// Returns the reserved out tokens, the listing fee and the attached deposit, except for the
+    /// storage used to register the in tokens, to the owner of a sale that failed to be created.
+    pub fn internal_cancel_sale_create(
+        &mut self,
+        creator_id: &AccountId,
+        sale: &Sale,
+        storage_used: StorageUsage,
+        attached_deposit: u128,
+        listing_fee: &ListingFee,
+    ) {
+        let mut account = self.internal_unwrap_account(creator_id);
+        for (out_token, amount) in sale.out_tokens.iter().zip(sale.out_token_reserves()) {
+            if amount > 0 {
+                account.internal_token_deposit(&out_token.token_account_id, amount);
+            }
+        }
+        if let ListingFee::Token {
+            token_account_id,
+            amount,
+        } = listing_fee
+        {
+            account.internal_token_deposit(token_account_id, amount.0);
+        }
+        self.accounts.insert(creator_id, &account.into());
+        let refund = attached_deposit
+            .saturating_sub(env::storage_byte_cost().as_yoctonear() * storage_used as u128);
+        if refund > 1 {
+            Promise::new(creator_id.clone()).transfer(NearToken::from_yoctonear(refund));
+        }
+    }
+
     pub fn internal_distribute_unclaimed_tokens(&mut self, sale: &mut Sale) {
+        let mut amounts: Vec<(AccountId, U128)> = vec![];
         if sale.in_token_paid_unclaimed > 0 {
-            let mut account = self.internal_unwrap_account(&sale.owner_id);
-            let treasury_fee = sale.in_token_paid_unclaimed / TREASURY_FEE_DENOMINATOR;
-            self.treasury
-                .internal_deposit(&sale.in_token_account_id, treasury_fee);
-            sale.in_token_paid_unclaimed -= treasury_fee;
-            account.internal_token_deposit(&sale.in_token_account_id, sale.in_token_paid_unclaimed);
-            self.accounts.insert(&sale.owner_id, &account.into());
+            let mut account = self.internal_unwrap_account(&sale.proceeds_receiver_id);
+            for (index, in_token) in sale.in_tokens.iter_mut().enumerate() {
+                if in_token.paid_unclaimed == 0 {
+                    continue;
+                }
+                let treasury_fee = in_token.paid_unclaimed / TREASURY_FEE_DENOMINATOR;
+                self.treasury
+                    .internal_deposit(&in_token.token_account_id, treasury_fee);
+                in_token.paid_unclaimed -= treasury_fee;
+                if let Some(liquidity) = sale.liquidity.as_mut().filter(|_| index == 0) {
+                    let reserve = liquidity.reserve_amount(in_token.paid_unclaimed);
+                    liquidity.in_amount += reserve;
+                    in_token.paid_unclaimed -= reserve;
+                }
+                if let Some(proceeds_lock) = sale.proceeds_lock.as_mut() {
+                    proceeds_lock.locked[index] += in_token.paid_unclaimed;
+                } else {
+                    account.internal_token_deposit(
+                        &in_token.token_account_id,
+                        in_token.paid_unclaimed,
+                    );
+                    amounts.push((
+                        in_token.token_account_id.clone(),
+                        in_token.paid_unclaimed.into(),
+                    ));
+                }
+                in_token.paid_unclaimed = 0;
+            }
+            self.accounts
+                .insert(&sale.proceeds_receiver_id, &account.into());
 
             sale.in_token_paid_unclaimed = 0;
         }
+        amounts.extend(self.internal_release_proceeds(sale));
         let sale_ended = sale.has_ended();
         for out_token in &mut sale.out_tokens {
             self.treasury
@@ -454,43 +1311,212 @@ impl Contract {
             out_token.treasury_unclaimed = 0;
             if sale_ended && out_token.remaining > 0 {
                 // No one subscribed at the end of the sale
-                let mut account = self.internal_unwrap_account(&sale.owner_id);
+                let mut account = self.internal_unwrap_account(&sale.proceeds_receiver_id);
                 account.internal_token_deposit(&out_token.token_account_id, out_token.remaining);
-                self.accounts.insert(&sale.owner_id, &account.into());
+                self.accounts
+                    .insert(&sale.proceeds_receiver_id, &account.into());
+                amounts.push((
+                    out_token.token_account_id.clone(),
+                    out_token.remaining.into(),
+                ));
                 out_token.distributed += out_token.remaining;
+                out_token.returned += out_token.remaining;
                 out_token.remaining = 0;
             }
         }
+        if !amounts.is_empty() {
+            sale.add_hook(SaleHook::ProceedsDistributed(amounts));
+        }
     }
 }
 
 #[near_bindgen]
 impl Contract {
+    /// Reserves the out tokens and verifies that all tokens of the sale are NEP-141 contracts with
+    /// the Skyward contract registered. The sale is created in `after_sale_create`.
     #[payable]
-    pub fn sale_create(&mut self, sale: SaleInput) -> u64 {
+    pub fn sale_create(&mut self, sale: SaleInput) -> Promise {
+        assert!(
+            !self.pause_flags.sale_creation,
+            "{}",
+            errors::SALE_CREATION_PAUSED
+        );
         let initial_storage_usage = env::storage_usage();
-        let sale_id = self.num_sales;
-        let sale = Sale::from_input(sale, env::predecessor_account_id());
-        sale.assert_valid_not_started();
+        let attached_deposit = env::attached_deposit().as_yoctonear();
+        let listing_fee = self
+            .treasury
+            .get_listing_fee(sale.listing_fee_token_account_id.as_ref());
+        if let ListingFee::Near(amount) = &listing_fee {
+            assert!(
+                attached_deposit >= amount.0,
+                "{}",
+                errors::NOT_ENOUGH_ATTACHED_BALANCE
+            );
+        }
+        let creator_id = env::predecessor_account_id();
+        let new_sale = Sale::from_input(sale, creator_id.clone());
+        new_sale.assert_valid_not_started();
+        for in_token in &new_sale.in_tokens {
+            self.token_policy
+                .assert_in_token_allowed(&in_token.token_account_id);
+        }
+        for out_token in &new_sale.out_tokens {
+            self.token_policy
+                .assert_out_token_not_denied(&out_token.token_account_id);
+        }
 
-        let mut account = self.internal_unwrap_account(&sale.owner_id);
-        for out_token in &sale.out_tokens {
-            if out_token.remaining > 0 {
-                account.internal_token_withdraw(&out_token.token_account_id, out_token.remaining);
+        let mut account = self.internal_unwrap_account(&creator_id);
+        for (out_token, amount) in new_sale
+            .out_tokens
+            .iter()
+            .zip(new_sale.out_token_reserves())
+        {
+            if amount > 0 {
+                account.internal_token_withdraw(&out_token.token_account_id, amount);
             }
         }
-        self.internal_maybe_register_token(&mut account, &sale.in_token_account_id);
-        account.sales.insert(&sale_id);
+        if let ListingFee::Token {
+            token_account_id,
+            amount,
+        } = &listing_fee
+        {
+            account.internal_token_withdraw(token_account_id, amount.0);
+        }
+        self.accounts.insert(&creator_id, &account.into());
+        // The owner has to be registered to keep track of the sale.
+        self.internal_unwrap_account(&new_sale.owner_id);
+        let mut proceeds_receiver = self.internal_unwrap_account(&new_sale.proceeds_receiver_id);
+        for token_account_id in new_sale.token_account_ids() {
+            self.internal_maybe_register_token(&mut proceeds_receiver, &token_account_id);
+        }
+        self.accounts
+            .insert(&new_sale.proceeds_receiver_id, &proceeds_receiver.into());
+        self.treasury.locked_attached_deposits += attached_deposit;
+
+        let mut promise: Option<Promise> = None;
+        for token_account_id in new_sale.token_account_ids() {
+            let token_promise = ext_ft_metadata::ext(token_account_id.clone())
+                .with_static_gas(FT_METADATA_GAS)
+                .ft_metadata()
+                .and(
+                    ext_storage_management::ext(token_account_id)
+                        .with_static_gas(STORAGE_BALANCE_OF_GAS)
+                        .storage_balance_of(env::current_account_id()),
+                );
+            promise = Some(match promise {
+                Some(promise) => promise.and(token_promise),
+                None => token_promise,
+            });
+        }
+        promise.expect(errors::NO_IN_TOKENS).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(AFTER_SALE_CREATE_GAS)
+                .after_sale_create(
+                    creator_id,
+                    new_sale,
+                    env::storage_usage() - initial_storage_usage,
+                    attached_deposit.into(),
+                    listing_fee,
+                ),
+        )
+    }
+
+    /// Creates the sale validated in `sale_create` if all token checks passed. Otherwise returns
+    /// the reserved out tokens and the attached deposit to the owner.
+    #[private]
+    pub fn after_sale_create(
+        &mut self,
+        #[serializer(borsh)] creator_id: AccountId,
+        #[serializer(borsh)] mut sale: Sale,
+        #[serializer(borsh)] storage_used: StorageUsage,
+        #[serializer(borsh)] attached_deposit: U128,
+        #[serializer(borsh)] listing_fee: ListingFee,
+    ) -> Option<u64> {
+        let attached_deposit = attached_deposit.0;
+        self.treasury.locked_attached_deposits -= attached_deposit;
+        let owner_id = sale.owner_id.clone();
+
+        let mut token_metadata = vec![];
+        for (index, token_account_id) in sale.token_account_ids().into_iter().enumerate() {
+            let metadata = match env::promise_result(2 * index as u64) {
+                PromiseResult::Successful(value) => {
+                    serde_json::from_slice::<FungibleTokenMetadata>(&value).ok()
+                }
+                _ => None,
+            };
+            let is_registered = match env::promise_result(2 * index as u64 + 1) {
+                PromiseResult::Successful(value) => {
+                    serde_json::from_slice::<Option<StorageBalance>>(&value)
+                        .ok()
+                        .flatten()
+                        .is_some()
+                }
+                _ => false,
+            };
+            match metadata {
+                Some(metadata) if is_registered => token_metadata.push(metadata.into()),
+                _ => {
+                    log!("{} {}", errors::INVALID_TOKEN, token_account_id);
+                    self.internal_cancel_sale_create(
+                        &creator_id,
+                        &sale,
+                        storage_used,
+                        attached_deposit,
+                        &listing_fee,
+                    );
+                    return None;
+                }
+            }
+        }
+        sale.set_token_metadata(token_metadata);
 
-        self.accounts.insert(&sale.owner_id, &account.into());
+        let initial_storage_usage = env::storage_usage();
+        let sale_id = self.num_sales;
+        let mut account = self.internal_unwrap_account(&owner_id);
+        account.sales.insert(&sale_id);
+        self.accounts.insert(&owner_id, &account.into());
+        self.sale_index.internal_add_sale(sale_id, &sale);
+        self.internal_init_sale_history(sale_id, sale.out_tokens.len());
         self.sales.insert(&sale_id, &sale.into());
+
+        let near_listing_fee = match &listing_fee {
+            ListingFee::Near(amount) => amount.0,
+            ListingFee::Token { .. } => 0,
+        };
+        let required_cost = env::storage_byte_cost().as_yoctonear()
+            * (storage_used + env::storage_usage() - initial_storage_usage) as u128
+            + near_listing_fee;
+        if required_cost > attached_deposit {
+            log!("{} {}", errors::NOT_ENOUGH_ATTACHED_BALANCE, required_cost);
+            let sale: Sale = self.sales.remove(&sale_id).unwrap().into();
+            self.sale_index.internal_remove_sale(sale_id, &sale);
+            self.internal_remove_sale_history(sale_id);
+            let mut account = self.internal_unwrap_account(&owner_id);
+            account.sales.remove(&sale_id);
+            self.accounts.insert(&owner_id, &account.into());
+            self.internal_cancel_sale_create(
+                &creator_id,
+                &sale,
+                storage_used,
+                attached_deposit,
+                &listing_fee,
+            );
+            return None;
+        }
         self.num_sales += 1;
+        if let ListingFee::Token {
+            token_account_id,
+            amount,
+        } = listing_fee
+        {
+            self.treasury.internal_deposit(&token_account_id, amount.0);
+        }
 
-        refund_extra_storage_deposit(
-            env::storage_usage() - initial_storage_usage,
-            self.treasury.listing_fee_near,
-        );
-        sale_id
+        let refund = attached_deposit - required_cost;
+        if refund > 1 {
+            Promise::new(creator_id).transfer(NearToken::from_yoctonear(refund));
+        }
+        Some(sale_id)
     }
 
     pub fn get_sale(&self, sale_id: u64, account_id: Option<AccountId>) -> Option<SaleOutput> {
@@ -527,14 +1553,18 @@ impl Contract {
             .collect()
     }
 
+    /// Deposits the given amount of the in token into the sale. The in token defaults to the
+    /// first in token of the sale.
     #[payable]
     pub fn sale_deposit_in_token(
         &mut self,
         sale_id: u64,
         amount: U128,
         referral_id: Option<AccountId>,
+        in_token_account_id: Option<AccountId>,
     ) {
         assert_at_least_one_yocto();
+        assert!(!self.pause_flags.deposits, "{}", errors::DEPOSITS_PAUSED);
         let initial_storage_usage = env::storage_usage();
         let account_id = env::predecessor_account_id();
 
@@ -543,6 +1573,7 @@ impl Contract {
         let permissions_contract_id = self.internal_deposit_in_amount(
             sale_id,
             &account_id,
+            in_token_account_id.as_ref(),
             in_amount,
             referral_id.as_ref(),
             false,
@@ -560,6 +1591,7 @@ impl Contract {
                         .after_is_approved(
                             sale_id,
                             account_id.clone(),
+                            in_token_account_id,
                             in_amount.into(),
                             referral_id,
                             attached_deposit.as_yoctonear().into(),
@@ -582,41 +1614,177 @@ impl Contract {
     #[payable]
     pub fn sale_withdraw_in_token(&mut self, sale_id: u64, shares: Option<U128>) {
         assert_one_yocto();
+        assert!(
+            !self.pause_flags.withdrawals,
+            "{}",
+            errors::WITHDRAWALS_PAUSED
+        );
         let initial_storage_usage = env::storage_usage();
         let account_id = env::predecessor_account_id();
         self.internal_withdraw_shares(sale_id, &account_id, shares.map(|s| s.0));
-        refund_released_storage(&account_id, initial_storage_usage - env::storage_usage());
+        refund_released_storage(
+            &account_id,
+            initial_storage_usage.saturating_sub(env::storage_usage()),
+        );
     }
 
+    /// Withdraws the given normalized amount of in tokens. If the sale accepts multiple in
+    /// tokens, the amount is paid out in all of them proportionally to their remaining balances.
     #[payable]
     pub fn sale_withdraw_in_token_exact(&mut self, sale_id: u64, amount: U128) {
         assert_one_yocto();
+        assert!(
+            !self.pause_flags.withdrawals,
+            "{}",
+            errors::WITHDRAWALS_PAUSED
+        );
         let initial_storage_usage = env::storage_usage();
         let account_id = env::predecessor_account_id();
         self.internal_withdraw_in_token_exact(sale_id, &account_id, amount.0);
-        refund_released_storage(&account_id, initial_storage_usage - env::storage_usage());
+        refund_released_storage(
+            &account_id,
+            initial_storage_usage.saturating_sub(env::storage_usage()),
+        );
     }
 
     /// This method can be called by anyone in order to move in tokens to treasury
     pub fn sale_distribute_unclaimed_tokens(&mut self, sale_id: u64) {
+        assert!(!self.pause_flags.claims, "{}", errors::CLAIMS_PAUSED);
         let mut sale = self.internal_unwrap_sale(sale_id);
         self.internal_distribute_unclaimed_tokens(&mut sale);
-        self.sales.insert(&sale_id, &sale.into());
+        self.internal_save_sale(sale_id, sale);
     }
 
     pub fn sale_claim_out_tokens(&mut self, sale_id: u64) {
+        assert!(!self.pause_flags.claims, "{}", errors::CLAIMS_PAUSED);
         let account_id = env::predecessor_account_id();
         let initial_storage_usage = env::storage_usage();
-        let mut sale = self.internal_unwrap_sale(sale_id);
-        self.internal_distribute_unclaimed_tokens(&mut sale);
         let mut account = self.internal_unwrap_account(&account_id);
-        let subscription =
-            self.internal_update_subscription(&mut account, sale_id, &mut sale, None, false);
-
-        account.internal_save_subscription(sale_id, &sale, subscription);
+        self.internal_claim_out_tokens(&mut account, &account_id, sale_id);
+        self.accounts.insert(&account_id, &account.into());
+        // Referral records created by the claim are paid by the contract.
+        refund_released_storage(
+            &account_id,
+            initial_storage_usage.saturating_sub(env::storage_usage()),
+        );
+    }
 
+    /// Claims out tokens from the given sales or from all subscribed sales. Stops early when
+    /// there is not enough gas left and returns the sales that weren't claimed.
+    /// If `withdraw` is set, withdraws the claimed out tokens.
+    pub fn sale_claim_out_tokens_batch(
+        &mut self,
+        sale_ids: Option<Vec<u64>>,
+        withdraw: Option<bool>,
+    ) -> ClaimOutTokensBatchOutput {
+        assert!(!self.pause_flags.claims, "{}", errors::CLAIMS_PAUSED);
+        let withdraw = withdraw.unwrap_or(false);
+        assert!(
+            !withdraw || !self.pause_flags.withdrawals,
+            "{}",
+            errors::WITHDRAWALS_PAUSED
+        );
+        let account_id = env::predecessor_account_id();
+        let initial_storage_usage = env::storage_usage();
+        let mut account = self.internal_unwrap_account(&account_id);
+        let sale_ids = sale_ids.unwrap_or_else(|| account.subs.keys().collect());
+
+        let mut claimed_sale_ids = vec![];
+        let mut remaining_sale_ids = vec![];
+        let mut claimed_amounts: Vec<(AccountId, u128)> = vec![];
+        for sale_id in sale_ids {
+            if account.subs.get(&sale_id).is_none() {
+                continue;
+            }
+            if remaining_sale_ids.is_empty() {
+                let withdraw_gas = if withdraw {
+                    WITHDRAW_TOKEN_GAS
+                        .saturating_mul((claimed_amounts.len() + MAX_NUM_OUT_TOKENS) as u64)
+                } else {
+                    Gas::from_gas(0)
+                };
+                let remaining_gas = env::prepaid_gas().saturating_sub(env::used_gas());
+                if remaining_gas >= CLAIM_OUT_TOKENS_GAS.saturating_add(withdraw_gas) {
+                    for (token_account_id, amount) in
+                        self.internal_claim_out_tokens(&mut account, &account_id, sale_id)
+                    {
+                        match claimed_amounts
+                            .iter_mut()
+                            .find(|(claimed_token_account_id, _)| {
+                                claimed_token_account_id == &token_account_id
+                            }) {
+                            Some((_, claimed_amount)) => *claimed_amount += amount,
+                            None => claimed_amounts.push((token_account_id, amount)),
+                        }
+                    }
+                    claimed_sale_ids.push(sale_id);
+                    continue;
+                }
+            }
+            remaining_sale_ids.push(sale_id);
+        }
+        if withdraw {
+            for (token_account_id, amount) in &claimed_amounts {
+                if *amount > 0 {
+                    account.internal_token_withdraw(token_account_id, *amount);
+                    self.internal_ft_transfer(&account_id, token_account_id, *amount);
+                }
+            }
+        }
         self.accounts.insert(&account_id, &account.into());
-        self.sales.insert(&sale_id, &sale.into());
-        refund_released_storage(&account_id, initial_storage_usage - env::storage_usage());
+        refund_released_storage(
+            &account_id,
+            initial_storage_usage.saturating_sub(env::storage_usage()),
+        );
+        ClaimOutTokensBatchOutput {
+            claimed_sale_ids,
+            remaining_sale_ids,
+        }
+    }
+}
+
+impl Contract {
+    /// Claims out tokens of the account's subscription and returns the claimed amount of every
+    /// out token of the sale.
+    fn internal_claim_out_tokens(
+        &mut self,
+        account: &mut Account,
+        account_id: &AccountId,
+        sale_id: u64,
+    ) -> Vec<(AccountId, u128)> {
+        let mut sale = self.internal_unwrap_sale(sale_id);
+        self.internal_distribute_unclaimed_tokens(&mut sale);
+        let initial_balances: Vec<u128> = sale
+            .out_tokens
+            .iter()
+            .map(|out_token| {
+                account
+                    .balances
+                    .get(&out_token.token_account_id)
+                    .unwrap_or(0)
+            })
+            .collect();
+        let subscription =
+            self.internal_update_subscription(account, sale_id, &mut sale, None, false);
+
+        self.internal_save_subscription(account, account_id, sale_id, &mut sale, subscription);
+
+        let claimed_amounts = sale
+            .out_tokens
+            .iter()
+            .zip(initial_balances)
+            .map(|(out_token, initial_balance)| {
+                let balance = account
+                    .balances
+                    .get(&out_token.token_account_id)
+                    .unwrap_or(0);
+                (
+                    out_token.token_account_id.clone(),
+                    balance - initial_balance,
+                )
+            })
+            .collect();
+        self.internal_save_sale(sale_id, sale);
+        claimed_amounts
     }
 }
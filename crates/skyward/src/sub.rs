@@ -1,4 +1,4 @@
-use crate::{errors, Contract, Sale, MULTIPLIER};
+use crate::{errors, Account, Contract, PenaltyReceiver, Sale, MULTIPLIER};
 use near_sdk::{
     borsh::{BorshDeserialize, BorshSerialize},
     json_types::U128,
@@ -7,20 +7,46 @@ use near_sdk::{
 };
 use primitive_types::U256;
 
+#[derive(BorshSerialize, BorshDeserialize)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct OldSubscription {
+    pub shares: u128,
+    pub last_in_balance: u128,
+    pub spent_in_balance_without_shares: u128,
+    pub last_out_token_per_share: Vec<[u64; 4]>,
+    pub claimed_out_balance: Vec<u128>,
+    pub referral_id: Option<AccountId>,
+}
+
 #[derive(BorshSerialize, BorshDeserialize)]
 #[borsh(crate = "near_sdk::borsh")]
 pub struct Subscription {
     pub shares: u128,
+    /// Only used to compute `spent_in_balances` of subscriptions from before multiple in tokens
+    /// were supported. The remaining in balance of shares isn't monotonic, since deposits and
+    /// withdrawals round in favor of the remaining shares, so it can't track the spent balance.
     pub last_in_balance: u128,
     pub spent_in_balance_without_shares: u128,
     pub last_out_token_per_share: Vec<[u64; 4]>,
     pub claimed_out_balance: Vec<u128>,
     pub referral_id: Option<AccountId>,
+    pub last_in_token_paid_per_share: Vec<[u64; 4]>,
+    pub spent_in_balances: Vec<u128>,
+    /// The shares bought with every in token, which sum up to `shares`.
+    pub in_token_shares: Vec<u128>,
+    /// The normalized in amount deposited net of withdrawals.
+    pub deposited_in_amount: u128,
+    pub last_in_token_penalty_per_share: Vec<[u64; 4]>,
+    /// The in amounts deposited with every referrer. Only used by amount weighted attribution.
+    pub referral_weights: Vec<(AccountId, u128)>,
+    /// The claimed out tokens before the referral fees.
+    pub gross_claimed_out_balance: Vec<u128>,
 }
 
 #[derive(BorshDeserialize, BorshSerialize)]
 #[borsh(crate = "near_sdk::borsh")]
 pub enum VSubscription {
+    First(OldSubscription),
     Current(Subscription),
 }
 
@@ -33,6 +59,23 @@ impl From<Subscription> for VSubscription {
 impl From<VSubscription> for Subscription {
     fn from(v_subscription: VSubscription) -> Self {
         match v_subscription {
+            VSubscription::First(old_subscription) => Subscription {
+                shares: old_subscription.shares,
+                last_in_balance: old_subscription.last_in_balance,
+                spent_in_balance_without_shares: old_subscription.spent_in_balance_without_shares,
+                last_out_token_per_share: old_subscription.last_out_token_per_share,
+                gross_claimed_out_balance: old_subscription.claimed_out_balance.clone(),
+                claimed_out_balance: old_subscription.claimed_out_balance,
+                referral_id: old_subscription.referral_id,
+                // Initialized on the first touch, since it requires the sale.
+                last_in_token_paid_per_share: vec![],
+                spent_in_balances: vec![],
+                in_token_shares: vec![],
+                deposited_in_amount: old_subscription.spent_in_balance_without_shares
+                    + old_subscription.last_in_balance,
+                last_in_token_penalty_per_share: vec![],
+                referral_weights: vec![],
+            },
             VSubscription::Current(subscription) => subscription,
         }
     }
@@ -48,12 +91,56 @@ pub struct SubscriptionOutput {
     pub claimed_out_balance: Vec<U128>,
     pub shares: U128,
     pub referral_id: Option<AccountId>,
+    pub referral_weights: Vec<(AccountId, U128)>,
+    pub in_tokens: Vec<SubscriptionOutputInToken>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct SubscriptionOutputInToken {
+    pub remaining_in_balance: U128,
+    pub spent_in_balance: U128,
 }
 
 impl Subscription {
     pub fn touch(&mut self, sale: &Sale) -> Vec<u128> {
         let shares = U256::from(self.shares);
         let multiplier = U256::from(MULTIPLIER);
+        if self.last_in_token_paid_per_share.len() < sale.in_tokens.len() {
+            // Subscription from before multiple in tokens were supported. Such sales have a
+            // single in token with weight 1, so the normalized spent balance can be used.
+            let remaining_in_balance = sale.shares_to_in_balance(self.shares);
+            self.spent_in_balances = vec![
+                self.spent_in_balance_without_shares
+                    + self.last_in_balance.saturating_sub(remaining_in_balance),
+            ];
+            self.in_token_shares = vec![self.shares];
+            self.last_in_token_paid_per_share = sale
+                .in_tokens
+                .iter()
+                .map(|in_token| in_token.paid_per_share)
+                .collect();
+            self.last_in_token_penalty_per_share = sale
+                .in_tokens
+                .iter()
+                .map(|in_token| in_token.penalty_per_share)
+                .collect();
+        }
+        for (((last_in_token_paid_per_share, spent_in_balance), &in_token_shares), in_token) in self
+            .last_in_token_paid_per_share
+            .iter_mut()
+            .zip(self.spent_in_balances.iter_mut())
+            .zip(self.in_token_shares.iter())
+            .zip(sale.in_tokens.iter())
+        {
+            let in_token_paid_per_share = U256(in_token.paid_per_share);
+            if in_token_paid_per_share != U256::zero() {
+                let diff = in_token_paid_per_share - U256(*last_in_token_paid_per_share);
+                *spent_in_balance += (diff * U256::from(in_token_shares) / multiplier).as_u128();
+            }
+            *last_in_token_paid_per_share = in_token_paid_per_share.0;
+        }
         self.last_out_token_per_share
             .iter_mut()
             .zip(sale.out_tokens.iter())
@@ -84,11 +171,165 @@ impl Subscription {
                 .collect(),
             claimed_out_balance: vec![0; sale.out_tokens.len()],
             referral_id,
+            last_in_token_paid_per_share: sale
+                .in_tokens
+                .iter()
+                .map(|in_token| in_token.paid_per_share)
+                .collect(),
+            spent_in_balances: vec![0; sale.in_tokens.len()],
+            in_token_shares: vec![0; sale.in_tokens.len()],
+            deposited_in_amount: 0,
+            last_in_token_penalty_per_share: sale
+                .in_tokens
+                .iter()
+                .map(|in_token| in_token.penalty_per_share)
+                .collect(),
+            referral_weights: vec![],
+            gross_claimed_out_balance: vec![0; sale.out_tokens.len()],
         }
     }
+
+    /// Splits the given shares between the in tokens in proportion to the shares bought with
+    /// every in token.
+    pub fn split_shares(&self, shares: u128) -> Vec<u128> {
+        let mut token_shares: Vec<u128> = self
+            .in_token_shares
+            .iter()
+            .map(|&in_token_shares| {
+                (U256::from(in_token_shares) * U256::from(shares) / U256::from(self.shares))
+                    .as_u128()
+            })
+            .collect();
+        // The rounding remainder is taken from the first in tokens that have shares left.
+        let mut remaining = shares - token_shares.iter().sum::<u128>();
+        for (shares, &in_token_shares) in token_shares.iter_mut().zip(self.in_token_shares.iter()) {
+            let amount = std::cmp::min(remaining, in_token_shares - *shares);
+            *shares += amount;
+            remaining -= amount;
+        }
+        token_shares
+    }
+
+    /// Removes the given shares of every in token from the subscription.
+    pub fn remove_token_shares(&mut self, token_shares: &[u128]) {
+        for (in_token_shares, &shares) in self.in_token_shares.iter_mut().zip(token_shares.iter()) {
+            *in_token_shares -= shares;
+            self.shares -= shares;
+        }
+    }
+
+    /// Returns the referrers the referral fees are attributed to.
+    pub fn referral_ids(&self) -> Vec<AccountId> {
+        if self.referral_weights.is_empty() {
+            self.referral_id.iter().cloned().collect()
+        } else {
+            self.referral_weights
+                .iter()
+                .map(|(referral_id, _)| referral_id.clone())
+                .collect()
+        }
+    }
+
+    /// Returns all referrers of the subscription, including the ones without weights.
+    pub fn all_referral_ids(&self) -> Vec<AccountId> {
+        let mut referral_ids: Vec<AccountId> = self
+            .referral_weights
+            .iter()
+            .map(|(referral_id, _)| referral_id.clone())
+            .collect();
+        referral_ids.extend(self.referral_id.clone());
+        referral_ids
+    }
+
+    /// Splits the amount between the referrers of the subscription in the order of
+    /// `referral_ids`.
+    pub fn split_by_referrers(&self, amount: u128) -> Vec<(AccountId, u128)> {
+        if self.referral_weights.is_empty() {
+            return self
+                .referral_id
+                .iter()
+                .map(|referral_id| (referral_id.clone(), amount))
+                .collect();
+        }
+        let total_weight: u128 = self.referral_weights.iter().map(|(_, weight)| weight).sum();
+        self.referral_weights
+            .iter()
+            .map(|(referral_id, weight)| {
+                (
+                    referral_id.clone(),
+                    (U256::from(amount) * U256::from(*weight) / U256::from(total_weight)).as_u128(),
+                )
+            })
+            .collect()
+    }
+
+    /// Returns the amounts of in tokens received from early exit penalties of other subscribers
+    /// since the last claim.
+    pub fn claim_penalties(&mut self, sale: &Sale) -> Vec<u128> {
+        let shares = U256::from(self.shares);
+        self.last_in_token_penalty_per_share
+            .iter_mut()
+            .zip(sale.in_tokens.iter())
+            .map(|(last_in_token_penalty_per_share, in_token)| {
+                let penalty_per_share = U256(in_token.penalty_per_share);
+                let amount = ((penalty_per_share - U256(*last_in_token_penalty_per_share))
+                    * shares
+                    / U256::from(MULTIPLIER))
+                .as_u128();
+                *last_in_token_penalty_per_share = penalty_per_share.0;
+                amount
+            })
+            .collect()
+    }
 }
 
 impl Contract {
+    /// Removes the given in balances from the sale and deposits them to the account minus the early
+    /// exit penalty. The withdrawn shares should already be removed from the sale.
+    /// Returns the normalized in amount deposited to the account.
+    fn internal_withdraw_in_balances(
+        &mut self,
+        sale: &mut Sale,
+        account: &mut Account,
+        subscription: &mut Subscription,
+        in_balances: Vec<u128>,
+    ) -> u128 {
+        sale.withdraw_in_balances(&in_balances);
+        let penalty_to_subscribers = sale.total_shares > 0
+            && sale
+                .early_exit_penalty
+                .map(|early_exit_penalty| early_exit_penalty.receiver)
+                == Some(PenaltyReceiver::Subscribers);
+        let mut in_amount = 0;
+        for (index, in_balance) in in_balances.into_iter().enumerate() {
+            let penalty = sale.early_exit_penalty_amount(in_balance);
+            let in_token = &mut sale.in_tokens[index];
+            if penalty > 0 {
+                if penalty_to_subscribers {
+                    in_token.penalty_per_share = (U256(in_token.penalty_per_share)
+                        + U256::from(penalty) * U256::from(MULTIPLIER)
+                            / U256::from(sale.total_shares))
+                    .0;
+                } else {
+                    self.treasury
+                        .internal_deposit(&in_token.token_account_id, penalty);
+                }
+            }
+            let amount = in_balance - penalty;
+            if amount > 0 {
+                account.internal_token_deposit(&in_token.token_account_id, amount);
+            }
+            in_amount += in_token.to_normalized(amount);
+        }
+        // The penalty is not shared with the remaining shares of the withdrawing account.
+        subscription.last_in_token_penalty_per_share = sale
+            .in_tokens
+            .iter()
+            .map(|in_token| in_token.penalty_per_share)
+            .collect();
+        in_amount
+    }
+
     pub fn internal_withdraw_shares(
         &mut self,
         sale_id: u64,
@@ -96,6 +337,8 @@ impl Contract {
         shares: Option<u128>,
     ) {
         let mut sale = self.internal_unwrap_sale(sale_id);
+        assert!(sale.paused_at.is_none(), "{}", errors::SALE_PAUSED);
+        sale.assert_withdrawals_unlocked();
         self.internal_distribute_unclaimed_tokens(&mut sale);
         let mut account = self.internal_unwrap_account(account_id);
         let mut subscription =
@@ -107,22 +350,23 @@ impl Contract {
             "{}",
             errors::NOT_ENOUGH_SHARES
         );
-        let remaining_in_balance = sale.shares_to_in_balance(subscription.shares);
-        subscription.spent_in_balance_without_shares +=
-            subscription.last_in_balance - remaining_in_balance;
-        subscription.shares -= shares;
-        let in_token_amount = sale.shares_to_in_balance(shares);
-        if in_token_amount > 0 {
-            account.internal_token_deposit(&sale.in_token_account_id, in_token_amount);
-        }
-        sale.total_shares -= shares;
-        sale.in_token_remaining -= in_token_amount;
-
-        subscription.last_in_balance = sale.shares_to_in_balance(subscription.shares);
+        let token_shares = subscription.split_shares(shares);
+        subscription.remove_token_shares(&token_shares);
+        let in_balances = sale.token_shares_to_in_balances(&token_shares);
+        sale.remove_token_shares(&token_shares);
+        let withdrawn_in_amount = self.internal_withdraw_in_balances(
+            &mut sale,
+            &mut account,
+            &mut subscription,
+            in_balances,
+        );
+        subscription.deposited_in_amount = subscription
+            .deposited_in_amount
+            .saturating_sub(withdrawn_in_amount);
 
-        account.internal_save_subscription(sale_id, &sale, subscription);
+        self.internal_save_subscription(&mut account, account_id, sale_id, &mut sale, subscription);
         self.accounts.insert(account_id, &account.into());
-        self.sales.insert(&sale_id, &sale.into());
+        self.internal_save_sale(sale_id, sale);
     }
 
     pub fn internal_withdraw_in_token_exact(
@@ -132,6 +376,8 @@ impl Contract {
         in_amount: u128,
     ) {
         let mut sale = self.internal_unwrap_sale(sale_id);
+        assert!(sale.paused_at.is_none(), "{}", errors::SALE_PAUSED);
+        sale.assert_withdrawals_unlocked();
         self.internal_distribute_unclaimed_tokens(&mut sale);
         let mut account = self.internal_unwrap_account(account_id);
         let mut subscription =
@@ -144,24 +390,32 @@ impl Contract {
             errors::NOT_ENOUGH_BALANCE
         );
         let shares = sale.in_amount_to_shares(in_amount, true);
-        subscription.spent_in_balance_without_shares +=
-            subscription.last_in_balance - remaining_in_balance;
-        subscription.shares -= shares;
-        account.internal_token_deposit(&sale.in_token_account_id, in_amount);
-        sale.total_shares -= shares;
-        sale.in_token_remaining -= in_amount;
-
-        subscription.last_in_balance = sale.shares_to_in_balance(subscription.shares);
+        // Every in token is withdrawn in proportion to the shares bought with it, so the
+        // withdrawn tokens can differ from the normalized amount by the rounding.
+        let token_shares = subscription.split_shares(shares);
+        subscription.remove_token_shares(&token_shares);
+        let in_balances = sale.token_shares_to_in_balances(&token_shares);
+        sale.remove_token_shares(&token_shares);
+        let withdrawn_in_amount = self.internal_withdraw_in_balances(
+            &mut sale,
+            &mut account,
+            &mut subscription,
+            in_balances,
+        );
+        subscription.deposited_in_amount = subscription
+            .deposited_in_amount
+            .saturating_sub(withdrawn_in_amount);
 
-        account.internal_save_subscription(sale_id, &sale, subscription);
+        self.internal_save_subscription(&mut account, account_id, sale_id, &mut sale, subscription);
         self.accounts.insert(account_id, &account.into());
-        self.sales.insert(&sale_id, &sale.into());
+        self.internal_save_sale(sale_id, sale);
     }
 
     pub fn internal_deposit_in_amount(
         &mut self,
         sale_id: u64,
         account_id: &AccountId,
+        in_token_account_id: Option<&AccountId>,
         in_amount: u128,
         referral_id: Option<&AccountId>,
         passed_permission_check: bool,
@@ -169,6 +423,21 @@ impl Contract {
         assert_ne!(referral_id, Some(account_id), "{}", errors::SELF_REFERRAL);
         assert!(in_amount > 0, "{}", errors::ZERO_IN_AMOUNT);
         let mut sale = self.internal_unwrap_sale(sale_id);
+        assert!(sale.paused_at.is_none(), "{}", errors::SALE_PAUSED);
+        let in_token_index = in_token_account_id
+            .map(|in_token_account_id| {
+                sale.in_token_index(in_token_account_id)
+                    .expect(errors::UNKNOWN_IN_TOKEN)
+            })
+            .unwrap_or(0);
+        let normalized_in_amount = sale.to_normalized_in_amount(in_token_index, in_amount);
+        if let Some(min_deposit) = sale.min_deposit {
+            assert!(
+                normalized_in_amount >= min_deposit,
+                "{}",
+                errors::DEPOSIT_BELOW_MIN
+            );
+        }
         self.internal_distribute_unclaimed_tokens(&mut sale);
         let mut account = self.internal_unwrap_account(account_id);
         if !passed_permission_check {
@@ -187,24 +456,47 @@ impl Contract {
             referral_id,
             passed_permission_check,
         );
+        if let Some(referral_id) = referral_id {
+            self.internal_attribute_referral(
+                sale_id,
+                account_id,
+                sale.referral_attribution,
+                &mut subscription,
+                referral_id,
+                normalized_in_amount,
+            );
+        }
 
-        account.internal_token_withdraw(&sale.in_token_account_id, in_amount);
+        account
+            .internal_token_withdraw(&sale.in_tokens[in_token_index].token_account_id, in_amount);
         for out_token in &sale.out_tokens {
             self.internal_maybe_register_token(&mut account, &out_token.token_account_id);
         }
-        let remaining_in_balance = sale.shares_to_in_balance(subscription.shares);
-        subscription.spent_in_balance_without_shares +=
-            subscription.last_in_balance - remaining_in_balance;
-        let shares = sale.in_amount_to_shares(in_amount, false);
+        for in_token in &sale.in_tokens {
+            self.internal_maybe_register_token(&mut account, &in_token.token_account_id);
+        }
+        subscription.deposited_in_amount = subscription
+            .deposited_in_amount
+            .checked_add(normalized_in_amount)
+            .expect(errors::BALANCE_OVERFLOW);
+        if let Some(max_account_in_amount) = sale.max_account_in_amount {
+            assert!(
+                subscription.deposited_in_amount <= max_account_in_amount,
+                "{}",
+                errors::MAX_ACCOUNT_IN_AMOUNT
+            );
+        }
+        let shares = sale.in_amount_to_shares(normalized_in_amount, false);
         subscription.shares += shares;
+        subscription.in_token_shares[in_token_index] += shares;
         sale.total_shares += shares;
-        sale.in_token_remaining += in_amount;
-
-        subscription.last_in_balance = sale.shares_to_in_balance(subscription.shares);
+        sale.in_tokens[in_token_index].shares += shares;
+        sale.in_tokens[in_token_index].remaining += in_amount;
+        sale.update_in_token_remaining();
 
-        account.internal_save_subscription(sale_id, &sale, subscription);
+        self.internal_save_subscription(&mut account, account_id, sale_id, &mut sale, subscription);
         self.accounts.insert(account_id, &account.into());
-        self.sales.insert(&sale_id, &sale.into());
+        self.internal_save_sale(sale_id, sale);
         None
     }
 }
@@ -4,11 +4,14 @@ use crate::{
 };
 use near_contract_standards::fungible_token::core::ext_ft_core;
 use near_sdk::{
+    assert_one_yocto,
     borsh::{BorshDeserialize, BorshSerialize},
     collections::UnorderedMap,
     env,
     json_types::U128,
-    near_bindgen, AccountId, NearToken, Promise, PromiseOrValue, PromiseResult,
+    near_bindgen,
+    serde::{Deserialize, Serialize},
+    AccountId, NearToken, Promise, PromiseOrValue, PromiseResult,
 };
 
 #[derive(BorshDeserialize, BorshSerialize)]
@@ -22,6 +25,20 @@ pub struct Treasury {
 
     // The amount of NEAR locked while the permissions are being verified.
     pub locked_attached_deposits: u128,
+
+    /// Listing fees payable in tokens instead of NEAR.
+    pub listing_fees: UnorderedMap<AccountId, u128>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[borsh(crate = "near_sdk::borsh")]
+#[serde(crate = "near_sdk::serde")]
+pub enum ListingFee {
+    Near(U128),
+    Token {
+        token_account_id: AccountId,
+        amount: U128,
+    },
 }
 
 impl Treasury {
@@ -31,9 +48,25 @@ impl Treasury {
             listing_fee_near,
             w_near_token_id,
             locked_attached_deposits: 0,
+            listing_fees: UnorderedMap::new(StorageKey::TreasuryListingFees),
         }
     }
 
+    /// Returns the listing fee in the given token, or the NEAR listing fee if the token doesn't
+    /// have a listing fee.
+    pub fn get_listing_fee(&self, token_account_id: Option<&AccountId>) -> ListingFee {
+        token_account_id
+            .and_then(|token_account_id| {
+                self.listing_fees
+                    .get(token_account_id)
+                    .map(|amount| ListingFee::Token {
+                        token_account_id: token_account_id.clone(),
+                        amount: amount.into(),
+                    })
+            })
+            .unwrap_or(ListingFee::Near(self.listing_fee_near.into()))
+    }
+
     pub fn internal_deposit(&mut self, token_account_id: &AccountId, amount: u128) {
         let balance = self.balances.get(token_account_id).unwrap_or(0);
         let new_balance = balance.checked_add(amount).expect(errors::BALANCE_OVERFLOW);
@@ -52,6 +85,11 @@ impl Treasury {
 #[near_bindgen]
 impl Contract {
     pub fn claim_treasury(&mut self) -> PromiseOrValue<()> {
+        assert!(
+            !self.pause_flags.treasury_claims,
+            "{}",
+            errors::TREASURY_CLAIMS_PAUSED
+        );
         let mut promise: Option<Promise> = None;
         let mut token_ids = Vec::with_capacity(self.treasury.balances.len() as usize);
         for (token_id, balance) in self.treasury.balances.iter() {
@@ -130,7 +168,34 @@ impl Contract {
         self.treasury.listing_fee_near.into()
     }
 
+    /// Sets the listing fee in the given token. Removes it if the amount is not given.
+    #[payable]
+    pub fn set_listing_fee(&mut self, token_account_id: AccountId, amount: Option<U128>) {
+        assert_one_yocto();
+        self.assert_called_by_dao();
+        if let Some(amount) = amount {
+            self.treasury
+                .listing_fees
+                .insert(&token_account_id, &amount.0);
+        } else {
+            self.treasury.listing_fees.remove(&token_account_id);
+        }
+    }
+
+    pub fn get_listing_fees(&self) -> Vec<(AccountId, U128)> {
+        self.treasury
+            .listing_fees
+            .iter()
+            .map(|(token_account_id, amount)| (token_account_id, amount.into()))
+            .collect()
+    }
+
     pub fn wrap_extra_near(&mut self) -> Promise {
+        assert!(
+            !self.pause_flags.treasury_claims,
+            "{}",
+            errors::TREASURY_CLAIMS_PAUSED
+        );
         let unused_near_balance = env::account_balance().as_yoctonear()
             - env::storage_usage() as u128 * env::storage_byte_cost().as_yoctonear()
             - self.treasury.locked_attached_deposits;
@@ -8,6 +8,7 @@ pub(crate) const EXTRA_NEAR_FOR_STORAGE: u128 = 1000;
 pub(crate) const EXTRA_NEAR: u128 = EXTRA_NEAR_FOR_STORAGE + STORAGE_DEPOSIT;
 
 pub(crate) const AFTER_FT_TRANSFER_GAS: Gas = Gas::from_tgas(5);
+pub(crate) const AFTER_FT_TRANSFER_CALL_GAS: Gas = Gas::from_tgas(10);
 pub(crate) const AFTER_NEAR_DEPOSIT_GAS: Gas = Gas::from_tgas(5);
 
 pub(crate) const STORAGE_DEPOSIT_GAS: Gas = Gas::from_tgas(10);
@@ -19,6 +20,26 @@ pub(crate) const MAYBE_REFUND_DEPOSIT_GAS: Gas = Gas::from_tgas(10);
 
 pub(crate) const AFTER_CLAIM_TREASURY_GAS: Gas = Gas::from_tgas(15);
 
+pub(crate) const FT_METADATA_GAS: Gas = Gas::from_tgas(5);
+pub(crate) const STORAGE_BALANCE_OF_GAS: Gas = Gas::from_tgas(5);
+pub(crate) const AFTER_SALE_CREATE_GAS: Gas = Gas::from_tgas(50);
+
+/// Gas reserved to claim out tokens from one sale in a batch.
+pub(crate) const CLAIM_OUT_TOKENS_GAS: Gas = Gas::from_tgas(15);
+/// Gas reserved to withdraw one token after a batch claim.
+pub(crate) const WITHDRAW_TOKEN_GAS: Gas = Gas::from_tgas(20);
+/// Gas attached to every call of a sale hooks contract.
+pub(crate) const SALE_HOOK_GAS: Gas = Gas::from_tgas(10);
+
+pub(crate) const FT_TRANSFER_CALL_GAS: Gas = Gas::from_tgas(40);
+pub(crate) const ADD_SIMPLE_POOL_GAS: Gas = Gas::from_tgas(20);
+pub(crate) const ADD_LIQUIDITY_GAS: Gas = Gas::from_tgas(20);
+pub(crate) const AMM_WITHDRAW_GAS: Gas = Gas::from_tgas(20);
+pub(crate) const AFTER_AMM_WITHDRAW_GAS: Gas = Gas::from_tgas(5);
+pub(crate) const AFTER_ADD_LIQUIDITY_GAS: Gas = Gas::from_tgas(60);
+pub(crate) const AFTER_LIQUIDITY_DEPOSIT_GAS: Gas = Gas::from_tgas(100);
+pub(crate) const AFTER_ADD_SIMPLE_POOL_GAS: Gas = Gas::from_tgas(190);
+
 pub type BasicPoints = u16;
 
 pub(crate) fn refund_extra_storage_deposit(storage_used: StorageUsage, used_balance: u128) {
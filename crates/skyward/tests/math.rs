@@ -0,0 +1,546 @@
+//! Deterministic simulations of the sale math in a mocked blockchain. Every scenario is driven by a
+//! seeded generator, so a failing seed can be replayed by running the test again.
+
+use near_sdk::json_types::{U128, U64};
+use near_sdk::test_utils::VMContextBuilder;
+use near_sdk::{testing_env, AccountId, NearToken, Timestamp};
+use primitive_types::U256;
+use skyward::{
+    Contract, InTokenWeight, Sale, SaleInput, SaleInputInToken, SaleInputOutToken, Subscription,
+    VSubscription,
+};
+
+const SALE_ID: u64 = 0;
+const START_TIME: Timestamp = 1_000_000_000_000;
+const DURATION: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+const OUT_SUPPLY: u128 = 1_000_000_000 * 10u128.pow(18);
+const NUM_USERS: usize = 5;
+const NUM_SEEDS: u64 = 32;
+const NUM_STEPS: usize = 60;
+
+/// xorshift64*, which is good enough to pick operations and amounts.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    /// Amounts spread over many orders of magnitude to hit the rounding edges.
+    fn amount(&mut self) -> u128 {
+        let magnitude = self.below(25) as u32;
+        (self.next() as u128 % 10u128.pow(magnitude)) + 1
+    }
+}
+
+fn mul_div(a: u128, b: u128, c: u128) -> u128 {
+    (U256::from(a) * U256::from(b) / U256::from(c)).as_u128()
+}
+
+fn account(name: &str) -> AccountId {
+    name.parse().unwrap()
+}
+
+fn w_near() -> AccountId {
+    account("wrap.near")
+}
+
+fn st_near() -> AccountId {
+    account("meta-pool.near")
+}
+
+fn out_token() -> AccountId {
+    account("token.near")
+}
+
+fn owner() -> AccountId {
+    account("owner.near")
+}
+
+fn user(index: usize) -> AccountId {
+    account(&format!("user{}.near", index))
+}
+
+/// Runs the scenario of every seed on a fresh thread, since the mocked blockchain storage is
+/// thread local and survives `testing_env!`.
+fn for_each_seed(scenario: fn(Rng)) {
+    for seed in 0..NUM_SEEDS {
+        std::thread::spawn(move || scenario(Rng::new(seed)))
+            .join()
+            .unwrap_or_else(|_| panic!("seed {} failed", seed));
+    }
+}
+
+fn set_context(predecessor_id: &AccountId, timestamp: Timestamp, attached_deposit: NearToken) {
+    testing_env!(VMContextBuilder::new()
+        .current_account_id(account("skyward.near"))
+        .predecessor_account_id(predecessor_id.clone())
+        .block_timestamp(timestamp)
+        .attached_deposit(attached_deposit)
+        .build());
+}
+
+fn sale_input(referral_bpt: Option<u16>) -> SaleInput {
+    SaleInput {
+        title: "math".to_string(),
+        url: None,
+        permissions_contract_id: None,
+        hooks_contract_id: None,
+        owner_id: None,
+        proceeds_receiver_id: None,
+        out_tokens: vec![SaleInputOutToken {
+            token_account_id: out_token(),
+            balance: U128(OUT_SUPPLY),
+            referral_bpt,
+            referrer_bpt: None,
+            second_level_referrer_bpt: None,
+            referral_payout: None,
+        }],
+        in_tokens: vec![SaleInputInToken {
+            token_account_id: w_near(),
+            weight: InTokenWeight::ONE,
+        }],
+        listing_fee_token_account_id: None,
+        min_deposit: None,
+        max_account_in_amount: None,
+        withdrawal_lock_duration: None,
+        early_exit_penalty: None,
+        referral_attribution: None,
+        liquidity: None,
+        proceeds_lock: None,
+        history_interval: None,
+        start_time: U64(START_TIME),
+        duration: U64(DURATION),
+    }
+}
+
+/// Runs the contract directly, bypassing the cross-contract calls of the sale creation and
+/// token transfers. The out tokens of the sale are minted out of thin air.
+struct Simulation {
+    contract: Contract,
+    timestamp: Timestamp,
+    in_supply: u128,
+    /// The number of subscription updates, each of which can lose a yocto of every out token
+    /// to rounding.
+    num_touches: u128,
+}
+
+impl Simulation {
+    fn new(referral_bpt: Option<u16>) -> Self {
+        Self::with_input(sale_input(referral_bpt))
+    }
+
+    fn with_input(input: SaleInput) -> Self {
+        set_context(&account("dao.near"), 0, NearToken::from_near(0));
+        let mut sim = Self {
+            contract: Contract::new(account("dao.near"), U128(0), w_near()),
+            timestamp: 0,
+            in_supply: 0,
+            num_touches: 0,
+        };
+        for account_id in std::iter::once(owner()).chain((0..NUM_USERS).map(user)) {
+            set_context(&account_id, 0, NearToken::from_near(1));
+            sim.contract
+                .register_tokens(None, vec![w_near(), st_near(), out_token()]);
+        }
+        set_context(&owner(), 0, NearToken::from_near(0));
+        let sale = Sale::from_input(input, owner());
+        sim.contract.sale_index.internal_add_sale(SALE_ID, &sale);
+        sim.contract.sales.insert(&SALE_ID, &sale.into());
+        sim.contract.num_sales = 1;
+        sim
+    }
+
+    fn sale(&self) -> Sale {
+        self.contract.internal_unwrap_sale(SALE_ID)
+    }
+
+    fn balance(&self, account_id: &AccountId, token_account_id: &AccountId) -> u128 {
+        self.contract
+            .internal_unwrap_account(account_id)
+            .balances
+            .get(token_account_id)
+            .unwrap_or(0)
+    }
+
+    fn treasury_balance(&self, token_account_id: &AccountId) -> u128 {
+        self.contract
+            .treasury
+            .balances
+            .get(token_account_id)
+            .unwrap_or(0)
+    }
+
+    fn subscription(&self, account_id: &AccountId) -> Option<Subscription> {
+        self.contract
+            .internal_unwrap_account(account_id)
+            .subs
+            .get(&SALE_ID)
+            .map(|s: VSubscription| s.into())
+    }
+
+    fn advance(&mut self, duration: u64) {
+        self.timestamp += duration;
+        set_context(&owner(), self.timestamp, NearToken::from_near(0));
+    }
+
+    fn deposit(&mut self, account_id: &AccountId, amount: u128, referral_id: Option<&AccountId>) {
+        self.deposit_in_token(account_id, &w_near(), amount, referral_id);
+    }
+
+    /// Only the first in token counts towards the supply checked by the conservation asserts.
+    fn deposit_in_token(
+        &mut self,
+        account_id: &AccountId,
+        token_account_id: &AccountId,
+        amount: u128,
+        referral_id: Option<&AccountId>,
+    ) {
+        set_context(account_id, self.timestamp, NearToken::from_near(0));
+        let mut account = self.contract.internal_unwrap_account(account_id);
+        account.internal_token_deposit(token_account_id, amount);
+        self.contract.accounts.insert(account_id, &account.into());
+        if token_account_id == &w_near() {
+            self.in_supply += amount;
+        }
+        self.num_touches += 1;
+        self.contract.internal_deposit_in_amount(
+            SALE_ID,
+            account_id,
+            Some(token_account_id),
+            amount,
+            referral_id,
+            false,
+        );
+    }
+
+    fn withdraw(&mut self, account_id: &AccountId, shares: Option<u128>) {
+        set_context(account_id, self.timestamp, NearToken::from_near(0));
+        self.num_touches += 1;
+        self.contract
+            .internal_withdraw_shares(SALE_ID, account_id, shares);
+    }
+
+    fn claim(&mut self, account_id: &AccountId) {
+        set_context(account_id, self.timestamp, NearToken::from_near(0));
+        let mut sale = self.sale();
+        self.contract
+            .internal_distribute_unclaimed_tokens(&mut sale);
+        let mut account = self.contract.internal_unwrap_account(account_id);
+        if account.subs.get(&SALE_ID).is_some() {
+            self.num_touches += 1;
+            let subscription = self.contract.internal_update_subscription(
+                &mut account,
+                SALE_ID,
+                &mut sale,
+                None,
+                false,
+            );
+            self.contract.internal_save_subscription(
+                &mut account,
+                account_id,
+                SALE_ID,
+                &mut sale,
+                subscription,
+            );
+            self.contract.accounts.insert(account_id, &account.into());
+        }
+        self.contract.internal_save_sale(SALE_ID, sale);
+    }
+
+    fn claim_all(&mut self) {
+        for index in 0..NUM_USERS {
+            self.claim(&user(index));
+        }
+    }
+
+    fn account_ids(&self) -> Vec<AccountId> {
+        std::iter::once(owner())
+            .chain((0..NUM_USERS).map(user))
+            .collect()
+    }
+
+    /// Every out token is either claimed by an account, including the referral fees, taken by
+    /// the treasury, or still held by the sale. Only the rounding dust of subscription updates
+    /// can be missing. Expects all subscriptions to be claimed up to the current time.
+    fn assert_out_token_conservation(&self) {
+        let sale = self.sale();
+        let out_token = &sale.out_tokens[0];
+        let claimed: u128 = self
+            .account_ids()
+            .iter()
+            .map(|account_id| self.balance(account_id, &out_token.token_account_id))
+            .sum();
+        let accounted = claimed
+            + self.treasury_balance(&out_token.token_account_id)
+            + out_token.treasury_unclaimed
+            + out_token.remaining;
+        assert_eq!(out_token.distributed + out_token.remaining, OUT_SUPPLY);
+        assert!(
+            accounted <= OUT_SUPPLY,
+            "out tokens created: {} > {}",
+            accounted,
+            OUT_SUPPLY
+        );
+        assert!(
+            OUT_SUPPLY - accounted <= self.num_touches,
+            "out tokens lost: {} > {}",
+            OUT_SUPPLY - accounted,
+            self.num_touches
+        );
+    }
+
+    /// Every in token is either in an account balance, taken by the treasury, or held by the
+    /// sale as remaining, paid but not yet distributed, or reserved for referrals.
+    fn assert_in_token_conservation(&self) {
+        let sale = self.sale();
+        let in_token = &sale.in_tokens[0];
+        let balances: u128 = self
+            .account_ids()
+            .iter()
+            .map(|account_id| self.balance(account_id, &in_token.token_account_id))
+            .sum();
+        assert_eq!(
+            balances
+                + self.treasury_balance(&in_token.token_account_id)
+                + in_token.remaining
+                + in_token.paid_unclaimed
+                + in_token.referral_reserve,
+            self.in_supply
+        );
+        assert_eq!(in_token.remaining, sale.in_token_remaining);
+        assert_eq!(in_token.paid, sale.in_token_paid);
+    }
+
+    /// Runs random deposits, withdrawals and claims over the whole sale and checks the
+    /// conservation of both tokens after every step.
+    fn run(&mut self, rng: &mut Rng) {
+        self.advance(START_TIME);
+        for _ in 0..NUM_STEPS {
+            let index = rng.below(NUM_USERS as u64) as usize;
+            let account_id = user(index);
+            match rng.below(4) {
+                0 | 1 => {
+                    let referral_id = match rng.below(3) {
+                        0 => Some(user((index + 1) % NUM_USERS)),
+                        _ => None,
+                    };
+                    let amount = rng.amount();
+                    self.deposit(&account_id, amount, referral_id.as_ref());
+                }
+                2 => {
+                    let shares = self
+                        .subscription(&account_id)
+                        .map(|subscription| subscription.shares)
+                        .unwrap_or(0);
+                    if shares > 0 {
+                        let shares = match rng.below(2) {
+                            0 => None,
+                            _ => Some(rng.next() as u128 % shares + 1),
+                        };
+                        self.withdraw(&account_id, shares);
+                    }
+                }
+                _ => self.claim(&account_id),
+            }
+            self.advance(rng.below(DURATION / NUM_STEPS as u64 * 2));
+            if self.timestamp >= START_TIME + DURATION {
+                break;
+            }
+            self.claim_all();
+            self.assert_out_token_conservation();
+            self.assert_in_token_conservation();
+        }
+        self.advance(DURATION);
+        self.claim_all();
+        let sale = self.sale();
+        assert!(sale.has_ended());
+        assert_eq!(sale.out_tokens[0].remaining, 0);
+        assert_eq!(sale.out_tokens[0].distributed, OUT_SUPPLY);
+        assert_eq!(sale.in_token_remaining, 0);
+        self.assert_out_token_conservation();
+        self.assert_in_token_conservation();
+    }
+}
+
+#[test]
+fn test_conservation_without_referrals() {
+    for_each_seed(|mut rng| Simulation::new(None).run(&mut rng));
+}
+
+#[test]
+fn test_conservation_with_referrals() {
+    for_each_seed(|mut rng| {
+        let referral_bpt = 1 + rng.below(1000) as u16;
+        Simulation::new(Some(referral_bpt)).run(&mut rng);
+    });
+}
+
+#[test]
+fn test_sale_touch_is_split_independent() {
+    for_each_seed(|mut rng| {
+        let mut sim = Simulation::new(None);
+        sim.advance(START_TIME);
+        sim.deposit(&user(0), rng.amount(), None);
+        let mut stepped = sim.sale();
+        let mut num_steps = 0;
+        while sim.timestamp < START_TIME + DURATION {
+            sim.advance(rng.below(DURATION / 10));
+            stepped.touch();
+            num_steps += 1;
+
+            let mut at_once = sim.sale();
+            at_once.touch();
+            let stepped_distributed = stepped.out_tokens[0].distributed;
+            let at_once_distributed = at_once.out_tokens[0].distributed;
+            assert!(stepped_distributed.abs_diff(at_once_distributed) <= num_steps);
+            assert_eq!(
+                stepped.in_token_paid + stepped.in_token_remaining,
+                at_once.in_token_paid + at_once.in_token_remaining
+            );
+            let treasury_fee = stepped.out_tokens[0].treasury_unclaimed;
+            assert!(treasury_fee <= stepped_distributed / 100);
+            assert!(stepped_distributed / 100 - treasury_fee <= num_steps);
+        }
+        assert_eq!(stepped.out_tokens[0].distributed, OUT_SUPPLY);
+        assert_eq!(stepped.in_token_remaining, 0);
+    });
+}
+
+#[test]
+fn test_subscription_touch_matches_shares() {
+    for_each_seed(|mut rng| {
+        let mut sim = Simulation::new(None);
+        sim.advance(START_TIME);
+        for index in 0..NUM_USERS {
+            sim.deposit(&user(index), rng.amount(), None);
+        }
+        sim.advance(rng.below(DURATION));
+        let mut sale = sim.sale();
+        sale.touch();
+        let distributed = sale.out_tokens[0].distributed - sale.out_tokens[0].treasury_unclaimed;
+        let paid = sale.in_token_paid;
+
+        let mut total_out = 0;
+        let mut total_spent = 0;
+        for index in 0..NUM_USERS {
+            let mut subscription = sim.subscription(&user(index)).unwrap();
+            let initial_spent = subscription.spent_in_balances[0];
+            let out_amount = subscription.touch(&sale)[0];
+            let spent = subscription.spent_in_balances[0] - initial_spent;
+            // Out tokens and spent in tokens are proportional to the shares.
+            let expected_out = mul_div(distributed, subscription.shares, sale.total_shares);
+            let expected_spent = mul_div(paid, subscription.shares, sale.total_shares);
+            assert!(expected_out.abs_diff(out_amount) <= 1);
+            assert!(expected_spent.abs_diff(spent) <= 1);
+            // Touching again at the same time is a no-op.
+            assert_eq!(subscription.touch(&sale)[0], 0);
+            total_out += out_amount;
+            total_spent += spent;
+        }
+        assert!(total_out <= distributed && distributed - total_out <= NUM_USERS as u128);
+        assert!(total_spent <= paid && paid - total_spent <= NUM_USERS as u128);
+    });
+}
+
+#[test]
+fn test_in_amount_to_shares_round_trip() {
+    for_each_seed(|mut rng| {
+        let mut sim = Simulation::new(None);
+        sim.advance(START_TIME);
+        sim.deposit(&user(0), rng.amount(), None);
+        sim.advance(rng.below(DURATION - 1));
+        sim.claim(&user(0));
+        let sale = sim.sale();
+        if sale.in_token_remaining == 0 {
+            return;
+        }
+        for _ in 0..NUM_STEPS {
+            let in_amount = rng.amount() % sale.in_token_remaining + 1;
+            let shares = sale.in_amount_to_shares(in_amount, false);
+            let shares_up = sale.in_amount_to_shares(in_amount, true);
+            assert!(shares <= shares_up && shares_up - shares <= 1);
+            // Deposits never get more than they paid for, withdrawals never take less shares than
+            // the amount they withdraw.
+            assert!(sale.shares_to_in_balance(shares) <= in_amount);
+            assert!(sale.shares_to_in_balance(shares_up) >= in_amount);
+        }
+    });
+}
+
+#[test]
+fn test_withdrawals_return_deposited_in_tokens() {
+    for_each_seed(|mut rng| {
+        let mut input = sale_input(None);
+        input.in_tokens.push(SaleInputInToken {
+            token_account_id: st_near(),
+            weight: InTokenWeight {
+                numerator: 6,
+                denominator: 5,
+            },
+        });
+        let mut sim = Simulation::with_input(input);
+        sim.advance(START_TIME);
+        let w_near_amount = 10u128.pow(18) + rng.amount();
+        let st_near_amount = 10u128.pow(18) + rng.amount();
+        sim.deposit(&user(0), w_near_amount, None);
+        sim.deposit_in_token(&user(1), &st_near(), st_near_amount, None);
+        sim.advance(rng.below(DURATION / 2));
+        sim.withdraw(&user(0), None);
+        sim.withdraw(&user(1), None);
+
+        // Every subscriber gets back only the remaining part of the token they deposited.
+        let sale = sim.sale();
+        assert_eq!(sim.balance(&user(0), &st_near()), 0);
+        assert_eq!(sim.balance(&user(1), &w_near()), 0);
+        assert_eq!(
+            sim.balance(&user(0), &w_near()) + sale.in_tokens[0].paid,
+            w_near_amount
+        );
+        assert_eq!(
+            sim.balance(&user(1), &st_near()) + sale.in_tokens[1].paid,
+            st_near_amount
+        );
+        assert_eq!(sale.total_shares, 0);
+        assert!(sale.in_tokens.iter().all(|in_token| in_token.shares == 0));
+        assert_eq!(sale.in_token_remaining, 0);
+    });
+}
+
+#[test]
+fn test_resume_caps_duration() {
+    const MAX_DURATION: u64 = 4 * 366 * 24 * 60 * 60 * 1_000_000_000;
+    let mut input = sale_input(None);
+    input.duration = U64(MAX_DURATION);
+    let mut sim = Simulation::with_input(input);
+    sim.advance(START_TIME);
+    sim.deposit(&user(0), 10u128.pow(24), None);
+    sim.advance(MAX_DURATION / 2);
+    let mut sale = sim.sale();
+    sale.paused_at = Some(sim.timestamp);
+    sim.contract.internal_save_sale(SALE_ID, sale);
+
+    sim.advance(DURATION);
+    let mut sale = sim.sale();
+    sale.resume();
+    // The sale can't be extended beyond the maximum duration.
+    assert_eq!(sale.duration, MAX_DURATION);
+    sim.contract.internal_save_sale(SALE_ID, sale);
+
+    sim.advance(MAX_DURATION / 2);
+    let sale = sim.sale();
+    assert!(sale.has_ended());
+    assert_eq!(sale.out_tokens[0].distributed, OUT_SUPPLY);
+    assert_eq!(sale.in_token_remaining, 0);
+}
@@ -1,23 +1,38 @@
 mod util;
 
-use near_sdk::{json_types::U128, serde_json::json, Gas, NearToken};
+use near_sdk::{
+    json_types::{U128, U64},
+    serde_json::json,
+    Gas, NearToken,
+};
 use near_workspaces::{
     types::{KeyType, SecretKey},
     AccountId,
 };
-use skyward::{SaleInput, SaleInputOutToken, SaleOutput, SaleOutputOutToken, SubscriptionOutput};
+use primitive_types::U256;
+use skyward::{
+    AccountPortfolioOutput, ClaimOutTokensBatchOutput, EarlyExitPenalty,
+    EscrowedReferralRewardOutput, InTokenWeight, LiquidityStatus, PauseFlags, PenaltyReceiver,
+    ProceedsLockInput, ProceedsLockOutput, ReferralAttribution, ReferralEarningOutput,
+    ReferralLeaderboardEntry, ReferralPayout, ReferralStatsOutput, SaleCheckpointOutput, SaleInput,
+    SaleInputInToken, SaleInputLiquidity, SaleInputOutToken, SaleOutput, SaleOutputInToken,
+    SaleOutputOutToken, SalePriceOutput, SaleStatus, SimulatedDepositOutput, SubscriptionOutput,
+    SubscriptionOutputInToken,
+};
 use util::*;
 
 const SKYWARD_WASM_BYTES: &[u8] = include_bytes!("../../../res/skyward_testing.wasm");
 const FUNGIBLE_TOKEN_WASM_BYTES: &[u8] = include_bytes!("../../../common/fungible_token.wasm");
 const W_NEAR_WASM_BYTES: &[u8] = include_bytes!("../../../common/w_near.wasm");
 const PERMISSIONS_WASM_BYTES: &[u8] = include_bytes!("../../../res/permissions.wasm");
+const MOCK_AMM_WASM_BYTES: &[u8] = include_bytes!("../../../res/mock_amm.wasm");
 
 const TITLE: &str = "sale title";
 const SKYWARD_ID: &str = "skyward.test.near";
 const WRAP_NEAR_ID: &str = "wrap.test.near";
 const SKYWARD_DAO_ID: &str = "skyward-dao.test.near";
 const PERMISSIONS_CONTRACT_ID: &str = "kyc.test.near";
+const MOCK_AMM_ID: &str = "amm.test.near";
 
 const TOKEN1_ID: &str = "token1.test.near";
 
@@ -198,6 +213,13 @@ async fn test_create_sale() -> anyhow::Result<()> {
         )
         .await?;
 
+    let out_token_metadata = sale.out_tokens[0].metadata.as_ref().unwrap();
+    assert_eq!(out_token_metadata.symbol, "EXAMPLE");
+    assert_eq!(out_token_metadata.decimals, 24);
+    let in_token_metadata = sale.in_tokens[0].metadata.as_ref().unwrap();
+    assert_eq!(in_token_metadata.symbol, "wNEAR");
+    assert_eq!(in_token_metadata.decimals, 24);
+
     let current_block = environment.worker.view_block().await?;
     assert_eq!(
         sale,
@@ -206,18 +228,43 @@ async fn test_create_sale() -> anyhow::Result<()> {
             title: TITLE.to_string(),
             url: None,
             permissions_contract_id: None,
+            hooks_contract_id: None,
             owner_id: alice.id().parse()?,
+            pending_owner_id: None,
+            proceeds_receiver_id: alice.id().parse()?,
             out_tokens: vec![SaleOutputOutToken {
                 token_account_id: token1.id().parse()?,
                 remaining: NearToken::from_near(4000).as_yoctonear().into(),
                 distributed: 0.into(),
                 treasury_unclaimed: 0.into(),
-                referral_bpt: None
+                referral_bpt: None,
+                referrer_bpt: None,
+                second_level_referrer_bpt: None,
+                referral_payout: ReferralPayout::OutToken,
+                metadata: sale.out_tokens[0].metadata.clone(),
+            }],
+            in_tokens: vec![SaleOutputInToken {
+                token_account_id: environment.w_near.id().parse()?,
+                weight: InTokenWeight::ONE,
+                remaining: U128(0),
+                paid_unclaimed: U128(0),
+                paid: U128(0),
+                referral_reserve: U128(0),
+                metadata: sale.in_tokens[0].metadata.clone(),
             }],
-            in_token_account_id: environment.w_near.id().parse()?,
             in_token_remaining: U128(0),
             in_token_paid_unclaimed: U128(0),
             in_token_paid: U128(0),
+            min_deposit: None,
+            max_account_in_amount: None,
+            withdrawal_lock_duration: None,
+            early_exit_penalty: None,
+            referral_attribution: ReferralAttribution::FirstReferrer,
+            liquidity: None,
+            proceeds_lock: None,
+            history_interval: (BLOCK_DURATION * 60 / 64).into(),
+            paused_at: None,
+            verified: false,
             total_shares: U128(0),
             start_time: start_time.into(),
             duration: (BLOCK_DURATION * 60).into(),
@@ -231,58 +278,2933 @@ async fn test_create_sale() -> anyhow::Result<()> {
     );
 
     assert_eq!(
-        environment.balances_of(alice).await?,
-        vec![
-            (
-                environment.w_near.id().clone(),
-                NearToken::from_near(10).as_yoctonear()
-            ),
-            (
-                token1.id().clone(),
-                NearToken::from_near(6000).as_yoctonear()
-            ),
-        ]
+        environment.balances_of(alice).await?,
+        vec![
+            (
+                environment.w_near.id().clone(),
+                NearToken::from_near(10).as_yoctonear()
+            ),
+            (
+                token1.id().clone(),
+                NearToken::from_near(6000).as_yoctonear()
+            ),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_create_sale_with_unregistered_token() -> anyhow::Result<()> {
+    let environment = Env::init(1).await?;
+    let alice = environment.users.first().unwrap();
+
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_near(10_000))
+        .await?;
+    log_tx_result(
+        "storage_unregister",
+        environment
+            .skyward
+            .as_account()
+            .call(token1.id(), "storage_unregister")
+            .args_json(json!({ "force": true }))
+            .deposit(NearToken::from_yoctonear(1))
+            .transact()
+            .await?,
+    )?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let initial_near_balance = alice.view_account().await?.balance;
+    let sale_id: Option<u64> = log_tx_result(
+        "sale_create",
+        alice
+            .call(environment.skyward.id(), "sale_create")
+            .args_json((environment.sale_input(
+                &[(
+                    token1.as_account(),
+                    NearToken::from_near(4000).as_yoctonear(),
+                )],
+                current_time + BLOCK_DURATION * 15,
+                BLOCK_DURATION * 60,
+            )?,))
+            .deposit(NearToken::from_near(11))
+            .max_gas()
+            .transact()
+            .await?,
+    )?
+    .0
+    .json()?;
+    assert_eq!(sale_id, None);
+
+    // The reserved tokens and the listing fee are refunded.
+    assert_eq!(
+        environment.balances_of(alice).await?,
+        vec![
+            (
+                environment.w_near.id().clone(),
+                NearToken::from_near(10).as_yoctonear()
+            ),
+            (
+                token1.id().clone(),
+                NearToken::from_near(10_000).as_yoctonear()
+            ),
+        ]
+    );
+    let near_spent = initial_near_balance
+        .checked_sub(alice.view_account().await?.balance)
+        .unwrap();
+    assert!(near_spent < NearToken::from_millinear(100));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_create_sale_with_token_listing_fee() -> anyhow::Result<()> {
+    let environment = Env::init(1).await?;
+    let alice = environment.users.first().unwrap();
+
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_near(10_000))
+        .await?;
+    environment
+        .dao_call(
+            "set_listing_fee",
+            json!({
+                "token_account_id": token1.id(),
+                "amount": U128(NearToken::from_near(100).as_yoctonear()),
+            }),
+        )
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let mut sale_input = environment.sale_input(
+        &[(
+            token1.as_account(),
+            NearToken::from_near(4000).as_yoctonear(),
+        )],
+        current_time + BLOCK_DURATION * 15,
+        BLOCK_DURATION * 60,
+    )?;
+    sale_input.listing_fee_token_account_id = Some(token1.id().parse()?);
+    let initial_near_balance = alice.view_account().await?.balance;
+    let sale_id: Option<u64> = log_tx_result(
+        "sale_create",
+        alice
+            .call(environment.skyward.id(), "sale_create")
+            .args_json((sale_input,))
+            .deposit(NearToken::from_near(1))
+            .max_gas()
+            .transact()
+            .await?,
+    )?
+    .0
+    .json()?;
+    assert_eq!(sale_id, Some(0));
+
+    // Only the storage is paid in NEAR.
+    let near_spent = initial_near_balance
+        .checked_sub(alice.view_account().await?.balance)
+        .unwrap();
+    assert!(near_spent < NearToken::from_millinear(200));
+    assert_eq!(
+        environment.balances_of(alice).await?,
+        vec![
+            (
+                environment.w_near.id().clone(),
+                NearToken::from_near(10).as_yoctonear()
+            ),
+            (
+                token1.id().clone(),
+                NearToken::from_near(5_900).as_yoctonear()
+            ),
+        ]
+    );
+    let treasury_balance: Option<U128> = environment
+        .worker
+        .view(environment.skyward.id(), "get_treasury_balance")
+        .args_json((token1.id(),))
+        .await?
+        .json()?;
+    assert_eq!(
+        treasury_balance,
+        Some(U128(NearToken::from_near(100).as_yoctonear()))
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_join_sale() -> anyhow::Result<()> {
+    let environment = Env::init(2).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_near(10_000))
+        .await?;
+
+    let start_offset = BLOCK_DURATION * 15;
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let start_time = current_time + start_offset;
+    let sale = environment
+        .sale_create(
+            alice,
+            &[(
+                token1.as_account(),
+                NearToken::from_near(3_600).as_yoctonear(),
+            )],
+            start_time,
+        )
+        .await?;
+
+    log_tx_result(
+        "sale_deposit_in_token",
+        bob.call(environment.skyward.id(), "sale_deposit_in_token")
+            .args_json((
+                sale.sale_id,
+                U128(NearToken::from_near(4).as_yoctonear()),
+                None::<AccountId>,
+                None::<AccountId>,
+            ))
+            .deposit(NearToken::from_millinear(10))
+            .transact()
+            .await?,
+    )?;
+
+    let bobs_sale = environment.get_sale(0, Some(bob.id().clone())).await?;
+    assert_eq!(
+        bobs_sale.in_token_remaining.0,
+        NearToken::from_near(4).as_yoctonear()
+    );
+    assert_eq!(
+        bobs_sale.total_shares.0,
+        NearToken::from_near(4).as_yoctonear()
+    );
+    assert_eq!(
+        bobs_sale.subscription,
+        Some(SubscriptionOutput {
+            claimed_out_balance: vec![0.into()],
+            spent_in_balance: 0.into(),
+            remaining_in_balance: NearToken::from_near(4).as_yoctonear().into(),
+            unclaimed_out_balances: vec![U128(0)],
+            shares: NearToken::from_near(4).as_yoctonear().into(),
+            referral_id: None,
+            referral_weights: vec![],
+            in_tokens: vec![SubscriptionOutputInToken {
+                remaining_in_balance: NearToken::from_near(4).as_yoctonear().into(),
+                spent_in_balance: 0.into(),
+            }],
+        })
+    );
+
+    assert_eq!(
+        environment.balances_of(bob).await?,
+        vec![
+            (
+                environment.w_near.id().clone(),
+                NearToken::from_near(6).as_yoctonear()
+            ),
+            (token1.id().clone(), 0),
+        ]
+    );
+
+    environment.worker.fast_forward(500).await?;
+
+    let bobs_sale = environment.get_sale(0, Some(bob.id().clone())).await?;
+
+    environment.assert_sale_eq(
+        &bobs_sale,
+        PartialSale {
+            out_tokens: vec![PartialOutToken {
+                remaining: 0.into(),
+                distributed: NearToken::from_near(3_600).as_yoctonear().into(),
+                treasury_unclaimed: NearToken::from_near(36).as_yoctonear().into(),
+            }],
+            in_token_remaining: 0.into(),
+            in_token_paid_unclaimed: NearToken::from_near(4).as_yoctonear().into(),
+            in_token_paid: NearToken::from_near(4).as_yoctonear().into(),
+            total_shares: NearToken::from_near(4).as_yoctonear().into(),
+            subscription: Some(SubscriptionOutput {
+                claimed_out_balance: vec![0.into()],
+                spent_in_balance: NearToken::from_near(4).as_yoctonear().into(),
+                remaining_in_balance: 0.into(),
+                unclaimed_out_balances: vec![NearToken::from_near(3564).as_yoctonear().into()],
+                shares: NearToken::from_near(4).as_yoctonear().into(),
+                referral_id: None,
+                referral_weights: vec![],
+                in_tokens: vec![SubscriptionOutputInToken {
+                    remaining_in_balance: 0.into(),
+                    spent_in_balance: NearToken::from_near(4).as_yoctonear().into(),
+                }],
+            }),
+        },
+    );
+
+    assert_eq!(
+        environment.get_treasury_balances().await?,
+        vec![
+            (environment.w_near.id().clone(), 0),
+            (token1.id().clone(), 0),
+        ]
+    );
+
+    log_tx_result(
+        "sale_distribute_unclaimed_tokens",
+        alice
+            .call(environment.skyward.id(), "sale_distribute_unclaimed_tokens")
+            .args_json((0,))
+            .transact()
+            .await?,
+    )?;
+
+    assert_eq!(
+        environment.balances_of(alice).await?,
+        vec![
+            (
+                environment.w_near.id().clone(),
+                NearToken::from_millinear(13_960).as_yoctonear()
+            ),
+            (
+                token1.id().clone(),
+                NearToken::from_near(6400).as_yoctonear()
+            ),
+        ]
+    );
+    assert_eq!(
+        environment.get_treasury_balances().await?,
+        vec![
+            (
+                environment.w_near.id().clone(),
+                NearToken::from_millinear(40).as_yoctonear()
+            ),
+            (token1.id().clone(), NearToken::from_near(36).as_yoctonear()),
+        ]
+    );
+    assert_eq!(
+        environment.balances_of(bob).await?,
+        vec![
+            (
+                environment.w_near.id().clone(),
+                NearToken::from_near(6).as_yoctonear()
+            ),
+            (token1.id().clone(), 0),
+        ]
+    );
+
+    let bobs_sale = environment.get_sale(0, Some(bob.id().clone())).await?;
+
+    environment.assert_sale_eq(
+        &bobs_sale,
+        PartialSale {
+            out_tokens: vec![PartialOutToken {
+                remaining: 0.into(),
+                distributed: NearToken::from_near(3600).as_yoctonear().into(),
+                treasury_unclaimed: 0.into(),
+            }],
+            in_token_remaining: 0.into(),
+            in_token_paid_unclaimed: 0.into(),
+            in_token_paid: NearToken::from_near(4).as_yoctonear().into(),
+            total_shares: NearToken::from_near(4).as_yoctonear().into(),
+            subscription: Some(SubscriptionOutput {
+                claimed_out_balance: vec![0.into()],
+                spent_in_balance: NearToken::from_near(4).as_yoctonear().into(),
+                remaining_in_balance: 0.into(),
+                unclaimed_out_balances: vec![NearToken::from_near(3564).as_yoctonear().into()],
+                shares: NearToken::from_near(4).as_yoctonear().into(),
+                referral_id: None,
+                referral_weights: vec![],
+                in_tokens: vec![SubscriptionOutputInToken {
+                    remaining_in_balance: 0.into(),
+                    spent_in_balance: NearToken::from_near(4).as_yoctonear().into(),
+                }],
+            }),
+        },
+    );
+
+    log_tx_result(
+        "sale_claim_out_tokens",
+        bob.call(environment.skyward.id(), "sale_claim_out_tokens")
+            .args_json((0,))
+            .transact()
+            .await?,
+    )?;
+
+    let bobs_sale = environment.get_sale(0, Some(bob.id().clone())).await?;
+
+    environment.assert_sale_eq(
+        &bobs_sale,
+        PartialSale {
+            out_tokens: vec![PartialOutToken {
+                remaining: 0.into(),
+                distributed: NearToken::from_near(3600).as_yoctonear().into(),
+                treasury_unclaimed: 0.into(),
+            }],
+            in_token_remaining: 0.into(),
+            in_token_paid_unclaimed: 0.into(),
+            in_token_paid: NearToken::from_near(4).as_yoctonear().into(),
+            total_shares: 0.into(),
+            subscription: None,
+        },
+    );
+
+    assert_eq!(
+        environment.get_treasury_balances().await?,
+        vec![
+            (
+                environment.w_near.id().clone(),
+                NearToken::from_millinear(40).as_yoctonear()
+            ),
+            (token1.id().clone(), NearToken::from_near(36).as_yoctonear()),
+        ]
+    );
+    assert_eq!(
+        environment.balances_of(bob).await?,
+        vec![
+            (
+                environment.w_near.id().clone(),
+                NearToken::from_near(6).as_yoctonear()
+            ),
+            (
+                token1.id().clone(),
+                NearToken::from_near(3564).as_yoctonear()
+            ),
+        ]
+    );
+
+    environment
+        .storage_deposit(&token1, bob, None, Some(NearToken::from_millinear(50)))
+        .await?;
+    environment.withdraw_token(bob, token1.id(), None).await?;
+
+    assert_eq!(
+        environment.balances_of(bob).await?,
+        vec![
+            (
+                environment.w_near.id().clone(),
+                NearToken::from_near(6).as_yoctonear()
+            ),
+            (token1.id().clone(), 0),
+        ]
+    );
+    assert_eq!(
+        environment.ft_balance_of(bob, token1.id()).await?,
+        NearToken::from_near(3564).as_yoctonear()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sale_deposit_limits() -> anyhow::Result<()> {
+    let environment = Env::init(2).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_near(10_000))
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let start_time = current_time + BLOCK_DURATION * 15;
+    let tokens = [(
+        token1.as_account(),
+        NearToken::from_near(3_600).as_yoctonear(),
+    )];
+
+    let mut sale_input = environment.sale_input(&tokens, start_time, BLOCK_DURATION * 60)?;
+    sale_input.min_deposit = Some(NearToken::from_near(6).as_yoctonear().into());
+    sale_input.max_account_in_amount = Some(NearToken::from_near(5).as_yoctonear().into());
+    assert!(environment
+        .sale_create_from_input(alice, sale_input)
+        .await
+        .is_err());
+
+    let mut sale_input = environment.sale_input(&tokens, start_time, BLOCK_DURATION * 60)?;
+    sale_input.min_deposit = Some(NearToken::from_near(1).as_yoctonear().into());
+    sale_input.max_account_in_amount = Some(NearToken::from_near(5).as_yoctonear().into());
+    let sale = environment
+        .sale_create_from_input(alice, sale_input)
+        .await?;
+    assert_eq!(
+        sale.min_deposit,
+        Some(NearToken::from_near(1).as_yoctonear().into())
+    );
+    assert_eq!(
+        sale.max_account_in_amount,
+        Some(NearToken::from_near(5).as_yoctonear().into())
+    );
+
+    // Below the minimum deposit.
+    assert!(environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_millinear(500))
+        .await
+        .is_err());
+    environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(4))
+        .await?;
+    // Above the maximum per account.
+    assert!(environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(2))
+        .await
+        .is_err());
+
+    log_tx_result(
+        "sale_withdraw_in_token_exact",
+        bob.call(environment.skyward.id(), "sale_withdraw_in_token_exact")
+            .args_json((sale.sale_id, U128(NearToken::from_near(1).as_yoctonear())))
+            .deposit(NearToken::from_yoctonear(1))
+            .transact()
+            .await?,
+    )?;
+    environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(2))
+        .await?;
+
+    // The limit still applies after withdrawing everything.
+    log_tx_result(
+        "sale_withdraw_in_token",
+        bob.call(environment.skyward.id(), "sale_withdraw_in_token")
+            .args_json((sale.sale_id, None::<U128>))
+            .deposit(NearToken::from_yoctonear(1))
+            .transact()
+            .await?,
+    )?;
+    environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(5))
+        .await?;
+
+    let bobs_sale = environment
+        .get_sale(sale.sale_id, Some(bob.id().clone()))
+        .await?;
+    assert_eq!(
+        bobs_sale.in_token_remaining.0,
+        NearToken::from_near(5).as_yoctonear()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sale_withdrawal_lock_and_penalty() -> anyhow::Result<()> {
+    let environment = Env::init(3).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+    let carol = environment.users.get(2).unwrap();
+
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_near(10_000))
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let start_time = current_time + BLOCK_DURATION * 15;
+    let mut sale_input = environment.sale_input(
+        &[(
+            token1.as_account(),
+            NearToken::from_near(3_600).as_yoctonear(),
+        )],
+        start_time,
+        BLOCK_DURATION * 60,
+    )?;
+    sale_input.withdrawal_lock_duration = Some((BLOCK_DURATION * 40).into());
+    sale_input.early_exit_penalty = Some(EarlyExitPenalty {
+        penalty_bpt: 1000,
+        receiver: PenaltyReceiver::Subscribers,
+    });
+    let sale = environment
+        .sale_create_from_input(alice, sale_input)
+        .await?;
+    assert_eq!(
+        sale.early_exit_penalty,
+        Some(EarlyExitPenalty {
+            penalty_bpt: 1000,
+            receiver: PenaltyReceiver::Subscribers,
+        })
+    );
+
+    environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(4))
+        .await?;
+    environment
+        .sale_deposit_in_token(carol, sale.sale_id, NearToken::from_near(4))
+        .await?;
+
+    // Bob leaves before the start and pays 10% to Carol.
+    log_tx_result(
+        "sale_withdraw_in_token",
+        bob.call(environment.skyward.id(), "sale_withdraw_in_token")
+            .args_json((sale.sale_id, None::<U128>))
+            .deposit(NearToken::from_yoctonear(1))
+            .transact()
+            .await?,
+    )?;
+    assert_eq!(
+        environment.balances_of(bob).await?[0].1,
+        NearToken::from_millinear(9_600).as_yoctonear()
+    );
+
+    log_tx_result(
+        "sale_claim_out_tokens",
+        carol
+            .call(environment.skyward.id(), "sale_claim_out_tokens")
+            .args_json((sale.sale_id,))
+            .transact()
+            .await?,
+    )?;
+    assert_eq!(
+        environment.balances_of(carol).await?[0].1,
+        NearToken::from_millinear(6_400).as_yoctonear()
+    );
+
+    environment.worker.fast_forward(45).await?;
+
+    // Carol can't leave during the last 40 seconds of the sale.
+    assert!(carol
+        .call(environment.skyward.id(), "sale_withdraw_in_token")
+        .args_json((sale.sale_id, None::<U128>))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?
+        .into_result()
+        .is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pause() -> anyhow::Result<()> {
+    let environment = Env::init(2).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_near(10_000))
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let start_time = current_time + BLOCK_DURATION * 15;
+    let sale = environment
+        .sale_create(
+            alice,
+            &[(
+                token1.as_account(),
+                NearToken::from_near(3_600).as_yoctonear(),
+            )],
+            start_time,
+        )
+        .await?;
+
+    let pause_flags = PauseFlags {
+        deposits: true,
+        ..Default::default()
+    };
+    // Only the DAO can pause.
+    assert!(alice
+        .call(environment.skyward.id(), "set_pause_flags")
+        .args_json((&pause_flags,))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?
+        .into_result()
+        .is_err());
+    environment.set_pause_flags(&pause_flags).await?;
+    assert!(environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(4))
+        .await
+        .is_err());
+    assert!(bob
+        .call(environment.skyward.id(), "register_token")
+        .args_json((None::<AccountId>, token1.id()))
+        .deposit(NearToken::from_millinear(10))
+        .transact()
+        .await?
+        .into_result()
+        .is_err());
+
+    environment
+        .set_pause_flags(&PauseFlags {
+            ownership_transfers: true,
+            ..Default::default()
+        })
+        .await?;
+    assert!(alice
+        .call(environment.skyward.id(), "sale_transfer_ownership")
+        .args_json((sale.sale_id, bob.id()))
+        .deposit(NearToken::from_millinear(10))
+        .transact()
+        .await?
+        .into_result()
+        .is_err());
+    // Pausing sale creation doesn't block ownership transfers.
+    environment
+        .set_pause_flags(&PauseFlags {
+            sale_creation: true,
+            ..Default::default()
+        })
+        .await?;
+    log_tx_result(
+        "sale_transfer_ownership",
+        alice
+            .call(environment.skyward.id(), "sale_transfer_ownership")
+            .args_json((sale.sale_id, alice.id()))
+            .deposit(NearToken::from_millinear(10))
+            .transact()
+            .await?,
+    )?;
+    environment.set_pause_flags(&PauseFlags::default()).await?;
+    environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(4))
+        .await?;
+
+    environment.worker.fast_forward(20).await?;
+
+    environment
+        .dao_call("sale_pause", json!({ "sale_id": sale.sale_id }))
+        .await?;
+    let paused_sale = environment.get_sale(sale.sale_id, None).await?;
+    assert!(paused_sale.paused_at.is_some());
+
+    environment.worker.fast_forward(10).await?;
+
+    // Nothing is streamed while the sale is paused.
+    let sale_before_resume = environment.get_sale(sale.sale_id, None).await?;
+    assert_eq!(sale_before_resume.in_token_paid, paused_sale.in_token_paid);
+    assert!(environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(1))
+        .await
+        .is_err());
+    assert!(bob
+        .call(environment.skyward.id(), "sale_withdraw_in_token")
+        .args_json((sale.sale_id, None::<U128>))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?
+        .into_result()
+        .is_err());
+
+    environment
+        .dao_call("sale_resume", json!({ "sale_id": sale.sale_id }))
+        .await?;
+    let resumed_sale = environment.get_sale(sale.sale_id, None).await?;
+    assert_eq!(resumed_sale.paused_at, None);
+    assert!(resumed_sale.duration.0 >= sale.duration.0 + BLOCK_DURATION * 10);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_token_policy() -> anyhow::Result<()> {
+    let environment = Env::init(1).await?;
+    let alice = environment.users.first().unwrap();
+
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_near(10_000))
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let start_time = current_time + BLOCK_DURATION * 15;
+    let tokens = [(
+        token1.as_account(),
+        NearToken::from_near(3_600).as_yoctonear(),
+    )];
+
+    // token1 can't be used as an in token until the DAO allows it.
+    let mut sale_input = environment.sale_input(
+        &[(
+            environment.w_near.as_account(),
+            NearToken::from_near(5).as_yoctonear(),
+        )],
+        start_time,
+        BLOCK_DURATION * 60,
+    )?;
+    sale_input.in_tokens = vec![SaleInputInToken {
+        token_account_id: token1.id().parse()?,
+        weight: InTokenWeight::ONE,
+    }];
+    assert!(environment
+        .sale_create_from_input(alice, sale_input)
+        .await
+        .is_err());
+
+    environment
+        .dao_call(
+            "add_denied_out_tokens",
+            json!({ "token_account_ids": [token1.id()] }),
+        )
+        .await?;
+    assert!(environment
+        .sale_create(alice, &tokens, start_time)
+        .await
+        .is_err());
+    environment
+        .dao_call(
+            "remove_denied_out_tokens",
+            json!({ "token_account_ids": [token1.id()] }),
+        )
+        .await?;
+    let sale = environment.sale_create(alice, &tokens, start_time).await?;
+    assert!(!sale.verified);
+
+    environment
+        .dao_call(
+            "sale_set_verified",
+            json!({ "sale_id": sale.sale_id, "verified": true }),
+        )
+        .await?;
+    assert!(environment.get_sale(sale.sale_id, None).await?.verified);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sale_listing_views() -> anyhow::Result<()> {
+    let environment = Env::init(1).await?;
+    let alice = environment.users.first().unwrap();
+
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_near(10_000))
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let tokens = [(
+        token1.as_account(),
+        NearToken::from_near(1_000).as_yoctonear(),
+    )];
+    let sale = environment
+        .sale_create_from_input(
+            alice,
+            environment.sale_input(
+                &tokens,
+                current_time + BLOCK_DURATION * 15,
+                BLOCK_DURATION * 20,
+            )?,
+        )
+        .await?;
+    let later_sale = environment
+        .sale_create(alice, &tokens, current_time + BLOCK_DURATION * 1_000)
+        .await?;
+
+    assert_eq!(
+        environment
+            .get_sale_ids_by_status(SaleStatus::Upcoming)
+            .await?,
+        vec![sale.sale_id, later_sale.sale_id]
+    );
+    assert!(environment
+        .get_sale_ids_by_status(SaleStatus::Active)
+        .await?
+        .is_empty());
+
+    for token_account_id in [token1.id(), environment.w_near.id()] {
+        let sales: Vec<SaleOutput> = environment
+            .worker
+            .view(environment.skyward.id(), "get_sales_by_token")
+            .args_json((
+                token_account_id,
+                None::<AccountId>,
+                None::<u64>,
+                None::<u64>,
+            ))
+            .await?
+            .json()?;
+        assert_eq!(sales.len(), 2);
+    }
+    let sales: Vec<SaleOutput> = environment
+        .worker
+        .view(environment.skyward.id(), "get_sales_by_owner")
+        .args_json((alice.id(), None::<u64>, None::<u64>))
+        .await?
+        .json()?;
+    assert_eq!(sales.len(), 2);
+
+    let distribute_unclaimed_tokens = || {
+        alice
+            .call(environment.skyward.id(), "sale_distribute_unclaimed_tokens")
+            .args_json((sale.sale_id,))
+            .transact()
+    };
+    environment.worker.fast_forward(20).await?;
+    // The started sale is listed as upcoming until it's saved.
+    assert_eq!(
+        environment
+            .get_sale_ids_by_status(SaleStatus::Upcoming)
+            .await?,
+        vec![sale.sale_id, later_sale.sale_id]
+    );
+    distribute_unclaimed_tokens().await?.into_result()?;
+    assert_eq!(
+        environment
+            .get_sale_ids_by_status(SaleStatus::Active)
+            .await?,
+        vec![sale.sale_id]
+    );
+    let sales: Vec<SaleOutput> = environment
+        .worker
+        .view(environment.skyward.id(), "get_sales_by_status")
+        .args_json((SaleStatus::Upcoming, None::<AccountId>, 0u64, 1u64))
+        .await?
+        .json()?;
+    assert_eq!(
+        sales
+            .into_iter()
+            .map(|sale| sale.sale_id)
+            .collect::<Vec<_>>(),
+        vec![later_sale.sale_id]
+    );
+
+    // Re-indexing the existing sales keeps the index as is.
+    environment
+        .dao_call(
+            "migrate_sale_index",
+            json!({ "from_index": 0, "limit": 10 }),
+        )
+        .await?;
+    assert_eq!(
+        environment
+            .get_sale_ids_by_status(SaleStatus::Active)
+            .await?,
+        vec![sale.sale_id]
+    );
+
+    environment.worker.fast_forward(30).await?;
+    assert!(environment
+        .get_sale_ids_by_status(SaleStatus::Ended)
+        .await?
+        .is_empty());
+    distribute_unclaimed_tokens().await?.into_result()?;
+    assert!(environment
+        .get_sale_ids_by_status(SaleStatus::Active)
+        .await?
+        .is_empty());
+    assert_eq!(
+        environment
+            .get_sale_ids_by_status(SaleStatus::Ended)
+            .await?,
+        vec![sale.sale_id]
+    );
+    assert_eq!(
+        environment
+            .get_sale_ids_by_status(SaleStatus::Upcoming)
+            .await?,
+        vec![later_sale.sale_id]
+    );
+    assert!(environment
+        .get_sale_ids_by_status(SaleStatus::Cancelled)
+        .await?
+        .is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sale_price() -> anyhow::Result<()> {
+    let environment = Env::init(2).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_near(10_000))
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let sale = environment
+        .sale_create(
+            alice,
+            &[(
+                token1.as_account(),
+                NearToken::from_near(3_600).as_yoctonear(),
+            )],
+            current_time + BLOCK_DURATION * 15,
+        )
+        .await?;
+
+    let simulate_deposit = |amount: NearToken| {
+        environment
+            .worker
+            .view(environment.skyward.id(), "simulate_deposit")
+            .args_json((
+                sale.sale_id,
+                bob.id(),
+                U128(amount.as_yoctonear()),
+                None::<AccountId>,
+            ))
+    };
+    let simulated: SimulatedDepositOutput =
+        simulate_deposit(NearToken::from_near(4)).await?.json()?;
+    assert_eq!(
+        simulated,
+        SimulatedDepositOutput {
+            shares: NearToken::from_near(4).as_yoctonear().into(),
+            total_shares: NearToken::from_near(4).as_yoctonear().into(),
+            out_token_amounts: vec![NearToken::from_near(3_564).as_yoctonear().into()],
+        }
+    );
+
+    environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(4))
+        .await?;
+
+    let simulated: SimulatedDepositOutput =
+        simulate_deposit(NearToken::from_near(2)).await?.json()?;
+    assert_eq!(
+        simulated,
+        SimulatedDepositOutput {
+            shares: NearToken::from_near(2).as_yoctonear().into(),
+            total_shares: NearToken::from_near(6).as_yoctonear().into(),
+            out_token_amounts: vec![NearToken::from_near(1_188).as_yoctonear().into()],
+        }
+    );
+
+    // 4 wNEAR for 3600 tokens.
+    let price: SalePriceOutput = environment
+        .worker
+        .view(environment.skyward.id(), "get_sale_price")
+        .args_json((sale.sale_id,))
+        .await?
+        .json()?;
+    let out_token_price = &price.out_tokens[0];
+    let expected_price = Some(U128(NearToken::from_near(4).as_yoctonear() / 3_600));
+    assert_eq!(out_token_price.current_price, expected_price);
+    assert_eq!(out_token_price.projected_price, expected_price);
+    assert_eq!(out_token_price.average_price, None);
+
+    // The ended sale doesn't accept deposits.
+    environment.worker.fast_forward(100).await?;
+    let simulated: SimulatedDepositOutput =
+        simulate_deposit(NearToken::from_near(2)).await?.json()?;
+    assert_eq!(
+        simulated,
+        SimulatedDepositOutput {
+            shares: 0.into(),
+            total_shares: NearToken::from_near(4).as_yoctonear().into(),
+            out_token_amounts: vec![0.into()],
+        }
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_account_portfolio() -> anyhow::Result<()> {
+    let environment = Env::init(2).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+
+    let sale_amount = NearToken::from_near(10_000).as_yoctonear();
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_yoctonear(sale_amount))
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let sale = environment
+        .sale_create_with_ref(
+            alice,
+            &[(token1.as_account(), sale_amount)],
+            current_time + BLOCK_DURATION * 15,
+        )
+        .await?;
+
+    log_tx_result(
+        "sale_deposit_in_token",
+        bob.call(environment.skyward.id(), "sale_deposit_in_token")
+            .args_json((
+                sale.sale_id,
+                U128(NearToken::from_near(4).as_yoctonear()),
+                Some(alice.id().clone()),
+                None::<AccountId>,
+            ))
+            .deposit(NearToken::from_millinear(10))
+            .transact()
+            .await?,
+    )?;
+
+    environment.worker.fast_forward(500).await?;
+
+    let get_account_portfolio = |account_id: &AccountId| {
+        environment
+            .worker
+            .view(environment.skyward.id(), "get_account_portfolio")
+            .args_json(json!({ "account_id": account_id }))
+    };
+    let bobs_portfolio: AccountPortfolioOutput = get_account_portfolio(bob.id())
+        .await?
+        .json::<Option<_>>()?
+        .unwrap();
+    assert_eq!(bobs_portfolio.subscriptions.len(), 1);
+    assert_eq!(bobs_portfolio.subscriptions[0].sale_id, sale.sale_id);
+    assert_eq!(
+        bobs_portfolio.subscriptions[0]
+            .subscription
+            .unclaimed_out_balances,
+        vec![(sale_amount * 99 / 100).into()]
+    );
+    assert!(bobs_portfolio.proceeds_sales.is_empty());
+    assert_eq!(bobs_portfolio.counts.num_subscriptions, 1);
+
+    let alices_portfolio: AccountPortfolioOutput = get_account_portfolio(alice.id())
+        .await?
+        .json::<Option<_>>()?
+        .unwrap();
+    assert_eq!(alices_portfolio.proceeds_sales.len(), 1);
+    // Alice has balances of wNEAR and token1.
+    assert_eq!(alices_portfolio.counts.num_balances, 2);
+    assert_eq!(alices_portfolio.counts.num_proceeds_sales, 1);
+    assert_eq!(alices_portfolio.counts.num_referrals, 1);
+    // The collections are paginated separately.
+    let page: AccountPortfolioOutput = environment
+        .worker
+        .view(environment.skyward.id(), "get_account_portfolio")
+        .args_json(json!({
+            "account_id": alice.id(),
+            "balances_page": { "from_index": 1, "limit": 1 },
+            "proceeds_sales_page": { "from_index": 0, "limit": 1 },
+        }))
+        .await?
+        .json::<Option<_>>()?
+        .unwrap();
+    assert_eq!(page.balances, alices_portfolio.balances[1..].to_vec());
+    assert_eq!(page.proceeds_sales, alices_portfolio.proceeds_sales);
+    assert_eq!(
+        page.pending_referral_earnings,
+        alices_portfolio.pending_referral_earnings
+    );
+    assert_eq!(
+        alices_portfolio.proceeds_sales[0].unclaimed_proceeds,
+        vec![(
+            environment.w_near.id().parse()?,
+            (NearToken::from_near(4).as_yoctonear() * 99 / 100).into()
+        )]
+    );
+    // Half of the 1% referral fee goes to the referrer.
+    assert_eq!(
+        alices_portfolio.pending_referral_earnings,
+        vec![(token1.id().parse()?, (sale_amount * 99 / 100 / 200).into())]
+    );
+
+    log_tx_result(
+        "sale_claim_out_tokens",
+        bob.call(environment.skyward.id(), "sale_claim_out_tokens")
+            .args_json((sale.sale_id,))
+            .transact()
+            .await?,
+    )?;
+    let alices_portfolio: AccountPortfolioOutput = get_account_portfolio(alice.id())
+        .await?
+        .json::<Option<_>>()?
+        .unwrap();
+    assert!(alices_portfolio.pending_referral_earnings.is_empty());
+    assert_eq!(
+        alices_portfolio.proceeds_sales[0].unclaimed_proceeds,
+        vec![(environment.w_near.id().parse()?, 0.into())]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_claim_out_tokens_batch() -> anyhow::Result<()> {
+    let environment = Env::init(2).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+
+    let sale_amount = NearToken::from_near(1_000).as_yoctonear();
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(
+            alice,
+            token1.id(),
+            NearToken::from_yoctonear(sale_amount * 2),
+        )
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let mut sale_ids = vec![];
+    for _ in 0..2 {
+        let sale = environment
+            .sale_create(
+                alice,
+                &[(token1.as_account(), sale_amount)],
+                current_time + BLOCK_DURATION * 20,
+            )
+            .await?;
+        environment
+            .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(2))
+            .await?;
+        sale_ids.push(sale.sale_id);
+    }
+
+    // Only the claimed tokens are withdrawn, the transferred ones stay.
+    let transferred_amount = NearToken::from_near(1).as_yoctonear();
+    log_tx_result(
+        "internal_transfer",
+        alice
+            .call(environment.skyward.id(), "internal_transfer")
+            .args_json((
+                bob.id(),
+                token1.id(),
+                U128(transferred_amount),
+                None::<String>,
+            ))
+            .deposit(NearToken::from_yoctonear(1))
+            .transact()
+            .await?,
+    )?;
+
+    environment.worker.fast_forward(500).await?;
+
+    // Not enough gas to claim anything.
+    let output: ClaimOutTokensBatchOutput = bob
+        .call(environment.skyward.id(), "sale_claim_out_tokens_batch")
+        .args_json((None::<Vec<u64>>, Some(true)))
+        .gas(Gas::from_tgas(15))
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(
+        output,
+        ClaimOutTokensBatchOutput {
+            claimed_sale_ids: vec![],
+            remaining_sale_ids: sale_ids.clone(),
+        }
+    );
+
+    environment
+        .storage_deposit(&token1, bob, None, Some(NearToken::from_millinear(50)))
+        .await?;
+    let output: ClaimOutTokensBatchOutput = bob
+        .call(environment.skyward.id(), "sale_claim_out_tokens_batch")
+        .args_json((Some(&sale_ids), Some(true)))
+        .max_gas()
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(
+        output,
+        ClaimOutTokensBatchOutput {
+            claimed_sale_ids: sale_ids,
+            remaining_sale_ids: vec![],
+        }
+    );
+    assert_eq!(
+        environment.ft_balance_of(bob, token1.id()).await?,
+        sale_amount * 2 * 99 / 100
+    );
+    assert!(environment
+        .balances_of(bob)
+        .await?
+        .contains(&(token1.id().clone(), transferred_amount)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_withdraw_tokens() -> anyhow::Result<()> {
+    let environment = Env::init(2).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_near(1_000))
+        .await?;
+    environment
+        .storage_deposit(&token1, bob, None, Some(NearToken::from_millinear(50)))
+        .await?;
+    let initial_bob_w_near = environment
+        .ft_balance_of(bob, environment.w_near.id())
+        .await?;
+
+    // Withdrawals to another receiver require a full access key.
+    assert!(alice
+        .call(environment.skyward.id(), "withdraw_tokens")
+        .args_json((
+            vec![(environment.w_near.id(), None::<U128>)],
+            Some(bob.id()),
+            None::<String>,
+        ))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()
+        .is_err());
+
+    log_tx_result(
+        "withdraw_tokens",
+        alice
+            .call(environment.skyward.id(), "withdraw_tokens")
+            .args_json((
+                vec![
+                    (
+                        token1.id(),
+                        Some(U128(NearToken::from_near(100).as_yoctonear())),
+                    ),
+                    (environment.w_near.id(), None),
+                ],
+                Some(bob.id()),
+                None::<String>,
+            ))
+            .deposit(NearToken::from_yoctonear(1))
+            .max_gas()
+            .transact()
+            .await?,
+    )?;
+    assert_eq!(
+        environment.ft_balance_of(bob, token1.id()).await?,
+        NearToken::from_near(100).as_yoctonear()
+    );
+    assert_eq!(
+        environment
+            .ft_balance_of(bob, environment.w_near.id())
+            .await?,
+        initial_bob_w_near + NearToken::from_near(10).as_yoctonear()
+    );
+    assert_eq!(
+        environment.balances_of(alice).await?,
+        vec![
+            (environment.w_near.id().clone(), 0),
+            (
+                token1.id().clone(),
+                NearToken::from_near(900).as_yoctonear()
+            ),
+        ]
+    );
+
+    // Bob's account has no contract, so the transfer call is refunded.
+    log_tx_result(
+        "withdraw_tokens",
+        alice
+            .call(environment.skyward.id(), "withdraw_tokens")
+            .args_json((
+                vec![(
+                    token1.id(),
+                    Some(U128(NearToken::from_near(100).as_yoctonear())),
+                )],
+                Some(bob.id()),
+                Some("deposit"),
+            ))
+            .deposit(NearToken::from_yoctonear(1))
+            .max_gas()
+            .transact()
+            .await?,
+    )?;
+    assert_eq!(
+        environment.ft_balance_of(bob, token1.id()).await?,
+        NearToken::from_near(100).as_yoctonear()
+    );
+    assert_eq!(
+        environment.balances_of(alice).await?,
+        vec![
+            (environment.w_near.id().clone(), 0),
+            (
+                token1.id().clone(),
+                NearToken::from_near(900).as_yoctonear()
+            ),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_internal_transfer() -> anyhow::Result<()> {
+    let environment = Env::init(2).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+
+    let amount = NearToken::from_near(3).as_yoctonear();
+    let transfer = || {
+        alice
+            .call(environment.skyward.id(), "internal_transfer")
+            .args_json((
+                bob.id(),
+                environment.w_near.id(),
+                U128(amount),
+                Some("funding"),
+            ))
+    };
+    assert!(transfer().transact().await?.into_result().is_err());
+    let (_, events) = log_tx_result(
+        "internal_transfer",
+        transfer()
+            .deposit(NearToken::from_yoctonear(1))
+            .transact()
+            .await?,
+    )?;
+    assert_eq!(
+        events,
+        vec![event::ContractEvent::Skyward(event::SkywardEvent {
+            version: "1.0.0".to_string(),
+            event_kind: event::SkywardEventKind::InternalTransfer(vec![event::InternalTransfer {
+                sender_id: alice.id().to_string(),
+                receiver_id: bob.id().to_string(),
+                token_account_id: environment.w_near.id().to_string(),
+                amount: amount.to_string(),
+                memo: Some("funding".to_string()),
+            }]),
+        })]
+    );
+    assert_eq!(
+        environment.balances_of(alice).await?,
+        vec![(
+            environment.w_near.id().clone(),
+            NearToken::from_near(10).as_yoctonear() - amount
+        )]
+    );
+    assert_eq!(
+        environment.balances_of(bob).await?,
+        vec![(
+            environment.w_near.id().clone(),
+            NearToken::from_near(10).as_yoctonear() + amount
+        )]
+    );
+
+    // Alice deposits token1 on behalf of Bob.
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    log_tx_result(
+        "register_tokens",
+        alice
+            .call(environment.skyward.id(), "register_tokens")
+            .args_json((Some(bob.id()), vec![token1.id()]))
+            .deposit(NearToken::from_millinear(10))
+            .transact()
+            .await?,
+    )?;
+    log_tx_result(
+        "ft_transfer_call",
+        alice
+            .call(token1.id(), "ft_transfer_call")
+            .args_json(json!({
+                "receiver_id": environment.skyward.id(),
+                "amount": U128::from(amount),
+                "msg": json!({ "AccountDepositFor": { "account_id": bob.id() } }).to_string(),
+            }))
+            .max_gas()
+            .deposit(NearToken::from_yoctonear(1))
+            .transact()
+            .await?,
+    )?;
+    assert!(environment
+        .balances_of(bob)
+        .await?
+        .contains(&(token1.id().clone(), amount)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_create_sale_on_behalf() -> anyhow::Result<()> {
+    let environment = Env::init(3).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+    let carol = environment.users.get(2).unwrap();
+
+    let sale_amount = NearToken::from_near(1_000).as_yoctonear();
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_yoctonear(sale_amount))
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let mut sale_input = environment.sale_input(
+        &[(token1.as_account(), sale_amount)],
+        current_time + BLOCK_DURATION * 15,
+        BLOCK_DURATION * 60,
+    )?;
+    sale_input.owner_id = Some(bob.id().parse()?);
+    sale_input.proceeds_receiver_id = Some(carol.id().parse()?);
+    let sale = environment
+        .sale_create_from_input(alice, sale_input)
+        .await?;
+    // Alice owns the sale until Bob accepts the ownership.
+    assert_eq!(sale.owner_id.as_str(), alice.id().as_str());
+    assert_eq!(
+        sale.pending_owner_id.map(|id| id.to_string()),
+        Some(bob.id().to_string())
+    );
+    assert_eq!(sale.proceeds_receiver_id.as_str(), carol.id().as_str());
+
+    let get_account_sales = |account_id: &AccountId| {
+        environment
+            .worker
+            .view(environment.skyward.id(), "get_account_sales")
+            .args_json((account_id, None::<u64>, None::<u64>))
+    };
+    let alices_sales: Vec<SaleOutput> = get_account_sales(alice.id()).await?.json()?;
+    assert_eq!(alices_sales.len(), 1);
+
+    log_tx_result(
+        "sale_accept_ownership",
+        bob.call(environment.skyward.id(), "sale_accept_ownership")
+            .args_json((sale.sale_id,))
+            .deposit(NearToken::from_millinear(10))
+            .transact()
+            .await?,
+    )?;
+    let bobs_sales: Vec<SaleOutput> = get_account_sales(bob.id()).await?.json()?;
+    assert_eq!(bobs_sales.len(), 1);
+    let alices_sales: Vec<SaleOutput> = get_account_sales(alice.id()).await?.json()?;
+    assert!(alices_sales.is_empty());
+
+    environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(4))
+        .await?;
+    environment.worker.fast_forward(500).await?;
+    log_tx_result(
+        "sale_distribute_unclaimed_tokens",
+        alice
+            .call(environment.skyward.id(), "sale_distribute_unclaimed_tokens")
+            .args_json((sale.sale_id,))
+            .transact()
+            .await?,
+    )?;
+
+    assert_eq!(
+        environment.balances_of(carol).await?,
+        vec![
+            (
+                environment.w_near.id().clone(),
+                NearToken::from_near(10).as_yoctonear()
+                    + NearToken::from_near(4).as_yoctonear() * 99 / 100
+            ),
+            (token1.id().clone(), 0),
+        ]
+    );
+    assert_eq!(
+        environment.balances_of(alice).await?,
+        vec![
+            (
+                environment.w_near.id().clone(),
+                NearToken::from_near(10).as_yoctonear()
+            ),
+            (token1.id().clone(), 0),
+        ]
+    );
+
+    // Without a separate proceeds receiver, the proceeds go to the proposed owner.
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_yoctonear(sale_amount))
+        .await?;
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let mut sale_input = environment.sale_input(
+        &[(token1.as_account(), sale_amount)],
+        current_time + BLOCK_DURATION * 15,
+        BLOCK_DURATION * 60,
+    )?;
+    sale_input.owner_id = Some(bob.id().parse()?);
+    let sale = environment
+        .sale_create_from_input(alice, sale_input)
+        .await?;
+    assert_eq!(sale.owner_id.as_str(), alice.id().as_str());
+    assert_eq!(sale.proceeds_receiver_id.as_str(), bob.id().as_str());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_transfer_sale_ownership() -> anyhow::Result<()> {
+    let environment = Env::init(3).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+    let carol = environment.users.get(2).unwrap();
+
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_near(1_000))
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let sale = environment
+        .sale_create(
+            alice,
+            &[(
+                token1.as_account(),
+                NearToken::from_near(1_000).as_yoctonear(),
+            )],
+            current_time + BLOCK_DURATION * 15,
+        )
+        .await?;
+    environment
+        .sale_deposit_in_token(carol, sale.sale_id, NearToken::from_near(4))
+        .await?;
+
+    let transfer_ownership = |user: &near_workspaces::Account| {
+        user.call(environment.skyward.id(), "sale_transfer_ownership")
+            .args_json((sale.sale_id, bob.id()))
+            .deposit(NearToken::from_millinear(10))
+    };
+    let accept_ownership = |user: &near_workspaces::Account| {
+        user.call(environment.skyward.id(), "sale_accept_ownership")
+            .args_json((sale.sale_id,))
+            .deposit(NearToken::from_millinear(10))
+    };
+    assert!(transfer_ownership(bob)
+        .transact()
+        .await?
+        .into_result()
+        .is_err());
+    log_tx_result(
+        "sale_transfer_ownership",
+        transfer_ownership(alice).transact().await?,
+    )?;
+    let pending_sale = environment.get_sale(sale.sale_id, None).await?;
+    assert_eq!(pending_sale.owner_id.as_str(), alice.id().as_str());
+    assert_eq!(
+        pending_sale.pending_owner_id.map(|id| id.to_string()),
+        Some(bob.id().to_string())
+    );
+
+    environment.worker.fast_forward(30).await?;
+
+    assert!(accept_ownership(carol)
+        .transact()
+        .await?
+        .into_result()
+        .is_err());
+    log_tx_result(
+        "sale_accept_ownership",
+        accept_ownership(bob).transact().await?,
+    )?;
+
+    let transferred_sale = environment.get_sale(sale.sale_id, None).await?;
+    assert_eq!(transferred_sale.owner_id.as_str(), bob.id().as_str());
+    assert_eq!(
+        transferred_sale.proceeds_receiver_id.as_str(),
+        bob.id().as_str()
+    );
+    assert_eq!(transferred_sale.pending_owner_id, None);
+    // The proceeds until the transfer went to Alice.
+    let alices_w_near = environment
+        .balances_of(alice)
+        .await?
+        .into_iter()
+        .find(|(token_account_id, _)| token_account_id == environment.w_near.id())
+        .unwrap()
+        .1;
+    assert!(alices_w_near > NearToken::from_near(10).as_yoctonear());
+    assert!(environment
+        .balances_of(bob)
+        .await?
+        .contains(&(token1.id().clone(), 0)));
+
+    let get_account_sales = |account_id: &AccountId| {
+        environment
+            .worker
+            .view(environment.skyward.id(), "get_account_sales")
+            .args_json((account_id, None::<u64>, None::<u64>))
+    };
+    let alices_sales: Vec<SaleOutput> = get_account_sales(alice.id()).await?.json()?;
+    assert!(alices_sales.is_empty());
+    let bobs_sales: Vec<SaleOutput> = get_account_sales(bob.id()).await?.json()?;
+    assert_eq!(bobs_sales.len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_multi_level_referral() -> anyhow::Result<()> {
+    let environment = Env::init(4).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+    let carol = environment.users.get(2).unwrap();
+    let dave = environment.users.get(3).unwrap();
+
+    let sale_amount = NearToken::from_near(10_000).as_yoctonear();
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_yoctonear(sale_amount))
+        .await?;
+    log_tx_result(
+        "register_token",
+        dave.call(environment.skyward.id(), "register_token")
+            .args_json((None::<AccountId>, token1.id()))
+            .deposit(NearToken::from_millinear(10))
+            .transact()
+            .await?,
+    )?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let mut sale_input = environment.sale_input(
+        &[(token1.as_account(), sale_amount)],
+        current_time + BLOCK_DURATION * 15,
+        BLOCK_DURATION * 60,
+    )?;
+    sale_input.out_tokens[0].referral_bpt = Some(100);
+    sale_input.out_tokens[0].referrer_bpt = Some(60);
+    sale_input.out_tokens[0].second_level_referrer_bpt = Some(20);
+    let sale = environment
+        .sale_create_from_input(alice, sale_input)
+        .await?;
+    assert_eq!(sale.out_tokens[0].referrer_bpt, Some(60));
+    assert_eq!(sale.out_tokens[0].second_level_referrer_bpt, Some(20));
+    assert_eq!(sale.out_tokens[0].referral_payout, ReferralPayout::OutToken);
+
+    // Dave referred Bob, so Dave is the second level referrer of Carol.
+    for (user, referral) in [(bob, dave), (carol, bob)] {
+        log_tx_result(
+            "sale_deposit_in_token",
+            user.call(environment.skyward.id(), "sale_deposit_in_token")
+                .args_json((
+                    sale.sale_id,
+                    U128(NearToken::from_near(4).as_yoctonear()),
+                    Some(referral.id().clone()),
+                    None::<AccountId>,
+                ))
+                .deposit(NearToken::from_millinear(10))
+                .transact()
+                .await?,
+        )?;
+    }
+
+    environment.worker.fast_forward(500).await?;
+
+    let carols_sale = environment
+        .get_sale(sale.sale_id, Some(carol.id().clone()))
+        .await?;
+    let out_amount = carols_sale.subscription.unwrap().unclaimed_out_balances[0].0;
+    let referrer_amount = out_amount * 60 / 10000;
+    let second_level_referrer_amount = out_amount * 20 / 10000;
+
+    log_tx_result(
+        "sale_claim_out_tokens",
+        carol
+            .call(environment.skyward.id(), "sale_claim_out_tokens")
+            .args_json((sale.sale_id,))
+            .transact()
+            .await?,
+    )?;
+
+    assert_eq!(
+        environment.balances_of(carol).await?[1],
+        (
+            token1.id().clone(),
+            out_amount - referrer_amount - second_level_referrer_amount
+        )
+    );
+    assert_eq!(
+        environment.balances_of(bob).await?[1],
+        (token1.id().clone(), referrer_amount)
+    );
+    assert_eq!(
+        environment.balances_of(dave).await?[1],
+        (token1.id().clone(), second_level_referrer_amount)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_referral_stats() -> anyhow::Result<()> {
+    let environment = Env::init(4).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+    let carol = environment.users.get(2).unwrap();
+    let dave = environment.users.get(3).unwrap();
+
+    let sale_amount = NearToken::from_near(10_000).as_yoctonear();
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_yoctonear(sale_amount))
+        .await?;
+    environment
+        .dao_call(
+            "set_referral_escrow_duration",
+            json!({ "duration": U64(0) }),
+        )
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let sale = environment
+        .sale_create_with_ref(
+            alice,
+            &[(token1.as_account(), sale_amount)],
+            current_time + BLOCK_DURATION * 15,
+        )
+        .await?;
+
+    // Carol doesn't have the out token registered and the escrow is disabled, so her referral
+    // fees go to the treasury.
+    for (user, referral) in [(bob, carol), (dave, alice)] {
+        log_tx_result(
+            "sale_deposit_in_token",
+            user.call(environment.skyward.id(), "sale_deposit_in_token")
+                .args_json((
+                    sale.sale_id,
+                    U128(NearToken::from_near(4).as_yoctonear()),
+                    Some(referral.id().clone()),
+                    None::<AccountId>,
+                ))
+                .deposit(NearToken::from_millinear(10))
+                .transact()
+                .await?,
+        )?;
+    }
+
+    environment.worker.fast_forward(500).await?;
+
+    let mut ref_amounts = vec![];
+    for user in [bob, dave] {
+        let users_sale = environment
+            .get_sale(sale.sale_id, Some(user.id().clone()))
+            .await?;
+        ref_amounts.push(users_sale.subscription.unwrap().unclaimed_out_balances[0].0 / 100 / 2);
+        log_tx_result(
+            "sale_claim_out_tokens",
+            user.call(environment.skyward.id(), "sale_claim_out_tokens")
+                .args_json((sale.sale_id,))
+                .transact()
+                .await?,
+        )?;
+    }
+
+    let get_referral_stats = |account_id: &AccountId| {
+        environment
+            .worker
+            .view(environment.skyward.id(), "get_referral_stats")
+            .args_json((account_id, None::<u64>, None::<u64>))
+    };
+    let alices_stats: ReferralStatsOutput = get_referral_stats(alice.id()).await?.json()?;
+    assert_eq!(
+        alices_stats.earnings,
+        vec![ReferralEarningOutput {
+            sale_id: sale.sale_id,
+            token_account_id: token1.id().parse()?,
+            earned: ref_amounts[1].into(),
+            lost: 0.into(),
+        }]
+    );
+    let carols_stats: ReferralStatsOutput = get_referral_stats(carol.id()).await?.json()?;
+    assert_eq!(
+        carols_stats.earnings,
+        vec![ReferralEarningOutput {
+            sale_id: sale.sale_id,
+            token_account_id: token1.id().parse()?,
+            earned: 0.into(),
+            lost: ref_amounts[0].into(),
+        }]
+    );
+
+    let leaderboard: Vec<ReferralLeaderboardEntry> = environment
+        .worker
+        .view(environment.skyward.id(), "get_sale_referral_leaderboard")
+        .args_json((sale.sale_id, token1.id(), None::<u64>, None::<u64>))
+        .await?
+        .json()?;
+    assert_eq!(
+        leaderboard,
+        vec![
+            ReferralLeaderboardEntry {
+                account_id: alice.id().parse()?,
+                earned: ref_amounts[1].into(),
+                lost: 0.into(),
+            },
+            ReferralLeaderboardEntry {
+                account_id: carol.id().parse()?,
+                earned: 0.into(),
+                lost: ref_amounts[0].into(),
+            },
+        ]
+    );
+    let lost_amounts: Vec<(AccountId, U128)> = environment
+        .worker
+        .view(environment.skyward.id(), "get_referral_lost_amounts")
+        .await?
+        .json()?;
+    assert_eq!(
+        lost_amounts,
+        vec![(token1.id().clone(), ref_amounts[0].into())]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_referral_escrow() -> anyhow::Result<()> {
+    let environment = Env::init(4).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+    let carol = environment.users.get(2).unwrap();
+    let dave = environment.users.get(3).unwrap();
+    let nobody: AccountId = "nobody.test.near".parse()?;
+
+    let sale_amount = NearToken::from_near(10_000).as_yoctonear();
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_yoctonear(sale_amount))
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let sale = environment
+        .sale_create_with_ref(
+            alice,
+            &[(token1.as_account(), sale_amount)],
+            current_time + BLOCK_DURATION * 15,
+        )
+        .await?;
+
+    // Neither Carol nor the referrer of Dave have the out token registered.
+    for (user, referral) in [(bob, carol.id().clone()), (dave, nobody.clone())] {
+        log_tx_result(
+            "sale_deposit_in_token",
+            user.call(environment.skyward.id(), "sale_deposit_in_token")
+                .args_json((
+                    sale.sale_id,
+                    U128(NearToken::from_near(4).as_yoctonear()),
+                    Some(referral),
+                    None::<AccountId>,
+                ))
+                .deposit(NearToken::from_millinear(10))
+                .transact()
+                .await?,
+        )?;
+    }
+    environment.worker.fast_forward(500).await?;
+
+    let get_escrowed_referral_rewards = |account_id: &AccountId| {
+        environment
+            .worker
+            .view(environment.skyward.id(), "get_escrowed_referral_rewards")
+            .args_json((account_id,))
+    };
+
+    let bobs_sale = environment
+        .get_sale(sale.sale_id, Some(bob.id().clone()))
+        .await?;
+    let ref_amount = bobs_sale.subscription.unwrap().unclaimed_out_balances[0].0 / 100 / 2;
+    log_tx_result(
+        "sale_claim_out_tokens",
+        bob.call(environment.skyward.id(), "sale_claim_out_tokens")
+            .args_json((sale.sale_id,))
+            .transact()
+            .await?,
+    )?;
+    let rewards: Vec<EscrowedReferralRewardOutput> =
+        get_escrowed_referral_rewards(carol.id()).await?.json()?;
+    assert_eq!(rewards.len(), 1);
+    assert_eq!(rewards[0].amount, U128(ref_amount));
+
+    log_tx_result(
+        "register_token",
+        carol
+            .call(environment.skyward.id(), "register_token")
+            .args_json((None::<AccountId>, token1.id()))
+            .deposit(NearToken::from_millinear(10))
+            .transact()
+            .await?,
+    )?;
+    log_tx_result(
+        "claim_referral_rewards",
+        carol
+            .call(environment.skyward.id(), "claim_referral_rewards")
+            .args_json((vec![token1.id()],))
+            .transact()
+            .await?,
+    )?;
+    assert_eq!(
+        environment.balances_of(carol).await?[1],
+        (token1.id().clone(), ref_amount)
+    );
+    let rewards: Vec<EscrowedReferralRewardOutput> =
+        get_escrowed_referral_rewards(carol.id()).await?.json()?;
+    assert!(rewards.is_empty());
+
+    // Rewards escrowed with a 1 nanosecond grace period can be swept right away.
+    environment
+        .dao_call(
+            "set_referral_escrow_duration",
+            json!({ "duration": U64(1) }),
+        )
+        .await?;
+    let daves_sale = environment
+        .get_sale(sale.sale_id, Some(dave.id().clone()))
+        .await?;
+    let ref_amount = daves_sale.subscription.unwrap().unclaimed_out_balances[0].0 / 100 / 2;
+    log_tx_result(
+        "sale_claim_out_tokens",
+        dave.call(environment.skyward.id(), "sale_claim_out_tokens")
+            .args_json((sale.sale_id,))
+            .transact()
+            .await?,
+    )?;
+    log_tx_result(
+        "sweep_referral_rewards",
+        dave.call(environment.skyward.id(), "sweep_referral_rewards")
+            .args_json((&nobody, token1.id()))
+            .transact()
+            .await?,
+    )?;
+    let lost_amounts: Vec<(AccountId, U128)> = environment
+        .worker
+        .view(environment.skyward.id(), "get_referral_lost_amounts")
+        .await?
+        .json()?;
+    assert_eq!(lost_amounts, vec![(token1.id().clone(), ref_amount.into())]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_amount_weighted_referral_attribution() -> anyhow::Result<()> {
+    let environment = Env::init(4).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+    let carol = environment.users.get(2).unwrap();
+    let dave = environment.users.get(3).unwrap();
+
+    let sale_amount = NearToken::from_near(10_000).as_yoctonear();
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_yoctonear(sale_amount))
+        .await?;
+    for user in [carol, dave] {
+        log_tx_result(
+            "register_token",
+            user.call(environment.skyward.id(), "register_token")
+                .args_json((None::<AccountId>, token1.id()))
+                .deposit(NearToken::from_millinear(10))
+                .transact()
+                .await?,
+        )?;
+    }
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let mut sale_input = environment.sale_input(
+        &[(token1.as_account(), sale_amount)],
+        current_time + BLOCK_DURATION * 15,
+        BLOCK_DURATION * 60,
+    )?;
+    sale_input.out_tokens[0].referral_bpt = Some(100);
+    sale_input.referral_attribution = Some(ReferralAttribution::AmountWeighted);
+    let sale = environment
+        .sale_create_from_input(alice, sale_input)
+        .await?;
+    assert_eq!(
+        sale.referral_attribution,
+        ReferralAttribution::AmountWeighted
+    );
+
+    for (amount, referral) in [(3, carol), (1, dave)] {
+        log_tx_result(
+            "sale_deposit_in_token",
+            bob.call(environment.skyward.id(), "sale_deposit_in_token")
+                .args_json((
+                    sale.sale_id,
+                    U128(NearToken::from_near(amount).as_yoctonear()),
+                    Some(referral.id().clone()),
+                    None::<AccountId>,
+                ))
+                .deposit(NearToken::from_millinear(10))
+                .transact()
+                .await?,
+        )?;
+    }
+
+    environment.worker.fast_forward(500).await?;
+
+    let subscription = environment
+        .get_sale(sale.sale_id, Some(bob.id().clone()))
+        .await?
+        .subscription
+        .unwrap();
+    assert_eq!(subscription.referral_id, Some(carol.id().parse()?));
+    assert_eq!(
+        subscription.referral_weights,
+        vec![
+            (
+                carol.id().parse()?,
+                NearToken::from_near(3).as_yoctonear().into()
+            ),
+            (
+                dave.id().parse()?,
+                NearToken::from_near(1).as_yoctonear().into()
+            ),
+        ]
+    );
+    let out_amount = subscription.unclaimed_out_balances[0].0;
+    let carols_amount = out_amount * 3 / 4 / 100 / 2;
+    let daves_amount = out_amount / 4 / 100 / 2;
+
+    log_tx_result(
+        "sale_claim_out_tokens",
+        bob.call(environment.skyward.id(), "sale_claim_out_tokens")
+            .args_json((sale.sale_id,))
+            .transact()
+            .await?,
+    )?;
+
+    assert_eq!(
+        environment.balances_of(carol).await?[1],
+        (token1.id().clone(), carols_amount)
+    );
+    assert_eq!(
+        environment.balances_of(dave).await?[1],
+        (token1.id().clone(), daves_amount)
+    );
+    assert_eq!(
+        environment.balances_of(bob).await?[1],
+        (
+            token1.id().clone(),
+            out_amount - carols_amount - daves_amount
+        )
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sale_history() -> anyhow::Result<()> {
+    let environment = Env::init(2).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+
+    let sale_amount = NearToken::from_near(1_000).as_yoctonear();
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_yoctonear(sale_amount))
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let mut sale_input = environment.sale_input(
+        &[(token1.as_account(), sale_amount)],
+        current_time + BLOCK_DURATION * 15,
+        BLOCK_DURATION * 60,
+    )?;
+    sale_input.history_interval = Some((BLOCK_DURATION * 5).into());
+    let sale = environment
+        .sale_create_from_input(alice, sale_input)
+        .await?;
+    assert_eq!(sale.history_interval, U64(BLOCK_DURATION * 5));
+
+    let get_sale_history = || {
+        environment
+            .worker
+            .view(environment.skyward.id(), "get_sale_history")
+            .args_json((sale.sale_id,))
+    };
+    let history: Vec<SaleCheckpointOutput> = get_sale_history().await?.json()?;
+    assert!(history.is_empty());
+
+    environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(1))
+        .await?;
+    environment.worker.fast_forward(30).await?;
+    environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(1))
+        .await?;
+    environment.worker.fast_forward(500).await?;
+    log_tx_result(
+        "sale_claim_out_tokens",
+        bob.call(environment.skyward.id(), "sale_claim_out_tokens")
+            .args_json((sale.sale_id,))
+            .transact()
+            .await?,
+    )?;
+
+    let history: Vec<SaleCheckpointOutput> = get_sale_history().await?.json()?;
+    assert_eq!(history.len(), 2);
+    assert!(history[0].timestamp.0 < history[1].timestamp.0);
+    assert!(history[0].out_token_distributed[0].0 < sale_amount);
+    let last_checkpoint = history.last().unwrap();
+    assert_eq!(
+        last_checkpoint.timestamp.0,
+        sale.start_time.0 + sale.duration.0
+    );
+    assert_eq!(last_checkpoint.in_token_remaining, U128(0));
+    assert_eq!(
+        last_checkpoint.in_token_paid,
+        U128(NearToken::from_near(2).as_yoctonear())
+    );
+    assert_eq!(
+        last_checkpoint.out_token_distributed,
+        vec![U128(sale_amount)]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sale_hooks() -> anyhow::Result<()> {
+    let environment = Env::init(2).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+    // The permissions contract doesn't implement the hooks, so every hook call fails.
+    let hooks_contract = &environment.permissions_contract;
+
+    let sale_amount = NearToken::from_near(1_000).as_yoctonear();
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_yoctonear(sale_amount))
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let mut sale_input = environment.sale_input(
+        &[(token1.as_account(), sale_amount)],
+        current_time + BLOCK_DURATION * 15,
+        BLOCK_DURATION * 60,
+    )?;
+    sale_input.hooks_contract_id = Some(hooks_contract.id().parse()?);
+    let sale = environment
+        .sale_create_from_input(alice, sale_input)
+        .await?;
+    assert_eq!(
+        sale.hooks_contract_id.as_ref().map(|id| id.as_str()),
+        Some(hooks_contract.id().as_str())
+    );
+
+    environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(4))
+        .await?;
+    environment.worker.fast_forward(500).await?;
+
+    let res = alice
+        .call(environment.skyward.id(), "sale_distribute_unclaimed_tokens")
+        .args_json((sale.sale_id,))
+        .max_gas()
+        .transact()
+        .await?;
+    let hook_calls = res
+        .receipt_outcomes()
+        .iter()
+        .filter(|outcome| outcome.executor_id == *hooks_contract.id())
+        .count();
+    // Both `on_sale_ended` and `on_proceeds_distributed`.
+    assert_eq!(hook_calls, 2);
+    assert!(!res.receipt_failures().is_empty());
+    log_tx_result("sale_distribute_unclaimed_tokens", res)?;
+
+    let sale = environment.get_sale(sale.sale_id, None).await?;
+    assert_eq!(sale.in_token_paid_unclaimed, U128(0));
+    assert!(sale.end_block_height.is_some());
+    assert_eq!(
+        environment.balances_of(alice).await?[0],
+        (
+            environment.w_near.id().clone(),
+            NearToken::from_near(10).as_yoctonear()
+                + NearToken::from_near(4).as_yoctonear() * 99 / 100
+        )
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_seed_liquidity() -> anyhow::Result<()> {
+    let environment = Env::init(2).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+
+    let sale_amount = NearToken::from_near(1_000).as_yoctonear();
+    let liquidity_amount = NearToken::from_near(500).as_yoctonear();
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(
+            alice,
+            token1.id(),
+            NearToken::from_yoctonear(sale_amount + liquidity_amount),
+        )
+        .await?;
+    let amm = environment
+        .deploy_mock_amm(&[environment.w_near.id(), token1.id()])
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let mut sale_input = environment.sale_input(
+        &[(token1.as_account(), sale_amount)],
+        current_time + BLOCK_DURATION * 15,
+        BLOCK_DURATION * 60,
+    )?;
+    sale_input.liquidity = Some(SaleInputLiquidity {
+        amm_contract_id: amm.id().parse()?,
+        in_token_bpt: 2000,
+        out_token_amount: liquidity_amount.into(),
+        fee: 30,
+        lock_duration: (BLOCK_DURATION * 100).into(),
+    });
+    let sale = environment
+        .sale_create_from_input(alice, sale_input)
+        .await?;
+    assert_eq!(environment.balances_of(alice).await?[1].1, 0);
+
+    environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(4))
+        .await?;
+    environment.worker.fast_forward(500).await?;
+
+    log_tx_result(
+        "sale_seed_liquidity",
+        bob.call(environment.skyward.id(), "sale_seed_liquidity")
+            .args_json((sale.sale_id,))
+            .deposit(NearToken::from_millinear(200))
+            .max_gas()
+            .transact()
+            .await?,
+    )?;
+
+    // 20% of the proceeds after the treasury fee, paired at the average price of 4 wNEAR per
+    // 1000 tokens. The unused out tokens are returned to the owner.
+    let in_amount = NearToken::from_near(4).as_yoctonear() * 99 / 100 / 5;
+    let out_amount = in_amount * 1000 / 4;
+    let liquidity = environment
+        .get_sale(sale.sale_id, None)
+        .await?
+        .liquidity
+        .unwrap();
+    assert_eq!(liquidity.status, LiquidityStatus::Seeded);
+    assert_eq!(liquidity.pool_id, Some(0));
+    assert_eq!(liquidity.in_amount, U128(in_amount));
+    assert_eq!(liquidity.out_amount, U128(out_amount));
+    assert_eq!(liquidity.shares, U128(in_amount));
+    assert_eq!(
+        environment.balances_of(alice).await?,
+        vec![
+            (
+                environment.w_near.id().clone(),
+                NearToken::from_near(10).as_yoctonear()
+                    + NearToken::from_near(4).as_yoctonear() * 99 / 100
+                    - in_amount
+            ),
+            (token1.id().clone(), liquidity_amount - out_amount),
+        ]
+    );
+    let pool: near_sdk::serde_json::Value = amm.view("get_pool").args_json((0,)).await?.json()?;
+    assert_eq!(
+        pool["amounts"],
+        json!([in_amount.to_string(), out_amount.to_string()])
+    );
+
+    // The shares are still locked.
+    assert!(alice
+        .call(environment.skyward.id(), "sale_withdraw_liquidity_shares")
+        .args_json((sale.sale_id,))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()
+        .is_err());
+
+    environment.worker.fast_forward(200).await?;
+    // Alice isn't registered for the LP token, so the shares are returned to the sale.
+    alice
+        .call(environment.skyward.id(), "sale_withdraw_liquidity_shares")
+        .args_json((sale.sale_id,))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    let liquidity = environment
+        .get_sale(sale.sale_id, None)
+        .await?
+        .liquidity
+        .unwrap();
+    assert_eq!(liquidity.shares, U128(in_amount));
+
+    log_tx_result(
+        "mft_register",
+        alice
+            .call(amm.id(), "mft_register")
+            .args_json((":0", alice.id()))
+            .deposit(NearToken::from_millinear(10))
+            .transact()
+            .await?,
+    )?;
+    log_tx_result(
+        "sale_withdraw_liquidity_shares",
+        alice
+            .call(environment.skyward.id(), "sale_withdraw_liquidity_shares")
+            .args_json((sale.sale_id,))
+            .deposit(NearToken::from_yoctonear(1))
+            .max_gas()
+            .transact()
+            .await?,
+    )?;
+    let shares: U128 = amm
+        .view("mft_balance_of")
+        .args_json((":0", alice.id()))
+        .await?
+        .json()?;
+    assert_eq!(shares, U128(in_amount));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_seed_liquidity_after_subscription_gap() -> anyhow::Result<()> {
+    let environment = Env::init(2).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+
+    let sale_amount = NearToken::from_near(1_000).as_yoctonear();
+    let liquidity_amount = NearToken::from_near(500).as_yoctonear();
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(
+            alice,
+            token1.id(),
+            NearToken::from_yoctonear(sale_amount + liquidity_amount),
+        )
+        .await?;
+    let amm = environment
+        .deploy_mock_amm(&[environment.w_near.id(), token1.id()])
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let mut sale_input = environment.sale_input(
+        &[(token1.as_account(), sale_amount)],
+        current_time + BLOCK_DURATION * 15,
+        BLOCK_DURATION * 60,
+    )?;
+    sale_input.liquidity = Some(SaleInputLiquidity {
+        amm_contract_id: amm.id().parse()?,
+        in_token_bpt: 2000,
+        out_token_amount: liquidity_amount.into(),
+        fee: 30,
+        lock_duration: (BLOCK_DURATION * 100).into(),
+    });
+    let sale = environment
+        .sale_create_from_input(alice, sale_input)
+        .await?;
+
+    environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(4))
+        .await?;
+    environment.worker.fast_forward(45).await?;
+    // Bob leaves in the middle of the sale, so the rest of the out tokens isn't sold.
+    log_tx_result(
+        "sale_withdraw_in_token",
+        bob.call(environment.skyward.id(), "sale_withdraw_in_token")
+            .args_json((sale.sale_id, None::<U128>))
+            .deposit(NearToken::from_yoctonear(1))
+            .transact()
+            .await?,
+    )?;
+    environment.worker.fast_forward(500).await?;
+
+    log_tx_result(
+        "sale_seed_liquidity",
+        bob.call(environment.skyward.id(), "sale_seed_liquidity")
+            .args_json((sale.sale_id,))
+            .deposit(NearToken::from_millinear(200))
+            .max_gas()
+            .transact()
+            .await?,
+    )?;
+
+    let sale = environment.get_sale(sale.sale_id, None).await?;
+    let liquidity = sale.liquidity.unwrap();
+    assert_eq!(liquidity.status, LiquidityStatus::Seeded);
+    let in_paid = sale.in_token_paid.0;
+    let in_amount = liquidity.in_amount.0;
+    assert!(in_amount > 0);
+
+    // Alice received the unsold out tokens and the out tokens that didn't match the price.
+    let returned =
+        environment.balances_of(alice).await?[1].1 + liquidity.out_amount.0 - liquidity_amount;
+    assert!(returned > 0);
+    let sold = sale_amount - returned;
+    assert_eq!(liquidity.out_amount, U128(in_amount * sold / in_paid));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_proceeds_lock() -> anyhow::Result<()> {
+    let environment = Env::init(3).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+    let carol = environment.users.get(2).unwrap();
+
+    let sale_amount = NearToken::from_near(1_000).as_yoctonear();
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_yoctonear(sale_amount))
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let mut sale_input = environment.sale_input(
+        &[(token1.as_account(), sale_amount)],
+        current_time + BLOCK_DURATION * 15,
+        BLOCK_DURATION * 60,
+    )?;
+    sale_input.proceeds_lock = Some(ProceedsLockInput {
+        vesting_duration: (to_nano(WEEK) * 52).into(),
+        arbiter_id: Some(carol.id().parse()?),
+        refund_quorum_bpt: None,
+    });
+    let sale = environment
+        .sale_create_from_input(alice, sale_input)
+        .await?;
+
+    environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(4))
+        .await?;
+    environment.worker.fast_forward(500).await?;
+
+    log_tx_result(
+        "sale_distribute_unclaimed_tokens",
+        alice
+            .call(environment.skyward.id(), "sale_distribute_unclaimed_tokens")
+            .args_json((sale.sale_id,))
+            .transact()
+            .await?,
+    )?;
+    let get_sale_proceeds = || async {
+        let proceeds: Option<ProceedsLockOutput> = environment
+            .worker
+            .view(environment.skyward.id(), "get_sale_proceeds")
+            .args_json((sale.sale_id,))
+            .await?
+            .json()?;
+        anyhow::Ok(proceeds.unwrap())
+    };
+    let total = NearToken::from_near(4).as_yoctonear() * 99 / 100;
+    let proceeds = get_sale_proceeds().await?;
+    assert_eq!(proceeds.in_tokens[0].total, U128(total));
+    // Only a small part has vested since the end of the sale.
+    let released = proceeds.in_tokens[0].released.0;
+    assert!(released < total / 100);
+    assert_eq!(
+        environment.balances_of(alice).await?[0].1,
+        NearToken::from_near(10).as_yoctonear() + released
+    );
+    // The portfolio only reports the vested part of the locked proceeds.
+    let portfolio: AccountPortfolioOutput = environment
+        .worker
+        .view(environment.skyward.id(), "get_account_portfolio")
+        .args_json(json!({ "account_id": alice.id() }))
+        .await?
+        .json::<Option<_>>()?
+        .unwrap();
+    assert!(portfolio.proceeds_sales[0].unclaimed_proceeds[0].1 .0 < total / 100);
+
+    // Only the arbiter can approve milestones.
+    assert!(bob
+        .call(environment.skyward.id(), "sale_approve_milestone")
+        .args_json((sale.sale_id, 5000))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?
+        .into_result()
+        .is_err());
+    log_tx_result(
+        "sale_approve_milestone",
+        carol
+            .call(environment.skyward.id(), "sale_approve_milestone")
+            .args_json((sale.sale_id, 5000))
+            .deposit(NearToken::from_yoctonear(1))
+            .transact()
+            .await?,
+    )?;
+    let proceeds = get_sale_proceeds().await?;
+    assert_eq!(proceeds.released_bpt, 5000);
+    let released = proceeds.in_tokens[0].released.0;
+    assert!(released >= total / 2);
+    assert_eq!(proceeds.in_tokens[0].locked, U128(total - released));
+    assert_eq!(
+        environment.balances_of(alice).await?[0].1,
+        NearToken::from_near(10).as_yoctonear() + released
+    );
+
+    environment
+        .dao_call("sale_clawback_proceeds", json!({ "sale_id": sale.sale_id }))
+        .await?;
+    let proceeds = get_sale_proceeds().await?;
+    assert!(proceeds.clawed_back);
+    assert_eq!(proceeds.in_tokens[0].locked, U128(0));
+    let released = proceeds.in_tokens[0].released.0;
+
+    let bob_balance = environment.balances_of(bob).await?[0].1;
+    log_tx_result(
+        "sale_claim_clawback",
+        bob.call(environment.skyward.id(), "sale_claim_clawback")
+            .args_json((sale.sale_id,))
+            .deposit(NearToken::from_yoctonear(1))
+            .max_gas()
+            .transact()
+            .await?,
+    )?;
+    // Bob is the only subscriber, so all of the clawed back proceeds are refunded to Bob.
+    assert_eq!(
+        environment.balances_of(bob).await?[0].1,
+        bob_balance + total - released
+    );
+    // Bob returns the clawed back part of the out tokens to Alice.
+    let out_amount = sale_amount * 99 / 100;
+    let returned =
+        (U256::from(out_amount) * U256::from(total - released) / U256::from(total)).as_u128();
+    assert_eq!(
+        environment.balances_of(bob).await?[1].1,
+        out_amount - returned
+    );
+    assert_eq!(environment.balances_of(alice).await?[1].1, returned);
+    let proceeds = get_sale_proceeds().await?;
+    assert_eq!(proceeds.in_tokens[0].refunded, U128(total - released));
+
+    assert!(bob
+        .call(environment.skyward.id(), "sale_claim_clawback")
+        .args_json((sale.sale_id,))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()
+        .is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_clawback_after_release() -> anyhow::Result<()> {
+    let environment = Env::init(3).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+    let carol = environment.users.get(2).unwrap();
+
+    let sale_amount = NearToken::from_near(1_000).as_yoctonear();
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_yoctonear(sale_amount))
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let mut sale_input = environment.sale_input(
+        &[(token1.as_account(), sale_amount)],
+        current_time + BLOCK_DURATION * 15,
+        BLOCK_DURATION * 60,
+    )?;
+    sale_input.proceeds_lock = Some(ProceedsLockInput {
+        vesting_duration: (to_nano(WEEK) * 52).into(),
+        arbiter_id: Some(carol.id().parse()?),
+        refund_quorum_bpt: None,
+    });
+    let sale = environment
+        .sale_create_from_input(alice, sale_input)
+        .await?;
+    environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(4))
+        .await?;
+    environment.worker.fast_forward(500).await?;
+
+    log_tx_result(
+        "sale_approve_milestone",
+        carol
+            .call(environment.skyward.id(), "sale_approve_milestone")
+            .args_json((sale.sale_id, 10000))
+            .deposit(NearToken::from_yoctonear(1))
+            .transact()
+            .await?,
+    )?;
+    // All proceeds are released, so there is nothing left to claw back.
+    assert!(environment
+        .skyward_dao
+        .call(environment.skyward.id(), "sale_clawback_proceeds")
+        .args_json(json!({ "sale_id": sale.sale_id }))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?
+        .into_result()
+        .is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_refund_vote() -> anyhow::Result<()> {
+    let environment = Env::init(3).await?;
+    let alice = environment.users.first().unwrap();
+    let bob = environment.users.get(1).unwrap();
+    let carol = environment.users.get(2).unwrap();
+
+    let sale_amount = NearToken::from_near(1_000).as_yoctonear();
+    let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
+    environment
+        .register_and_deposit(alice, token1.id(), NearToken::from_yoctonear(sale_amount))
+        .await?;
+
+    let current_time = environment
+        .worker
+        .view_block()
+        .await?
+        .header()
+        .timestamp_nanosec();
+    let mut sale_input = environment.sale_input(
+        &[(token1.as_account(), sale_amount)],
+        current_time + BLOCK_DURATION * 15,
+        BLOCK_DURATION * 60,
+    )?;
+    sale_input.proceeds_lock = Some(ProceedsLockInput {
+        vesting_duration: (to_nano(WEEK) * 52).into(),
+        arbiter_id: None,
+        refund_quorum_bpt: Some(5000),
+    });
+    let sale = environment
+        .sale_create_from_input(alice, sale_input)
+        .await?;
+
+    environment
+        .sale_deposit_in_token(bob, sale.sale_id, NearToken::from_near(3))
+        .await?;
+    environment
+        .sale_deposit_in_token(carol, sale.sale_id, NearToken::from_near(1))
+        .await?;
+    environment.worker.fast_forward(500).await?;
+
+    // Carol's claimed out tokens are below the quorum.
+    log_tx_result(
+        "sale_vote_refund",
+        carol
+            .call(environment.skyward.id(), "sale_vote_refund")
+            .args_json((sale.sale_id,))
+            .deposit(NearToken::from_millinear(10))
+            .max_gas()
+            .transact()
+            .await?,
+    )?;
+    let carol_votes: Option<U128> = environment
+        .worker
+        .view(environment.skyward.id(), "get_refund_vote")
+        .args_json((sale.sale_id, carol.id()))
+        .await?
+        .json()?;
+    assert_eq!(
+        carol_votes,
+        Some(environment.balances_of(carol).await?[1].1.into())
+    );
+    assert!(
+        !environment
+            .get_sale(sale.sale_id, None)
+            .await?
+            .proceeds_lock
+            .unwrap()
+            .clawed_back
+    );
+    assert!(carol
+        .call(environment.skyward.id(), "sale_vote_refund")
+        .args_json((sale.sale_id,))
+        .deposit(NearToken::from_millinear(10))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()
+        .is_err());
+
+    log_tx_result(
+        "sale_vote_refund",
+        bob.call(environment.skyward.id(), "sale_vote_refund")
+            .args_json((sale.sale_id,))
+            .deposit(NearToken::from_millinear(10))
+            .max_gas()
+            .transact()
+            .await?,
+    )?;
+    let proceeds = environment
+        .get_sale(sale.sale_id, None)
+        .await?
+        .proceeds_lock
+        .unwrap();
+    assert!(proceeds.clawed_back);
+    assert_eq!(
+        environment
+            .get_sale_ids_by_status(SaleStatus::Cancelled)
+            .await?,
+        vec![sale.sale_id]
     );
 
+    // The proceeds that weren't released are refunded by the spent in tokens. Carol claims last
+    // and receives the rounding remainder.
+    let refund = proceeds.in_tokens[0].total.0 - proceeds.in_tokens[0].released.0;
+    let mut returned = 0;
+    for (user, amount) in [(bob, refund * 3 / 4), (carol, refund - refund * 3 / 4)] {
+        let balances = environment.balances_of(user).await?;
+        let (balance, out_balance) = (balances[0].1, balances[1].1);
+        log_tx_result(
+            "sale_claim_clawback",
+            user.call(environment.skyward.id(), "sale_claim_clawback")
+                .args_json((sale.sale_id,))
+                .deposit(NearToken::from_yoctonear(1))
+                .max_gas()
+                .transact()
+                .await?,
+        )?;
+        let balances = environment.balances_of(user).await?;
+        assert_eq!(balances[0].1, balance + amount);
+        // The subscribers return the clawed back part of their out tokens.
+        assert!(balances[1].1 < out_balance);
+        returned += out_balance - balances[1].1;
+    }
+    assert_eq!(environment.balances_of(alice).await?[1].1, returned);
+    let proceeds = environment
+        .get_sale(sale.sale_id, None)
+        .await?
+        .proceeds_lock
+        .unwrap();
+    assert_eq!(proceeds.in_tokens[0].refunded, U128(refund));
+
     Ok(())
 }
 
 #[tokio::test]
-async fn test_join_sale() -> anyhow::Result<()> {
+async fn test_claim_referral_storage() -> anyhow::Result<()> {
     let environment = Env::init(2).await?;
     let alice = environment.users.first().unwrap();
     let bob = environment.users.get(1).unwrap();
 
+    let sale_amount = NearToken::from_near(10_000).as_yoctonear();
     let token1 = environment.deploy_ft(alice.id(), TOKEN1_ID).await?;
     environment
-        .register_and_deposit(alice, token1.id(), NearToken::from_near(10_000))
+        .register_and_deposit(alice, token1.id(), NearToken::from_yoctonear(sale_amount))
         .await?;
 
-    let start_offset = BLOCK_DURATION * 15;
     let current_time = environment
         .worker
         .view_block()
         .await?
         .header()
         .timestamp_nanosec();
-    let start_time = current_time + start_offset;
     let sale = environment
-        .sale_create(
+        .sale_create_with_ref(
             alice,
-            &[(
-                token1.as_account(),
-                NearToken::from_near(3_600).as_yoctonear(),
-            )],
-            start_time,
+            &[(token1.as_account(), sale_amount)],
+            current_time + BLOCK_DURATION * 15,
         )
         .await?;
-
     log_tx_result(
         "sale_deposit_in_token",
         bob.call(environment.skyward.id(), "sale_deposit_in_token")
             .args_json((
                 sale.sale_id,
                 U128(NearToken::from_near(4).as_yoctonear()),
+                Some(alice.id().clone()),
                 None::<AccountId>,
             ))
             .deposit(NearToken::from_millinear(10))
@@ -290,210 +3212,21 @@ async fn test_join_sale() -> anyhow::Result<()> {
             .await?,
     )?;
 
-    let bobs_sale = environment.get_sale(0, Some(bob.id().clone())).await?;
-    assert_eq!(
-        bobs_sale.in_token_remaining.0,
-        NearToken::from_near(4).as_yoctonear()
-    );
-    assert_eq!(
-        bobs_sale.total_shares.0,
-        NearToken::from_near(4).as_yoctonear()
-    );
-    assert_eq!(
-        bobs_sale.subscription,
-        Some(SubscriptionOutput {
-            claimed_out_balance: vec![0.into()],
-            spent_in_balance: 0.into(),
-            remaining_in_balance: NearToken::from_near(4).as_yoctonear().into(),
-            unclaimed_out_balances: vec![U128(0)],
-            shares: NearToken::from_near(4).as_yoctonear().into(),
-            referral_id: None
-        })
-    );
-
-    assert_eq!(
-        environment.balances_of(bob).await?,
-        vec![
-            (
-                environment.w_near.id().clone(),
-                NearToken::from_near(6).as_yoctonear()
-            ),
-            (token1.id().clone(), 0),
-        ]
-    );
-
-    environment.worker.fast_forward(500).await?;
-
-    let bobs_sale = environment.get_sale(0, Some(bob.id().clone())).await?;
-
-    environment.assert_sale_eq(
-        &bobs_sale,
-        PartialSale {
-            out_tokens: vec![PartialOutToken {
-                remaining: 0.into(),
-                distributed: NearToken::from_near(3_600).as_yoctonear().into(),
-                treasury_unclaimed: NearToken::from_near(36).as_yoctonear().into(),
-            }],
-            in_token_remaining: 0.into(),
-            in_token_paid_unclaimed: NearToken::from_near(4).as_yoctonear().into(),
-            in_token_paid: NearToken::from_near(4).as_yoctonear().into(),
-            total_shares: NearToken::from_near(4).as_yoctonear().into(),
-            subscription: Some(SubscriptionOutput {
-                claimed_out_balance: vec![0.into()],
-                spent_in_balance: NearToken::from_near(4).as_yoctonear().into(),
-                remaining_in_balance: 0.into(),
-                unclaimed_out_balances: vec![NearToken::from_near(3564).as_yoctonear().into()],
-                shares: NearToken::from_near(4).as_yoctonear().into(),
-                referral_id: None,
-            }),
-        },
-    );
-
-    assert_eq!(
-        environment.get_treasury_balances().await?,
-        vec![
-            (environment.w_near.id().clone(), 0),
-            (token1.id().clone(), 0),
-        ]
-    );
-
-    log_tx_result(
-        "sale_distribute_unclaimed_tokens",
-        alice
-            .call(environment.skyward.id(), "sale_distribute_unclaimed_tokens")
-            .args_json((0,))
-            .transact()
-            .await?,
-    )?;
-
-    assert_eq!(
-        environment.balances_of(alice).await?,
-        vec![
-            (
-                environment.w_near.id().clone(),
-                NearToken::from_millinear(13_960).as_yoctonear()
-            ),
-            (
-                token1.id().clone(),
-                NearToken::from_near(6400).as_yoctonear()
-            ),
-        ]
-    );
-    assert_eq!(
-        environment.get_treasury_balances().await?,
-        vec![
-            (
-                environment.w_near.id().clone(),
-                NearToken::from_millinear(40).as_yoctonear()
-            ),
-            (token1.id().clone(), NearToken::from_near(36).as_yoctonear()),
-        ]
-    );
-    assert_eq!(
-        environment.balances_of(bob).await?,
-        vec![
-            (
-                environment.w_near.id().clone(),
-                NearToken::from_near(6).as_yoctonear()
-            ),
-            (token1.id().clone(), 0),
-        ]
-    );
-
-    let bobs_sale = environment.get_sale(0, Some(bob.id().clone())).await?;
-
-    environment.assert_sale_eq(
-        &bobs_sale,
-        PartialSale {
-            out_tokens: vec![PartialOutToken {
-                remaining: 0.into(),
-                distributed: NearToken::from_near(3600).as_yoctonear().into(),
-                treasury_unclaimed: 0.into(),
-            }],
-            in_token_remaining: 0.into(),
-            in_token_paid_unclaimed: 0.into(),
-            in_token_paid: NearToken::from_near(4).as_yoctonear().into(),
-            total_shares: NearToken::from_near(4).as_yoctonear().into(),
-            subscription: Some(SubscriptionOutput {
-                claimed_out_balance: vec![0.into()],
-                spent_in_balance: NearToken::from_near(4).as_yoctonear().into(),
-                remaining_in_balance: 0.into(),
-                unclaimed_out_balances: vec![NearToken::from_near(3564).as_yoctonear().into()],
-                shares: NearToken::from_near(4).as_yoctonear().into(),
-                referral_id: None,
-            }),
-        },
-    );
+    environment.worker.fast_forward(30).await?;
 
+    // The first referral payout records the referral stats, which the contract pays for.
     log_tx_result(
         "sale_claim_out_tokens",
         bob.call(environment.skyward.id(), "sale_claim_out_tokens")
-            .args_json((0,))
+            .args_json((sale.sale_id,))
             .transact()
             .await?,
     )?;
-
-    let bobs_sale = environment.get_sale(0, Some(bob.id().clone())).await?;
-
-    environment.assert_sale_eq(
-        &bobs_sale,
-        PartialSale {
-            out_tokens: vec![PartialOutToken {
-                remaining: 0.into(),
-                distributed: NearToken::from_near(3600).as_yoctonear().into(),
-                treasury_unclaimed: 0.into(),
-            }],
-            in_token_remaining: 0.into(),
-            in_token_paid_unclaimed: 0.into(),
-            in_token_paid: NearToken::from_near(4).as_yoctonear().into(),
-            total_shares: 0.into(),
-            subscription: None,
-        },
-    );
-
-    assert_eq!(
-        environment.get_treasury_balances().await?,
-        vec![
-            (
-                environment.w_near.id().clone(),
-                NearToken::from_millinear(40).as_yoctonear()
-            ),
-            (token1.id().clone(), NearToken::from_near(36).as_yoctonear()),
-        ]
-    );
-    assert_eq!(
-        environment.balances_of(bob).await?,
-        vec![
-            (
-                environment.w_near.id().clone(),
-                NearToken::from_near(6).as_yoctonear()
-            ),
-            (
-                token1.id().clone(),
-                NearToken::from_near(3564).as_yoctonear()
-            ),
-        ]
-    );
-
-    environment
-        .storage_deposit(&token1, bob, None, Some(NearToken::from_millinear(50)))
+    let sale = environment
+        .get_sale(sale.sale_id, Some(bob.id().clone()))
         .await?;
-    environment.withdraw_token(bob, token1.id(), None).await?;
-
-    assert_eq!(
-        environment.balances_of(bob).await?,
-        vec![
-            (
-                environment.w_near.id().clone(),
-                NearToken::from_near(6).as_yoctonear()
-            ),
-            (token1.id().clone(), 0),
-        ]
-    );
-    assert_eq!(
-        environment.ft_balance_of(bob, token1.id()).await?,
-        NearToken::from_near(3564).as_yoctonear()
-    );
+    assert!(sale.remaining_duration.0 > 0);
+    assert!(sale.subscription.unwrap().claimed_out_balance[0].0 > 0);
 
     Ok(())
 }
@@ -540,6 +3273,7 @@ async fn test_join_sale_with_referral() -> anyhow::Result<()> {
                 sale.sale_id,
                 U128(NearToken::from_near(4).as_yoctonear()),
                 Some(alice.id().clone()),
+                None::<AccountId>,
             ))
             .deposit(NearToken::from_millinear(10))
             .transact()
@@ -569,6 +3303,11 @@ async fn test_join_sale_with_referral() -> anyhow::Result<()> {
                 unclaimed_out_balances: vec![(sale_amount * 99 / 100).into()],
                 shares: NearToken::from_near(4).as_yoctonear().into(),
                 referral_id: Some(alice.id().parse()?),
+                referral_weights: vec![],
+                in_tokens: vec![SubscriptionOutputInToken {
+                    remaining_in_balance: 0.into(),
+                    spent_in_balance: NearToken::from_near(4).as_yoctonear().into(),
+                }],
             }),
         },
     );
@@ -642,6 +3381,11 @@ async fn test_join_sale_with_referral() -> anyhow::Result<()> {
                 unclaimed_out_balances: vec![(sale_amount * 99 / 100).into()],
                 shares: NearToken::from_near(4).as_yoctonear().into(),
                 referral_id: Some(alice.id().parse()?),
+                referral_weights: vec![],
+                in_tokens: vec![SubscriptionOutputInToken {
+                    remaining_in_balance: 0.into(),
+                    spent_in_balance: NearToken::from_near(4).as_yoctonear().into(),
+                }],
             }),
         },
     );
@@ -762,6 +3506,7 @@ async fn test_join_sale_with_referral_and_alice() -> anyhow::Result<()> {
                 sale.sale_id,
                 U128(NearToken::from_near(4).as_yoctonear()),
                 Some(alice.id().clone()),
+                None::<AccountId>,
             ))
             .deposit(NearToken::from_millinear(10))
             .transact()
@@ -775,6 +3520,7 @@ async fn test_join_sale_with_referral_and_alice() -> anyhow::Result<()> {
                 sale.sale_id,
                 U128(NearToken::from_near(1).as_yoctonear()),
                 None::<String>,
+                None::<AccountId>,
             ))
             .deposit(NearToken::from_millinear(10))
             .transact()
@@ -802,6 +3548,11 @@ async fn test_join_sale_with_referral_and_alice() -> anyhow::Result<()> {
                 unclaimed_out_balances: vec![0.into()],
                 shares: NearToken::from_near(4).as_yoctonear().into(),
                 referral_id: Some(alice.id().parse()?),
+                referral_weights: vec![],
+                in_tokens: vec![SubscriptionOutputInToken {
+                    remaining_in_balance: NearToken::from_near(4).as_yoctonear().into(),
+                    spent_in_balance: 0.into(),
+                }],
             }),
         },
     );
@@ -818,6 +3569,11 @@ async fn test_join_sale_with_referral_and_alice() -> anyhow::Result<()> {
             unclaimed_out_balances: vec![0.into()],
             shares: NearToken::from_near(1).as_yoctonear().into(),
             referral_id: None,
+            referral_weights: vec![],
+            in_tokens: vec![SubscriptionOutputInToken {
+                remaining_in_balance: NearToken::from_near(1).as_yoctonear().into(),
+                spent_in_balance: 0.into(),
+            }],
         }),
     );
 
@@ -844,6 +3600,11 @@ async fn test_join_sale_with_referral_and_alice() -> anyhow::Result<()> {
                 unclaimed_out_balances: vec![(sale_amount * 99 / 100 * 4 / 5).into()],
                 shares: NearToken::from_near(4).as_yoctonear().into(),
                 referral_id: Some(alice.id().parse()?),
+                referral_weights: vec![],
+                in_tokens: vec![SubscriptionOutputInToken {
+                    remaining_in_balance: 0.into(),
+                    spent_in_balance: NearToken::from_near(4).as_yoctonear().into(),
+                }],
             }),
         },
     );
@@ -868,6 +3629,11 @@ async fn test_join_sale_with_referral_and_alice() -> anyhow::Result<()> {
             unclaimed_out_balances: vec![(sale_amount * 99 / 100 / 5).into()],
             shares: NearToken::from_near(1).as_yoctonear().into(),
             referral_id: None,
+            referral_weights: vec![],
+            in_tokens: vec![SubscriptionOutputInToken {
+                remaining_in_balance: 0.into(),
+                spent_in_balance: NearToken::from_near(1).as_yoctonear().into(),
+            }],
         }),
     );
 
@@ -932,6 +3698,11 @@ async fn test_join_sale_with_referral_and_alice() -> anyhow::Result<()> {
                 unclaimed_out_balances: vec![(sale_amount * 99 / 100 * 4 / 5).into()],
                 shares: NearToken::from_near(4).as_yoctonear().into(),
                 referral_id: Some(alice.id().parse()?),
+                referral_weights: vec![],
+                in_tokens: vec![SubscriptionOutputInToken {
+                    remaining_in_balance: 0.into(),
+                    spent_in_balance: NearToken::from_near(4).as_yoctonear().into(),
+                }],
             }),
         },
     );
@@ -948,6 +3719,11 @@ async fn test_join_sale_with_referral_and_alice() -> anyhow::Result<()> {
             unclaimed_out_balances: vec![(sale_amount * 99 / 100 / 5).into()],
             shares: NearToken::from_near(1).as_yoctonear().into(),
             referral_id: None,
+            referral_weights: vec![],
+            in_tokens: vec![SubscriptionOutputInToken {
+                remaining_in_balance: 0.into(),
+                spent_in_balance: NearToken::from_near(1).as_yoctonear().into(),
+            }],
         }),
     );
 
@@ -989,6 +3765,11 @@ async fn test_join_sale_with_referral_and_alice() -> anyhow::Result<()> {
             unclaimed_out_balances: vec![(sale_amount * 99 / 100 / 5).into()],
             shares: NearToken::from_near(1).as_yoctonear().into(),
             referral_id: None,
+            referral_weights: vec![],
+            in_tokens: vec![SubscriptionOutputInToken {
+                remaining_in_balance: 0.into(),
+                spent_in_balance: NearToken::from_near(1).as_yoctonear().into(),
+            }],
         }),
     );
 
@@ -2,28 +2,61 @@ use owo_colors::OwoColorize;
 use serde::{Deserialize, Serialize};
 use std::fmt::{self, Display, Formatter};
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 #[serde(tag = "standard")]
 #[serde(rename_all = "kebab-case")]
 pub enum ContractEvent {
     Nep141(Nep141Event),
+    Skyward(SkywardEvent),
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Nep141Event {
     pub version: String,
     #[serde(flatten)]
     pub event_kind: Nep141EventKind,
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 #[serde(tag = "event", content = "data")]
 #[serde(rename_all = "snake_case")]
 pub enum Nep141EventKind {
     FtTransfer(Vec<FtTransfer>),
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct SkywardEvent {
+    pub version: String,
+    #[serde(flatten)]
+    pub event_kind: SkywardEventKind,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "event", content = "data")]
+#[serde(rename_all = "snake_case")]
+pub enum SkywardEventKind {
+    InternalTransfer(Vec<InternalTransfer>),
+    AccountDeposit(Vec<AccountDeposit>),
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct InternalTransfer {
+    pub sender_id: String,
+    pub receiver_id: String,
+    pub token_account_id: String,
+    pub amount: String,
+    pub memo: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct AccountDeposit {
+    pub sender_id: String,
+    pub account_id: String,
+    pub token_account_id: String,
+    pub amount: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct FtTransfer {
     pub old_owner_id: String,
     pub new_owner_id: String,
@@ -35,6 +68,14 @@ impl Display for ContractEvent {
     fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
         match self {
             ContractEvent::Nep141(event) => formatter.write_fmt(format_args!("{}", event)),
+            ContractEvent::Skyward(event) => formatter.write_fmt(format_args!(
+                "{}: skyward\n{}: {}\n{}: {:?}",
+                "standard".bright_cyan(),
+                "version".bright_cyan(),
+                event.version,
+                "event".bright_cyan(),
+                event.event_kind
+            )),
         }
     }
 }
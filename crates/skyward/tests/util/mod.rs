@@ -221,6 +221,33 @@ impl Env {
         Ok(token)
     }
 
+    /// Deploys the mock AMM and registers it with the given tokens.
+    pub async fn deploy_mock_amm(
+        &self,
+        token_account_ids: &[&AccountId],
+    ) -> anyhow::Result<Contract> {
+        let amm = self
+            .worker
+            .create_tla_and_deploy(
+                MOCK_AMM_ID.parse()?,
+                SecretKey::from_random(KeyType::ED25519),
+                MOCK_AMM_WASM_BYTES,
+            )
+            .await?
+            .into_result()?;
+        log_tx_result("Initialize mock AMM", amm.call("new").transact().await?)?;
+        for token_account_id in token_account_ids {
+            self.skyward_dao
+                .call(token_account_id, "storage_deposit")
+                .args_json((amm.id(), None::<bool>))
+                .deposit(NearToken::from_millinear(50))
+                .transact()
+                .await?
+                .into_result()?;
+        }
+        Ok(amm)
+    }
+
     pub async fn wrap_near(&self, user: &Account, amount: NearToken) -> anyhow::Result<()> {
         user.call(self.w_near.id(), "near_deposit")
             .args_json(&json!({
@@ -311,6 +338,61 @@ impl Env {
         sale_duration: u64,
         permissions_contract_id: Option<AccountId>,
         referral_bpt: Option<u16>,
+    ) -> anyhow::Result<SaleOutput> {
+        let mut sale = self.sale_input(tokens, start_time, sale_duration)?;
+        sale.permissions_contract_id = permissions_contract_id.map(|id| id.parse().unwrap());
+        for out_token in sale.out_tokens.iter_mut() {
+            out_token.referral_bpt = referral_bpt;
+        }
+        self.sale_create_from_input(user, sale).await
+    }
+
+    pub fn sale_input(
+        &self,
+        tokens: &[(&Account, u128)],
+        start_time: u64,
+        sale_duration: u64,
+    ) -> anyhow::Result<SaleInput> {
+        Ok(SaleInput {
+            title: TITLE.to_string(),
+            url: None,
+            permissions_contract_id: None,
+            hooks_contract_id: None,
+            owner_id: None,
+            proceeds_receiver_id: None,
+            out_tokens: tokens
+                .iter()
+                .map(|(token, balance)| SaleInputOutToken {
+                    token_account_id: token.id().parse().unwrap(),
+                    balance: (*balance).into(),
+                    referral_bpt: None,
+                    referrer_bpt: None,
+                    second_level_referrer_bpt: None,
+                    referral_payout: None,
+                })
+                .collect(),
+            in_tokens: vec![SaleInputInToken {
+                token_account_id: self.w_near.id().parse()?,
+                weight: InTokenWeight::ONE,
+            }],
+            listing_fee_token_account_id: None,
+            min_deposit: None,
+            max_account_in_amount: None,
+            withdrawal_lock_duration: None,
+            early_exit_penalty: None,
+            referral_attribution: None,
+            liquidity: None,
+            proceeds_lock: None,
+            history_interval: None,
+            start_time: start_time.into(),
+            duration: sale_duration.into(),
+        })
+    }
+
+    pub async fn sale_create_from_input(
+        &self,
+        user: &Account,
+        sale: SaleInput,
     ) -> anyhow::Result<SaleOutput> {
         let initial_balance = user.view_account().await?.balance;
 
@@ -321,47 +403,80 @@ impl Env {
         } else {
             NearToken::from_yoctonear(0)
         };
-        let sale_id: u64 = log_tx_result(
+        let sale_id: Option<u64> = log_tx_result(
             "sale_create",
             user.call(self.skyward.id(), "sale_create")
-                .args_json((SaleInput {
-                    title: TITLE.to_string(),
-                    url: None,
-                    permissions_contract_id: permissions_contract_id.map(|id| id.parse().unwrap()),
-                    out_tokens: tokens
-                        .iter()
-                        .map(|(token, balance)| SaleInputOutToken {
-                            token_account_id: token.id().parse().unwrap(),
-                            balance: (*balance).into(),
-                            referral_bpt,
-                        })
-                        .collect(),
-                    in_token_account_id: self.w_near.id().parse()?,
-                    start_time: start_time.into(),
-                    duration: sale_duration.into(),
-                },))
+                .args_json((sale,))
                 .deposit(deposit)
+                .max_gas()
                 .transact()
                 .await?,
         )?
         .0
         .json()?;
+        let sale_id = sale_id.ok_or_else(|| anyhow::anyhow!("Sale was not created"))?;
 
         let balance_spent = initial_balance
             .checked_sub(user.view_account().await?.balance)
             .unwrap();
-        // Should be listing fee plus some for storage. The rest should be refunded.
+        // Should be listing fee plus some for storage, which includes the sale history. The rest
+        // should be refunded.
         assert!(
             LISTING_FEE_NEAR < balance_spent
                 && balance_spent
                     < LISTING_FEE_NEAR
-                        .checked_add(NearToken::from_millinear(20))
+                        .checked_add(NearToken::from_millinear(120))
                         .unwrap()
         );
 
         self.get_sale(sale_id, None).await
     }
 
+    pub async fn sale_deposit_in_token(
+        &self,
+        user: &Account,
+        sale_id: u64,
+        amount: NearToken,
+    ) -> anyhow::Result<()> {
+        log_tx_result(
+            "sale_deposit_in_token",
+            user.call(self.skyward.id(), "sale_deposit_in_token")
+                .args_json((
+                    sale_id,
+                    U128(amount.as_yoctonear()),
+                    None::<AccountId>,
+                    None::<AccountId>,
+                ))
+                .deposit(NearToken::from_millinear(10))
+                .transact()
+                .await?,
+        )?;
+        Ok(())
+    }
+
+    pub async fn dao_call(
+        &self,
+        method: &str,
+        args: serde_json::Value,
+    ) -> anyhow::Result<ExecutionResult<Value>> {
+        let (res, _) = log_tx_result(
+            method,
+            self.skyward_dao
+                .call(self.skyward.id(), method)
+                .args_json(args)
+                .deposit(NearToken::from_yoctonear(1))
+                .transact()
+                .await?,
+        )?;
+        Ok(res)
+    }
+
+    pub async fn set_pause_flags(&self, pause_flags: &PauseFlags) -> anyhow::Result<()> {
+        self.dao_call("set_pause_flags", json!({ "pause_flags": pause_flags }))
+            .await?;
+        Ok(())
+    }
+
     pub async fn withdraw_token(
         &self,
         user: &Account,
@@ -392,6 +507,16 @@ impl Env {
             .json()?)
     }
 
+    pub async fn get_sale_ids_by_status(&self, status: SaleStatus) -> anyhow::Result<Vec<u64>> {
+        let sales: Vec<SaleOutput> = self
+            .worker
+            .view(self.skyward.id(), "get_sales_by_status")
+            .args_json((status, None::<AccountId>, None::<u64>, None::<u64>))
+            .await?
+            .json()?;
+        Ok(sales.into_iter().map(|sale| sale.sale_id).collect())
+    }
+
     pub async fn balances_of(&self, user: &Account) -> anyhow::Result<Vec<(AccountId, u128)>> {
         let res: Vec<(AccountId, U128)> = user
             .view(self.skyward.id(), "balances_of")
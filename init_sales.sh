@@ -17,10 +17,10 @@ near call $CONTRACT_ID --accountId=$CONTRACT_ID sale_create '{"sale": {
       "balance": "250000000000000000000000",
       "referral_bpt": 100
     }],
-    "in_token_account_id": "'$WRAP_NEAR_TOKEN_ID'",
+    "in_tokens": [{"token_account_id": "'$WRAP_NEAR_TOKEN_ID'", "weight": 1}],
     "start_time": "'$START_TIME'000000000",
     "duration": "604800000000000"
-}}'
+}}' --gas=300000000000000
 
 START_TIME=1627776000
 near call $CONTRACT_ID --accountId=$CONTRACT_ID sale_create '{"sale": {
@@ -30,10 +30,10 @@ near call $CONTRACT_ID --accountId=$CONTRACT_ID sale_create '{"sale": {
       "balance": "200000000000000000000000",
       "referral_bpt": 100
     }],
-    "in_token_account_id": "'$WRAP_NEAR_TOKEN_ID'",
+    "in_tokens": [{"token_account_id": "'$WRAP_NEAR_TOKEN_ID'", "weight": 1}],
     "start_time": "'$START_TIME'000000000",
     "duration": "604800000000000"
-}}'
+}}' --gas=300000000000000
 
 START_TIME=1630454400
 near call $CONTRACT_ID --accountId=$CONTRACT_ID sale_create '{"sale": {
@@ -43,10 +43,10 @@ near call $CONTRACT_ID --accountId=$CONTRACT_ID sale_create '{"sale": {
       "balance": "150000000000000000000000",
       "referral_bpt": 100
     }],
-    "in_token_account_id": "'$WRAP_NEAR_TOKEN_ID'",
+    "in_tokens": [{"token_account_id": "'$WRAP_NEAR_TOKEN_ID'", "weight": 1}],
     "start_time": "'$START_TIME'000000000",
     "duration": "604800000000000"
-}}'
+}}' --gas=300000000000000
 
 START_TIME=1633046400
 near call $CONTRACT_ID --accountId=$CONTRACT_ID sale_create '{"sale": {
@@ -56,10 +56,10 @@ near call $CONTRACT_ID --accountId=$CONTRACT_ID sale_create '{"sale": {
       "balance": "100000000000000000000000",
       "referral_bpt": 100
     }],
-    "in_token_account_id": "'$WRAP_NEAR_TOKEN_ID'",
+    "in_tokens": [{"token_account_id": "'$WRAP_NEAR_TOKEN_ID'", "weight": 1}],
     "start_time": "'$START_TIME'000000000",
     "duration": "604800000000000"
-}}'
+}}' --gas=300000000000000
 
 START_TIME=1635724800
 near call $CONTRACT_ID --accountId=$CONTRACT_ID sale_create '{"sale": {
@@ -69,10 +69,10 @@ near call $CONTRACT_ID --accountId=$CONTRACT_ID sale_create '{"sale": {
       "balance": "100000000000000000000000",
       "referral_bpt": 100
     }],
-    "in_token_account_id": "'$WRAP_NEAR_TOKEN_ID'",
+    "in_tokens": [{"token_account_id": "'$WRAP_NEAR_TOKEN_ID'", "weight": 1}],
     "start_time": "'$START_TIME'000000000",
     "duration": "604800000000000"
-}}'
+}}' --gas=300000000000000
 
 START_TIME=1638316800
 near call $CONTRACT_ID --accountId=$CONTRACT_ID sale_create '{"sale": {
@@ -82,7 +82,7 @@ near call $CONTRACT_ID --accountId=$CONTRACT_ID sale_create '{"sale": {
       "balance": "100000000000000000000000",
       "referral_bpt": 100
     }],
-    "in_token_account_id": "'$WRAP_NEAR_TOKEN_ID'",
+    "in_tokens": [{"token_account_id": "'$WRAP_NEAR_TOKEN_ID'", "weight": 1}],
     "start_time": "'$START_TIME'000000000",
     "duration": "604800000000000"
-}}'
+}}' --gas=300000000000000